@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::structs::record_types::soa_record::SOARecord;
+use crate::{
+    DNSFlags, DNSLabel, DNSPacket, DNSPacketBuffer, DNSQuestion, DNSRecord, DNSResourceRecord, Name,
+};
+
+/// How many answers are cached by default
+pub const CACHE_SIZE: usize = 10_000;
+/// How much memory cached answers may take by default, 64 MiB
+pub const CACHE_MEMORY: usize = 64 * 1024 * 1024;
+/// The longest an answer is cached by default, one day
+pub const CACHE_MAX_TTL: u32 = 86_400;
+/// How long expired answers can still be served by default when
+/// no upstream answers, one day as RFC 8767 suggests
+pub const CACHE_STALE_WINDOW: u32 = 86_400;
+/// The TTL stale answers are served with (RFC 8767 section 4)
+pub const STALE_TTL: u32 = 30;
+/// How often an answer has to be served before it is refreshed ahead
+/// of its expiry
+pub const PREFETCH_MIN_HITS: u32 = 2;
+/// The longest a name is remembered not to exist by default,
+/// in the range RFC 2308 section 5 suggests
+pub const CACHE_NEGATIVE_MAX_TTL: u32 = 3_600;
+/// How often the cache statistics are logged
+pub const CACHE_STATS_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How the cache is sized and how long answers stay in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// the most answers kept, 0 turns the cache off
+    pub size: usize,
+    /// the most bytes the answers kept may take, as estimated
+    /// from their records. 0 turns the cache off
+    pub memory: usize,
+    /// TTLs below this are raised to it
+    pub min_ttl: u32,
+    /// TTLs above this are lowered to it
+    pub max_ttl: u32,
+    /// like `max_ttl`, for NXDOMAIN and NODATA answers
+    pub negative_max_ttl: u32,
+    /// seconds an expired answer is kept to be served when the
+    /// upstreams fail, 0 turns serving stale answers off
+    pub stale_window: u32,
+    /// whether answers in use are refreshed shortly before they expire
+    pub prefetch: bool,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            size: CACHE_SIZE,
+            memory: CACHE_MEMORY,
+            min_ttl: 0,
+            max_ttl: CACHE_MAX_TTL,
+            negative_max_ttl: CACHE_NEGATIVE_MAX_TTL,
+            stale_window: CACHE_STALE_WINDOW,
+            prefetch: true,
+        }
+    }
+}
+
+/// How well the cache is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// expired answers served because no upstream answered
+    pub stale: u64,
+    /// answers refreshed before they expired
+    pub prefetches: u64,
+    /// answers currently cached, expired ones included
+    /// until they are looked up or evicted
+    pub entries: usize,
+    /// the bytes the cached answers take, as estimated
+    pub memory: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hits: {}, misses: {}, stale: {}, prefetches: {}, entries: {}, bytes: {}",
+            self.hits, self.misses, self.stale, self.prefetches, self.entries, self.memory
+        )
+    }
+}
+
+/// Answers from upstream servers, kept for as long as their TTLs
+/// allow. Served answers have their TTLs lowered by the time they
+/// spent in the cache. When the cache is full, by the number of answers
+/// or by their size, the answer that was used least recently is dropped.
+///
+/// NXDOMAIN and NODATA answers are cached too, for as long as the SOA
+/// record in their authority section allows (RFC 2308).
+///
+/// Expired answers are kept for a while longer, to be served when no
+/// upstream answers (RFC 8767). Answers that are in use are refreshed
+/// shortly before they expire, see [`Cache::take_prefetch`].
+#[derive(Debug, Default)]
+pub struct Cache {
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale: AtomicU64,
+    prefetches: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    limits: CacheLimits,
+    entries: HashMap<Key, Entry>,
+    /// keys by the time they were last used, oldest first
+    recently_used: BTreeMap<u64, Key>,
+    /// the estimated size of all entries
+    memory: usize,
+    /// counts up on every use, to order the entries
+    clock: u64,
+}
+
+/// Answers are cached per question, names compare case-insensitively
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    name: Name,
+    qtype: u16,
+    qclass: u16,
+}
+
+#[derive(Debug)]
+struct Entry {
+    rcode: u8,
+    answers: Vec<DNSResourceRecord>,
+    authorities: Vec<DNSResourceRecord>,
+    additionals: Vec<DNSResourceRecord>,
+    stored: Instant,
+    expires: Instant,
+    /// the TTL the answer was cached with
+    ttl: u32,
+    /// how often the answer was served
+    hits: u32,
+    /// whether a refresh has been handed out
+    prefetching: bool,
+    last_used: u64,
+    /// the estimated bytes the entry takes, see [`entry_size`]
+    size: usize,
+}
+
+impl Key {
+    /// none when the name could not exist, such questions are not cached
+    fn new(question: &DNSQuestion) -> Option<Self> {
+        Some(Self {
+            name: Name::from_labels(&question.qname).ok()?,
+            qtype: question.qtype,
+            qclass: question.qclass,
+        })
+    }
+}
+
+impl Cache {
+    pub fn new(limits: CacheLimits) -> Self {
+        let cache = Self::default();
+        cache.set_limits(limits);
+        cache
+    }
+
+    /// changes the limits, dropping the oldest answers
+    /// when the cache is now too large
+    pub fn set_limits(&self, limits: CacheLimits) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.limits = limits;
+        while inner.entries.len() > limits.size || inner.memory > limits.memory {
+            inner.evict();
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+            prefetches: self.prefetches.load(Ordering::Relaxed),
+            entries: inner.entries.len(),
+            memory: inner.memory,
+        }
+    }
+
+    /// the cached answer to the query, with the TTLs lowered by the
+    /// time the answer has been cached
+    pub fn get(&self, query: &DNSPacket, now: Instant) -> Option<DNSPacket> {
+        let response = self.lookup(query, now, false);
+        let counter = match response {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        response
+    }
+
+    /// the cached answer to the query even when it has expired, as long
+    /// as it is within the stale window. Expired answers are served with
+    /// a TTL of [`STALE_TTL`]. Only meant for when resolving failed.
+    pub fn get_stale(&self, query: &DNSPacket, now: Instant) -> Option<DNSPacket> {
+        let response = self.lookup(query, now, true)?;
+        self.stale.fetch_add(1, Ordering::Relaxed);
+        Some(response)
+    }
+
+    /// whether the cached answer to the query should be refreshed now:
+    /// it has been served a few times and has less than a tenth of its
+    /// TTL left. Only returns `true` once per cached answer, unless
+    /// the refresh failed, see [`Cache::prefetch_failed`].
+    pub fn take_prefetch(&self, query: &DNSPacket, now: Instant) -> bool {
+        let [question] = &query.questions[..] else {
+            return false;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.limits.prefetch {
+            return false;
+        }
+        let Some(entry) = Key::new(question).and_then(|key| inner.entries.get_mut(&key)) else {
+            return false;
+        };
+
+        let left = entry.expires.saturating_duration_since(now);
+        let due = left > Duration::ZERO && left < Duration::from_secs(entry.ttl as u64) / 10;
+        if !due || entry.prefetching || entry.hits < PREFETCH_MIN_HITS {
+            return false;
+        }
+        entry.prefetching = true;
+        self.prefetches.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// hands the refresh of the cached answer to the query out again,
+    /// after a prefetch got no answer that could replace it
+    pub fn prefetch_failed(&self, query: &DNSPacket) {
+        let [question] = &query.questions[..] else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = Key::new(question).and_then(|key| inner.entries.get_mut(&key)) {
+            entry.prefetching = false;
+        }
+    }
+
+    fn lookup(&self, query: &DNSPacket, now: Instant, stale: bool) -> Option<DNSPacket> {
+        let [question] = &query.questions[..] else {
+            return None;
+        };
+        let key = Key::new(question)?;
+        let mut inner = self.inner.lock().unwrap();
+        let stale_window = Duration::from_secs(inner.limits.stale_window as u64);
+
+        let entry = inner.entries.get_mut(&key)?;
+        if entry.expires + stale_window <= now {
+            inner.remove(&key);
+            return None;
+        }
+        let expired = entry.expires <= now;
+        if expired && !stale {
+            // kept in case the upstreams fail
+            return None;
+        }
+        entry.hits = entry.hits.saturating_add(1);
+
+        let age = now.duration_since(entry.stored).as_secs() as u32;
+        let decay = |records: &[DNSResourceRecord]| -> Vec<DNSResourceRecord> {
+            records
+                .iter()
+                .map(|record| {
+                    let mut record = record.clone();
+                    record.ttl = if expired {
+                        STALE_TTL
+                    } else {
+                        record.ttl.saturating_sub(age)
+                    };
+                    record
+                })
+                .collect()
+        };
+
+        let mut response = DNSPacket::response_to(query);
+        response.header.flags.rcode = entry.rcode;
+        response.answers = decay(&entry.answers);
+        response.authorities = decay(&entry.authorities);
+        response.additionals = decay(&entry.additionals);
+        response.update_counts();
+
+        inner.touch(&key);
+        Some(response)
+    }
+
+    /// caches an upstream response to the query, if it can be cached.
+    /// TTLs are clamped to the limits and the answer expires with its
+    /// shortest TTL. Negative answers need an SOA record to be cached.
+    pub fn insert(&self, query: &DNSPacket, response: &DNSPacket, now: Instant) {
+        let [question] = &query.questions[..] else {
+            return;
+        };
+        if response.questions != query.questions || response.header.flags.tc {
+            return;
+        }
+        let negative = match response.header.flags.rcode {
+            DNSFlags::NOERROR => response.answers.is_empty(),
+            DNSFlags::NXDOMAIN => true,
+            _ => return,
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        let limits = inner.limits;
+        if limits.size == 0 || limits.memory == 0 {
+            return;
+        }
+        let clamp = |records: &[DNSResourceRecord]| -> Vec<DNSResourceRecord> {
+            records
+                .iter()
+                .map(|record| {
+                    let mut record = record.clone();
+                    record.ttl = record.ttl.max(limits.min_ttl).min(limits.max_ttl);
+                    record
+                })
+                .collect()
+        };
+        let answers = clamp(&response.answers);
+        let mut authorities = clamp(&response.authorities);
+
+        let mut ttl = answers.iter().map(|record| record.ttl).min();
+        if negative {
+            let Some(negative_ttl) = negative_ttl(&response.authorities) else {
+                return;
+            };
+            let negative_ttl = negative_ttl
+                .max(limits.min_ttl)
+                .min(limits.negative_max_ttl);
+            // the SOA is served with how long the answer stays negative
+            for soa in authorities
+                .iter_mut()
+                .filter(|record| record.rtype == SOARecord::TYPE)
+            {
+                soa.ttl = negative_ttl;
+            }
+            ttl = Some(ttl.map_or(negative_ttl, |ttl| ttl.min(negative_ttl)));
+        }
+        let ttl = ttl.unwrap_or(0);
+        if ttl == 0 {
+            return;
+        }
+
+        let Some(key) = Key::new(question) else {
+            return;
+        };
+        let additionals = clamp(&response.additionals);
+        let size = entry_size(question, &[&answers, &authorities, &additionals]);
+        inner.remove(&key);
+        if size > limits.memory {
+            return;
+        }
+        while inner.entries.len() >= limits.size || inner.memory + size > limits.memory {
+            inner.evict();
+        }
+        let entry = Entry {
+            rcode: response.header.flags.rcode,
+            answers,
+            authorities,
+            additionals,
+            stored: now,
+            expires: now + Duration::from_secs(ttl as u64),
+            ttl,
+            hits: 0,
+            prefetching: false,
+            last_used: 0,
+            size,
+        };
+        inner.memory += size;
+        inner.entries.insert(key.clone(), entry);
+        inner.touch(&key);
+    }
+}
+
+/// how long a negative answer may be cached, the smaller of the TTL
+/// and the minimum field of the SOA record (RFC 2308 section 5)
+fn negative_ttl(authorities: &[DNSResourceRecord]) -> Option<u32> {
+    authorities.iter().find_map(|record| match &record.data {
+        DNSRecord::SOA(soa) => Some(record.ttl.min(soa.minimum_ttl)),
+        _ => None,
+    })
+}
+
+/// roughly the bytes an entry for the question takes: the entry and
+/// its key, and per record its name and its data as on the wire
+fn entry_size(question: &DNSQuestion, sections: &[&[DNSResourceRecord]]) -> usize {
+    let name_size = |name: &[DNSLabel]| -> usize {
+        name.iter()
+            .map(|label| size_of::<DNSLabel>() + label.value.len())
+            .sum()
+    };
+    let record_size = |record: &DNSResourceRecord| -> usize {
+        let mut buffer = DNSPacketBuffer::empty();
+        let data = match record.data.write_to_bytes(&mut buffer) {
+            Ok(()) => buffer.offset,
+            Err(_) => 0,
+        };
+        size_of::<DNSResourceRecord>() + name_size(&record.name) + data
+    };
+
+    // the key is kept twice, the second time by when it was used
+    let records: usize = sections
+        .iter()
+        .flat_map(|records| records.iter())
+        .map(record_size)
+        .sum();
+    size_of::<Entry>() + 2 * (size_of::<Key>() + name_size(&question.qname)) + records
+}
+
+impl Inner {
+    /// marks the entry as the most recently used
+    fn touch(&mut self, key: &Key) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recently_used.remove(&entry.last_used);
+            entry.last_used = clock;
+            self.recently_used.insert(clock, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recently_used.remove(&entry.last_used);
+            self.memory -= entry.size;
+        }
+    }
+
+    /// drops the least recently used entry
+    fn evict(&mut self) {
+        if let Some((_, key)) = self.recently_used.pop_first() {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.memory -= entry.size;
+            }
+        }
+    }
+}
@@ -0,0 +1,613 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::cache::CacheLimits;
+use crate::options::{parse_upstream, Options};
+use crate::records::{RecordStore, CLASS_IN};
+use crate::server::forward::UPSTREAM_RETRIES;
+use crate::server::handler::{MAX_QUERIES, UPSTREAM_TIMEOUT};
+use crate::server::tcp::{MAX_TCP_CONNECTIONS, TCP_IDLE_TIMEOUT};
+use crate::server::upstreams::{HealthLimits, UpstreamStrategy};
+use crate::structs::record_types::txt_record::TXTRecord;
+use crate::zone::{load_zone, parse_rdata};
+use crate::{DNSLabel, DNSRecord, DNSResourceRecord, Name};
+
+/// The TTL of configured records that do not set one
+pub const DEFAULT_TTL: u32 = 300;
+
+/// The running configuration of the server, built from the command
+/// line and optionally a config file. It is swapped as a whole when
+/// the config file is reloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// addresses to listen on, only read at start-up
+    pub listen: Vec<SocketAddr>,
+    /// upstream servers, asked as `upstream_strategy` picks
+    pub upstreams: Vec<SocketAddr>,
+    pub upstream_strategy: UpstreamStrategy,
+    pub upstream_timeout: Duration,
+    /// how many more times the upstreams are tried when none answered
+    pub upstream_retries: u32,
+    pub upstream_health: HealthLimits,
+    /// upstreams for the names below some domains,
+    /// used instead of `upstreams`
+    pub forward_rules: Vec<ForwardRule>,
+    /// whether names no forwarding rule covers are resolved from
+    /// the root servers instead of being forwarded to `upstreams`
+    pub recursive: bool,
+    /// whether the resolver only shows name servers as much of
+    /// the name as they need to see (RFC 9156)
+    pub qname_minimisation: bool,
+    /// only read at start-up
+    pub tcp_idle_timeout: Duration,
+    /// how many queries are answered at once, over UDP and TCP
+    /// together. Only read at start-up
+    pub max_queries: usize,
+    /// how many TCP connections can be open at once,
+    /// only read at start-up
+    pub max_tcp_connections: usize,
+    /// records answered locally
+    pub records: RecordStore,
+    /// names that are answered with NXDOMAIN,
+    /// together with everything below them
+    pub blocklist: HashSet<Name>,
+    pub acl: Acl,
+    pub cache: CacheLimits,
+}
+
+/// The file as it is written, before it is validated.
+/// Every field is optional, missing ones keep the value
+/// from the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    listen: Option<Vec<String>>,
+    upstreams: Option<Vec<String>>,
+    upstream_strategy: Option<String>,
+    /// in milliseconds
+    upstream_timeout: Option<u64>,
+    upstream_retries: Option<u32>,
+    upstream_health: HealthEntry,
+    forward: Vec<ForwardEntry>,
+    recursive: Option<bool>,
+    qname_minimisation: Option<bool>,
+    /// in seconds
+    tcp_idle_timeout: Option<u64>,
+    max_queries: Option<usize>,
+    max_tcp_connections: Option<usize>,
+    records: Vec<RecordEntry>,
+    zones: Vec<ZoneEntry>,
+    blocklist: Vec<String>,
+    acl: AclEntry,
+    cache: CacheEntry,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordEntry {
+    name: String,
+    #[serde(rename = "type")]
+    rtype: String,
+    value: String,
+    ttl: Option<u32>,
+}
+
+/// a zone file served authoritatively
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ZoneEntry {
+    origin: String,
+    /// relative to the directory of the config file
+    file: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AclEntry {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CacheEntry {
+    size: Option<usize>,
+    /// in bytes
+    memory: Option<usize>,
+    /// in seconds
+    min_ttl: Option<u32>,
+    /// in seconds
+    max_ttl: Option<u32>,
+    /// in seconds
+    negative_max_ttl: Option<u32>,
+    /// in seconds
+    stale_window: Option<u32>,
+    prefetch: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HealthEntry {
+    max_failures: Option<u32>,
+    /// in seconds
+    eject_time: Option<u64>,
+    /// in seconds
+    check_interval: Option<u64>,
+}
+
+/// upstreams for a domain
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ForwardEntry {
+    domain: String,
+    upstreams: Vec<String>,
+    strategy: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// the file could not be read
+    Io { path: String, msg: String },
+    /// the file is not valid TOML or YAML, or has unknown fields
+    Syntax { msg: String },
+    /// a value in the file is not valid
+    Invalid { field: String, msg: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, msg } => write!(f, "Could not read {}: {}", path, msg),
+            ConfigError::Syntax { msg } => write!(f, "Invalid config file: {}", msg),
+            ConfigError::Invalid { field, msg } => {
+                write!(f, "Invalid value for {}: {}", field, msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The file formats a config can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// `.yaml` and `.yml` files are read as YAML, everything else as TOML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+impl Config {
+    /// the configuration given by the command line alone
+    pub fn from_options(options: &Options) -> Self {
+        Self {
+            listen: options.listen.clone(),
+            upstreams: options.upstreams.clone(),
+            upstream_strategy: options.upstream_strategy,
+            upstream_timeout: options.upstream_timeout(),
+            recursive: options.recursive,
+            upstream_retries: options.upstream_retries,
+            tcp_idle_timeout: options.tcp_idle_timeout(),
+            max_queries: options.max_queries as usize,
+            max_tcp_connections: options.max_tcp_connections as usize,
+            cache: CacheLimits {
+                size: options.cache_size,
+                memory: options.cache_memory,
+                ..CacheLimits::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// reads and validates the config file, on top of the
+    /// configuration given by the command line
+    pub fn load(path: &Path, options: &Options) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io {
+            path: path.display().to_string(),
+            msg: e.to_string(),
+        })?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        Self::from_options(options).merge_in(&text, ConfigFormat::from_path(path), directory)
+    }
+
+    /// applies the settings of a config file to this configuration.
+    /// Zone files are looked up from the working directory.
+    pub fn merge(self, text: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        self.merge_in(text, format, Path::new(""))
+    }
+
+    /// like [`Config::merge`], with zone files looked up from `directory`
+    pub fn merge_in(
+        mut self,
+        text: &str,
+        format: ConfigFormat,
+        directory: &Path,
+    ) -> Result<Self, ConfigError> {
+        let file: ConfigFile = match format {
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| ConfigError::Syntax {
+                msg: e.message().to_string(),
+            })?,
+            ConfigFormat::Yaml => serde_yaml::from_str(text)
+                .map_err(|e| ConfigError::Syntax { msg: e.to_string() })?,
+        };
+
+        if let Some(listen) = file.listen {
+            self.listen = parse_list("listen", &listen, |value| {
+                value
+                    .parse()
+                    .map_err(|_| "not an address with a port".to_string())
+            })?;
+            if self.listen.is_empty() {
+                return Err(invalid("listen", "at least one address is needed"));
+            }
+        }
+        if let Some(upstreams) = file.upstreams {
+            self.upstreams = parse_list("upstreams", &upstreams, parse_upstream)?;
+        }
+        if let Some(strategy) = file.upstream_strategy {
+            self.upstream_strategy = strategy
+                .parse()
+                .map_err(|msg: String| invalid("upstream_strategy", &msg))?;
+        }
+        if let Some(upstream_timeout) = file.upstream_timeout {
+            if upstream_timeout == 0 {
+                return Err(invalid("upstream_timeout", "must be more than 0"));
+            }
+            self.upstream_timeout = Duration::from_millis(upstream_timeout);
+        }
+        if let Some(upstream_retries) = file.upstream_retries {
+            self.upstream_retries = upstream_retries;
+        }
+        if let Some(max_failures) = file.upstream_health.max_failures {
+            self.upstream_health.max_failures = max_failures;
+        }
+        if let Some(eject_time) = file.upstream_health.eject_time {
+            self.upstream_health.eject_time = Duration::from_secs(eject_time);
+        }
+        if let Some(check_interval) = file.upstream_health.check_interval {
+            self.upstream_health.check_interval = Duration::from_secs(check_interval);
+        }
+        if !file.forward.is_empty() {
+            self.forward_rules = file
+                .forward
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    entry
+                        .to_forward_rule()
+                        .map_err(|msg| invalid(&format!("forward[{}]", i), &msg))
+                })
+                .collect::<Result<_, _>>()?;
+            for (i, rule) in self.forward_rules.iter().enumerate() {
+                if self.forward_rules[..i]
+                    .iter()
+                    .any(|other| other.domain == rule.domain)
+                {
+                    return Err(invalid(
+                        &format!("forward[{}]", i),
+                        "the domain already has a rule",
+                    ));
+                }
+            }
+        }
+        if let Some(recursive) = file.recursive {
+            self.recursive = recursive;
+        }
+        if let Some(qname_minimisation) = file.qname_minimisation {
+            self.qname_minimisation = qname_minimisation;
+        }
+        // a resolver starts from the root servers instead
+        if self.upstreams.is_empty() && !self.recursive {
+            return Err(invalid("upstreams", "at least one upstream is needed"));
+        }
+        if let Some(tcp_idle_timeout) = file.tcp_idle_timeout {
+            if tcp_idle_timeout == 0 {
+                return Err(invalid("tcp_idle_timeout", "must be more than 0"));
+            }
+            self.tcp_idle_timeout = Duration::from_secs(tcp_idle_timeout);
+        }
+        if let Some(max_queries) = file.max_queries {
+            if max_queries == 0 {
+                return Err(invalid("max_queries", "must be more than 0"));
+            }
+            self.max_queries = max_queries;
+        }
+        if let Some(max_tcp_connections) = file.max_tcp_connections {
+            if max_tcp_connections == 0 {
+                return Err(invalid("max_tcp_connections", "must be more than 0"));
+            }
+            self.max_tcp_connections = max_tcp_connections;
+        }
+
+        for (i, record) in file.records.iter().enumerate() {
+            let record = record
+                .to_resource_record()
+                .map_err(|msg| invalid(&format!("records[{}]", i), &msg))?;
+            self.records.insert(record);
+        }
+
+        for (i, entry) in file.zones.iter().enumerate() {
+            let field = format!("zones[{}]", i);
+            let origin = parse_name(&entry.origin).map_err(|msg| invalid(&field, &msg))?;
+            let records = load_zone(&directory.join(&entry.file), &origin.to_labels())
+                .map_err(|e| invalid(&field, &e.to_string()))?;
+            for record in records {
+                self.records.insert(record);
+            }
+        }
+
+        for (i, name) in file.blocklist.iter().enumerate() {
+            let name =
+                parse_name(name).map_err(|msg| invalid(&format!("blocklist[{}]", i), &msg))?;
+            self.blocklist.insert(name);
+        }
+
+        self.acl = Acl {
+            allow: parse_list("acl.allow", &file.acl.allow, |value| value.parse())?,
+            deny: parse_list("acl.deny", &file.acl.deny, |value| value.parse())?,
+        };
+
+        if let Some(size) = file.cache.size {
+            self.cache.size = size;
+        }
+        if let Some(memory) = file.cache.memory {
+            self.cache.memory = memory;
+        }
+        if let Some(min_ttl) = file.cache.min_ttl {
+            self.cache.min_ttl = min_ttl;
+        }
+        if let Some(max_ttl) = file.cache.max_ttl {
+            self.cache.max_ttl = max_ttl;
+        }
+        if let Some(negative_max_ttl) = file.cache.negative_max_ttl {
+            self.cache.negative_max_ttl = negative_max_ttl;
+        }
+        if let Some(stale_window) = file.cache.stale_window {
+            self.cache.stale_window = stale_window;
+        }
+        if let Some(prefetch) = file.cache.prefetch {
+            self.cache.prefetch = prefetch;
+        }
+        if self.cache.min_ttl > self.cache.max_ttl {
+            return Err(invalid("cache.min_ttl", "must not be above cache.max_ttl"));
+        }
+
+        Ok(self)
+    }
+
+    /// the forwarding rule for the longest domain the name is in
+    pub fn forward_rule(&self, name: &[DNSLabel]) -> Option<&ForwardRule> {
+        if self.forward_rules.is_empty() {
+            return None;
+        }
+        let name = Name::from_labels(name).ok()?;
+        self.forward_rules
+            .iter()
+            .filter(|rule| name.is_subdomain_of(&rule.domain))
+            .max_by_key(|rule| rule.domain.label_count())
+    }
+
+    /// the upstreams to forward a query for the name to, and the
+    /// strategy to ask them with. Names no forwarding rule covers
+    /// go to `upstreams`.
+    pub fn route(&self, name: &[DNSLabel]) -> (&[SocketAddr], UpstreamStrategy) {
+        match self.forward_rule(name) {
+            Some(rule) => (
+                &rule.upstreams,
+                rule.strategy.unwrap_or(self.upstream_strategy),
+            ),
+            None => (&self.upstreams, self.upstream_strategy),
+        }
+    }
+
+    /// every upstream in use, the ones of the forwarding rules too
+    pub fn all_upstreams(&self) -> Vec<SocketAddr> {
+        let mut upstreams = self.upstreams.clone();
+        for rule in self.forward_rules.iter() {
+            for upstream in rule.upstreams.iter() {
+                if !upstreams.contains(upstream) {
+                    upstreams.push(*upstream);
+                }
+            }
+        }
+        upstreams
+    }
+
+    /// whether the name or one of the domains above it is blocked
+    pub fn is_blocked(&self, name: &[DNSLabel]) -> bool {
+        if self.blocklist.is_empty() {
+            return false;
+        }
+        let Ok(name) = Name::from_labels(name) else {
+            return false;
+        };
+        (1..=name.label_count()).any(|count| self.blocklist.contains(&name.suffix(count)))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 5300))],
+            upstreams: vec![SocketAddr::from(([8, 8, 4, 4], 53))],
+            upstream_strategy: UpstreamStrategy::default(),
+            upstream_timeout: UPSTREAM_TIMEOUT,
+            upstream_retries: UPSTREAM_RETRIES,
+            upstream_health: HealthLimits::default(),
+            forward_rules: Vec::new(),
+            recursive: false,
+            qname_minimisation: true,
+            tcp_idle_timeout: TCP_IDLE_TIMEOUT,
+            max_queries: MAX_QUERIES,
+            max_tcp_connections: MAX_TCP_CONNECTIONS,
+            records: RecordStore::new(),
+            blocklist: HashSet::new(),
+            acl: Acl::default(),
+            cache: CacheLimits::default(),
+        }
+    }
+}
+
+impl RecordEntry {
+    fn to_resource_record(&self) -> Result<DNSResourceRecord, String> {
+        let name = parse_name(&self.name)?.to_labels();
+        let rtype = DNSRecord::type_from_name(&self.rtype)
+            .ok_or_else(|| format!("unknown record type {:?}", self.rtype))?;
+
+        // a TXT value is a single string, spaces and all
+        let data = if rtype == TXTRecord::TYPE {
+            if self.value.len() > 255 {
+                return Err("TXT values can be at most 255 bytes".to_string());
+            }
+            DNSRecord::TXT(TXTRecord(vec![self.value.clone().into_bytes()]))
+        } else {
+            parse_rdata(rtype, &self.value, &[])?
+        };
+
+        Ok(DNSResourceRecord {
+            name,
+            rtype,
+            class: CLASS_IN,
+            ttl: self.ttl.unwrap_or(DEFAULT_TTL),
+            rdlength: 0,
+            data,
+        })
+    }
+}
+
+/// Queries for names in `domain` are forwarded to `upstreams`
+/// instead of the default ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardRule {
+    pub domain: Name,
+    pub upstreams: Vec<SocketAddr>,
+    /// the strategy of the default upstreams when not set
+    pub strategy: Option<UpstreamStrategy>,
+}
+
+impl ForwardEntry {
+    fn to_forward_rule(&self) -> Result<ForwardRule, String> {
+        let domain = parse_name(&self.domain)?;
+        if self.upstreams.is_empty() {
+            return Err("at least one upstream is needed".to_string());
+        }
+        let upstreams = self
+            .upstreams
+            .iter()
+            .map(|upstream| parse_upstream(upstream))
+            .collect::<Result<_, _>>()?;
+        let strategy = self.strategy.as_deref().map(str::parse).transpose()?;
+        Ok(ForwardRule {
+            domain,
+            upstreams,
+            strategy,
+        })
+    }
+}
+
+/// Which clients may query the server. Denied networks win over
+/// allowed ones, and an empty allow list allows everyone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub allow: Vec<Network>,
+    pub deny: Vec<Network>,
+}
+
+impl Acl {
+    pub fn allows(&self, address: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(address)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(address))
+    }
+}
+
+/// An IP network in CIDR notation, like `192.168.0.0/16`.
+/// A plain address is a network of just that address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl Network {
+    /// IPv4 clients of a dual-stack socket show up as IPv4-mapped
+    /// IPv6 addresses, those are matched as the IPv4 address they are
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("{:?} is not an IP address", address))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("{:?} is not a prefix length", prefix))?,
+            None => max_prefix,
+        };
+        Ok(Self { address, prefix })
+    }
+}
+
+/// reads a domain name like `www.example.com`, with or without the
+/// final dot. `.` is the root.
+pub fn parse_name(name: &str) -> Result<Name, String> {
+    name.parse::<Name>().map_err(|e| e.to_string())
+}
+
+fn parse_list<T>(
+    field: &str,
+    values: &[String],
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<T>, ConfigError> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| parse(value).map_err(|msg| invalid(&format!("{}[{}]", field, i), &msg)))
+        .collect()
+}
+
+fn invalid(field: &str, msg: &str) -> ConfigError {
+    ConfigError::Invalid {
+        field: field.to_string(),
+        msg: msg.to_string(),
+    }
+}
@@ -0,0 +1,77 @@
+//! joyboy_dns is a small DNS server. The packet codec lives in [`structs`]
+//! and can be used on its own to parse, build and write DNS messages.
+//!
+//! ```
+//! use joyboy_dns::{DNSPacket, DNSPacketBuffer, DNSQuestion};
+//!
+//! let mut packet = DNSPacket::new();
+//! packet.push_questions(DNSQuestion::from_simple_string("example.com", 1, 1).unwrap());
+//! packet.header.id = 0x1234;
+//! packet.header.questions_count = 1;
+//!
+//! let mut buffer = DNSPacketBuffer::empty();
+//! packet.write_to_buffer(&mut buffer).unwrap();
+//!
+//! let parsed = DNSPacket::parse(buffer.response_bytes()).unwrap();
+//! assert_eq!(parsed.header.id, 0x1234);
+//! ```
+
+// the DNS* names follow the RFC spelling of the record types
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod structs {
+    pub mod dns_flags;
+    pub mod dns_header;
+    pub mod dns_packet;
+    pub mod dns_packet_buffer;
+    pub mod dns_question;
+    pub mod dns_record_types;
+    pub mod dns_resource_record;
+    pub mod error;
+    pub mod name;
+    pub mod presentation;
+    pub mod record_types {
+        pub mod a_record;
+        pub mod aaaa_record;
+        pub mod cname_record;
+        pub mod dname_record;
+        pub mod mx_record;
+        pub mod ns_record;
+        pub mod opt_record;
+        pub mod ptr_record;
+        pub mod soa_record;
+        pub mod srv_record;
+        pub mod txt_record;
+    }
+}
+
+pub mod cache;
+pub mod config;
+pub mod options;
+pub mod records;
+
+pub mod server {
+    pub mod forward;
+    pub mod handler;
+    pub mod reload;
+    pub mod resolver;
+    pub mod tcp;
+    pub mod udp;
+    pub mod upstreams;
+}
+
+pub mod utils;
+pub mod zone;
+
+pub use structs::dns_flags::DNSFlags;
+pub use structs::dns_header::DNSHeader;
+pub use structs::dns_packet::{DNSPacket, PacketRecordType};
+pub use structs::dns_packet_buffer::{
+    DNSLabel, DNSPacketBuffer, MAX_MESSAGE_SIZE, UDP_MESSAGE_SIZE,
+};
+pub use structs::dns_question::DNSQuestion;
+pub use structs::dns_record_types::DNSRecord;
+pub use structs::dns_resource_record::DNSResourceRecord;
+pub use structs::error::{NameError, ParseError, WriteError};
+pub use structs::name::Name;
+pub use structs::record_types::opt_record::{EDNSOption, OPTRecord, EDNS_UDP_PAYLOAD_SIZE};
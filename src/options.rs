@@ -0,0 +1,137 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+
+use crate::cache::{CACHE_MEMORY, CACHE_SIZE};
+use crate::server::forward::UPSTREAM_RETRIES;
+use crate::server::handler::MAX_QUERIES;
+use crate::server::tcp::MAX_TCP_CONNECTIONS;
+use crate::server::upstreams::UpstreamStrategy;
+
+/// The port DNS servers listen on
+pub const DNS_PORT: u16 = 53;
+
+/// Command line options. Every option can also be set through the
+/// environment variable named next to it in `--help`.
+#[derive(Debug, Clone, Parser)]
+#[command(version, about = "A small DNS server")]
+pub struct Options {
+    /// TOML or YAML config file. Its settings take precedence over the
+    /// flags, and it is reloaded on SIGHUP or when it changes
+    #[arg(short, long, env = "JOYBOY_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Addresses to listen on for UDP and TCP queries, IPv4 or IPv6.
+    /// Can be repeated or comma separated
+    #[arg(
+        short,
+        long = "listen",
+        env = "JOYBOY_LISTEN",
+        value_delimiter = ',',
+        default_value = "127.0.0.1:5300"
+    )]
+    pub listen: Vec<SocketAddr>,
+
+    /// Upstream servers to forward queries to, asked as
+    /// `--upstream-strategy` picks. The port defaults to 53
+    #[arg(
+        short,
+        long = "upstream",
+        env = "JOYBOY_UPSTREAM",
+        value_delimiter = ',',
+        value_parser = parse_upstream,
+        default_value = "8.8.4.4:53"
+    )]
+    pub upstreams: Vec<SocketAddr>,
+
+    /// How upstreams are picked: failover (in order), round-robin,
+    /// fastest (lowest round trip time) or parallel (first answer wins)
+    #[arg(long, env = "JOYBOY_UPSTREAM_STRATEGY", default_value_t = UpstreamStrategy::Failover)]
+    pub upstream_strategy: UpstreamStrategy,
+
+    /// Milliseconds to wait for an upstream server to answer
+    #[arg(
+        long,
+        env = "JOYBOY_UPSTREAM_TIMEOUT",
+        default_value_t = 5000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub upstream_timeout: u64,
+
+    /// How many more times the upstreams are tried
+    /// when none of them answered
+    #[arg(long, env = "JOYBOY_UPSTREAM_RETRIES", default_value_t = UPSTREAM_RETRIES)]
+    pub upstream_retries: u32,
+
+    /// Resolve names from the root servers instead of
+    /// forwarding them to the upstreams
+    #[arg(long, env = "JOYBOY_RECURSIVE")]
+    pub recursive: bool,
+
+    /// Seconds an idle TCP connection is kept open
+    #[arg(
+        long,
+        env = "JOYBOY_TCP_IDLE_TIMEOUT",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub tcp_idle_timeout: u64,
+
+    /// How many queries are answered at once, over UDP and TCP
+    /// together. UDP queries beyond it are dropped
+    #[arg(
+        long,
+        env = "JOYBOY_MAX_QUERIES",
+        default_value_t = MAX_QUERIES as u32,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub max_queries: u32,
+
+    /// How many TCP connections can be open at once,
+    /// further ones are closed right away
+    #[arg(
+        long,
+        env = "JOYBOY_MAX_TCP_CONNECTIONS",
+        default_value_t = MAX_TCP_CONNECTIONS as u32,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub max_tcp_connections: u32,
+
+    /// How many answers from upstream servers to cache, 0 turns
+    /// the cache off
+    #[arg(long, env = "JOYBOY_CACHE_SIZE", default_value_t = CACHE_SIZE)]
+    pub cache_size: usize,
+
+    /// How many bytes the cached answers may take, as estimated
+    /// from their records. 0 turns the cache off
+    #[arg(long, env = "JOYBOY_CACHE_MEMORY", default_value_t = CACHE_MEMORY)]
+    pub cache_memory: usize,
+
+    /// Log level: error, warn, info, debug or trace
+    #[arg(long, env = "JOYBOY_LOG_LEVEL", default_value = "info")]
+    pub log_level: tracing::Level,
+}
+
+impl Options {
+    pub fn upstream_timeout(&self) -> Duration {
+        Duration::from_millis(self.upstream_timeout)
+    }
+
+    pub fn tcp_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.tcp_idle_timeout)
+    }
+}
+
+/// reads an upstream address, with or without a port:
+/// `1.1.1.1`, `1.1.1.1:53`, `2606:4700::1111` or `[2606:4700::1111]:53`
+pub fn parse_upstream(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = value.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, DNS_PORT)),
+        Err(_) => Err(format!("{:?} is not an IP address", value)),
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound::{Excluded, Unbounded};
+
+use crate::structs::dns_packet_buffer::MAX_NAME_LENGTH;
+use crate::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord,
+    dname_record::DNAMERecord, ns_record::NSRecord, soa_record::SOARecord,
+};
+use crate::{DNSFlags, DNSLabel, DNSPacket, DNSRecord, DNSResourceRecord, Name};
+
+/// The QTYPE asking for every record at a name
+pub const QTYPE_ANY: u16 = 255;
+/// The IN class, the only one records are stored for
+pub const CLASS_IN: u16 = 1;
+/// The QCLASS matching any class
+pub const QCLASS_ANY: u16 = 255;
+/// How many CNAMEs are followed for one answer
+pub const MAX_ALIAS_CHAIN: usize = 8;
+
+/// Records the server answers for itself, without asking upstream.
+/// Records of the same name and type form an RRset and are always
+/// answered together.
+///
+/// A name with an SOA record is the apex of a zone. The server is
+/// authoritative for every name in the zone, so it answers missing
+/// names with NXDOMAIN instead of forwarding them, and NS records
+/// below the apex delegate to child zones. A DNAME redirects every
+/// name below its owner, in or outside of zones. Names starting with a `*`
+/// label are wildcards for the missing names below their parent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordStore {
+    /// records by their name, in canonical order
+    names: BTreeMap<Name, Vec<DNSResourceRecord>>,
+}
+
+impl RecordStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a record to its RRset. Adding the same data twice
+    /// keeps only the first one. A record whose name could not
+    /// exist is left out, nobody could ask for it.
+    pub fn insert(&mut self, record: DNSResourceRecord) {
+        let Ok(name) = Name::from_labels(&record.name) else {
+            return;
+        };
+        let records = self.names.entry(name).or_default();
+        let duplicate = records
+            .iter()
+            .any(|existing| existing.rtype == record.rtype && existing.data == record.data);
+        if !duplicate {
+            records.push(record);
+        }
+    }
+
+    /// the number of records in the store
+    pub fn len(&self) -> usize {
+        self.names.values().map(|records| records.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// every record stored at the name
+    pub fn records(&self, name: &[DNSLabel]) -> Option<&[DNSResourceRecord]> {
+        self.names
+            .get(&Name::from_labels(name).ok()?)
+            .map(|records| records.as_slice())
+    }
+
+    /// the RRset for the name and type. `None` when nothing is stored
+    /// at the name, an empty RRset when only other types are. A CNAME
+    /// at the name answers every other type.
+    pub fn lookup(&self, name: &[DNSLabel], qtype: u16) -> Option<Vec<DNSResourceRecord>> {
+        let records = self.records(name)?;
+
+        let rrset: Vec<DNSResourceRecord> = records
+            .iter()
+            .filter(|record| qtype == QTYPE_ANY || record.rtype == qtype)
+            .cloned()
+            .collect();
+        if !rrset.is_empty() {
+            return Some(rrset);
+        }
+
+        Some(
+            records
+                .iter()
+                .filter(|record| record.rtype == CNAMERecord::TYPE)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// the RRset of exactly the name and type, without CNAMEs
+    pub fn rrset(&self, name: &[DNSLabel], rtype: u16) -> Vec<DNSResourceRecord> {
+        self.records(name)
+            .unwrap_or_default()
+            .iter()
+            .filter(|record| record.rtype == rtype)
+            .cloned()
+            .collect()
+    }
+
+    /// the SOA record of the closest zone the name is in
+    pub fn zone_of(&self, name: &[DNSLabel]) -> Option<&DNSResourceRecord> {
+        (0..=name.len()).find_map(|i| {
+            self.records(&name[i..])?
+                .iter()
+                .find(|record| record.rtype == SOARecord::TYPE)
+        })
+    }
+
+    /// whether any name is stored below the name, which makes
+    /// it an empty non-terminal when nothing is stored at it. The
+    /// names below a name sort right after it.
+    fn has_children(&self, name: &[DNSLabel]) -> bool {
+        let Ok(name) = Name::from_labels(name) else {
+            return false;
+        };
+        self.names
+            .range((Excluded(&name), Unbounded))
+            .next()
+            .is_some_and(|(next, _)| next.is_subdomain_of(&name))
+    }
+
+    /// answers the query from the store, or returns `None` when the
+    /// store knows nothing about the name and it should be forwarded.
+    /// CNAMEs are followed as far as the store knows their targets,
+    /// see [`alias_target`] for where the store left off.
+    pub fn answer(&self, query: &DNSPacket) -> Option<DNSPacket> {
+        if self.is_empty() || query.questions.len() != 1 {
+            return None;
+        }
+        let question = &query.questions[0];
+        if question.qclass != CLASS_IN && question.qclass != QCLASS_ANY {
+            return None;
+        }
+
+        let mut response = DNSPacket::response_to(query);
+        if !self.resolve(&question.qname, question.qtype, &mut response) {
+            return None;
+        }
+        // the AA bit is about the name asked for, not
+        // the names its aliases lead to (RFC 1035 4.1.1)
+        let authoritative = response.header.flags.aa;
+        if question.qtype != CNAMERecord::TYPE && question.qtype != QTYPE_ANY {
+            self.follow_aliases(&question.qname, question.qtype, &mut response);
+        }
+        response.header.flags.aa = authoritative;
+        response.update_counts();
+        Some(response)
+    }
+
+    /// adds what the store has for one name to the response. `false`
+    /// when the store knows nothing about the name.
+    fn resolve(&self, name: &[DNSLabel], qtype: u16, response: &mut DNSPacket) -> bool {
+        if let Some(soa) = self.zone_of(name) {
+            return self.resolve_in_zone(name, qtype, soa, response);
+        }
+
+        // records outside of any zone only answer for themselves
+        // and, through a DNAME, for the names below them
+        if let Some(Cut::Dname(dname)) = self.cut(name, 0, false) {
+            response.header.flags.aa = true;
+            synthesize_cname(dname, name, response);
+            return true;
+        }
+        match self.lookup(name, qtype) {
+            Some(answers) => {
+                response.header.flags.aa = true;
+                response.answers.extend(answers);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// answers for a name in one of our zones. Such names are never
+    /// forwarded, a missing name is NXDOMAIN.
+    fn resolve_in_zone(
+        &self,
+        name: &[DNSLabel],
+        qtype: u16,
+        soa: &DNSResourceRecord,
+        response: &mut DNSPacket,
+    ) -> bool {
+        match self.cut(name, soa.name.len(), true) {
+            // a name reached through an alias is resolved upstream
+            // instead, the client asked about a different name
+            Some(Cut::Delegation(_)) if !response.answers.is_empty() => return false,
+            Some(Cut::Delegation(delegation)) => {
+                // not our data, point the client at the child zone
+                response.additionals = self.glue(&delegation, &soa.name);
+                response.authorities = delegation;
+                return true;
+            }
+            Some(Cut::Dname(dname)) => {
+                response.header.flags.aa = true;
+                synthesize_cname(dname, name, response);
+                return true;
+            }
+            None => {}
+        }
+
+        response.header.flags.aa = true;
+        let answers = match self.lookup(name, qtype) {
+            Some(answers) => answers,
+            None if self.has_children(name) => Vec::new(),
+            None => match self.wildcard(name, qtype, soa.name.len()) {
+                Some(answers) => answers,
+                None => {
+                    response.header.flags.rcode = DNSFlags::NXDOMAIN;
+                    response.authorities.push(negative_soa(soa));
+                    return true;
+                }
+            },
+        };
+        if answers.is_empty() {
+            // the name exists, but not with the type asked for
+            response.authorities.push(negative_soa(soa));
+        }
+        response.answers.extend(answers);
+        true
+    }
+
+    /// follows the CNAME chain in the answers, adding the records of
+    /// each target the store knows about. Stops at a loop or after
+    /// [`MAX_ALIAS_CHAIN`] aliases.
+    fn follow_aliases(&self, name: &[DNSLabel], qtype: u16, response: &mut DNSPacket) {
+        let mut seen = vec![name.to_vec()];
+        let mut name = name.to_vec();
+        while let Some(target) = alias_of(&response.answers, &name) {
+            if seen.contains(&target) || seen.len() > MAX_ALIAS_CHAIN {
+                return;
+            }
+            seen.push(target.clone());
+            if !self.resolve(&target, qtype, response) {
+                // the rest of the chain is not ours
+                return;
+            }
+            name = target;
+        }
+    }
+
+    /// the records a wildcard synthesizes for a name that does not
+    /// exist (RFC 4592). Only the wildcard directly below the closest
+    /// encloser, the longest existing parent of the name, can match.
+    fn wildcard(
+        &self,
+        name: &[DNSLabel],
+        qtype: u16,
+        apex_len: usize,
+    ) -> Option<Vec<DNSResourceRecord>> {
+        let encloser = (1..=name.len() - apex_len)
+            .map(|i| &name[i..])
+            .find(|parent| self.records(parent).is_some() || self.has_children(parent))?;
+
+        let mut wildcard = vec![DNSLabel {
+            value: b"*".to_vec(),
+        }];
+        wildcard.extend_from_slice(encloser);
+
+        let mut records = self.lookup(&wildcard, qtype)?;
+        for record in records.iter_mut() {
+            record.name = name.to_vec();
+        }
+        Some(records)
+    }
+
+    /// the highest delegation or DNAME between the apex of a zone and
+    /// the name. The NS records at the apex belong to the zone, and
+    /// a DNAME only redirects the names below its owner.
+    fn cut(&self, name: &[DNSLabel], apex_len: usize, delegations: bool) -> Option<Cut> {
+        for len in apex_len..=name.len() {
+            let owner = &name[name.len() - len..];
+            if delegations && len > apex_len {
+                let delegation = self.rrset(owner, NSRecord::TYPE);
+                if !delegation.is_empty() {
+                    return Some(Cut::Delegation(delegation));
+                }
+            }
+            if len < name.len() {
+                if let Some(dname) = self.rrset(owner, DNAMERecord::TYPE).into_iter().next() {
+                    return Some(Cut::Dname(dname));
+                }
+            }
+        }
+        None
+    }
+
+    /// the addresses of the nameservers of a delegation that live in
+    /// the zone, without which the child zone could not be reached
+    fn glue(&self, delegation: &[DNSResourceRecord], apex: &[DNSLabel]) -> Vec<DNSResourceRecord> {
+        let Ok(apex) = Name::from_labels(apex) else {
+            return Vec::new();
+        };
+        let mut glue = Vec::new();
+        for record in delegation {
+            let DNSRecord::NS(NSRecord(nameserver)) = &record.data else {
+                continue;
+            };
+            if !Name::from_labels(nameserver).is_ok_and(|name| name.is_subdomain_of(&apex)) {
+                continue;
+            }
+            glue.extend(self.rrset(nameserver, ARecord::TYPE));
+            glue.extend(self.rrset(nameserver, AAAARecord::TYPE));
+        }
+        glue
+    }
+}
+
+/// Where the tree of names stops being answered normally
+enum Cut {
+    /// the NS RRset of a child zone
+    Delegation(Vec<DNSResourceRecord>),
+    /// a DNAME redirecting the names below its owner
+    Dname(DNSResourceRecord),
+}
+
+/// the end of the CNAME chain in a response from [`RecordStore::answer`]
+/// when the store did not know how to go on, so the rest of the chain
+/// has to be resolved upstream
+pub fn alias_target(response: &DNSPacket) -> Option<Vec<DNSLabel>> {
+    let [question] = &response.questions[..] else {
+        return None;
+    };
+    if question.qtype == CNAMERecord::TYPE
+        || question.qtype == QTYPE_ANY
+        || response.header.flags.rcode != DNSFlags::NOERROR
+        || !response.authorities.is_empty()
+    {
+        return None;
+    }
+
+    chain_end(&response.answers, &question.qname, question.qtype)
+}
+
+/// where the CNAME chain starting at the name ends among the records,
+/// when the end still has to be asked for. `None` when the records
+/// answer the name, or the chain loops.
+pub fn chain_end(
+    records: &[DNSResourceRecord],
+    name: &[DNSLabel],
+    qtype: u16,
+) -> Option<Vec<DNSLabel>> {
+    let mut seen = vec![name.to_vec()];
+    let mut end = name.to_vec();
+    while let Some(target) = alias_of(records, &end) {
+        if seen.contains(&target) {
+            return None;
+        }
+        seen.push(target.clone());
+        end = target;
+    }
+
+    let answered = records
+        .iter()
+        .any(|record| record.rtype == qtype && record.name == end);
+    if answered || seen.len() == 1 {
+        return None;
+    }
+    Some(end)
+}
+
+/// the target of the CNAME owned by the name among the records
+pub fn alias_of(records: &[DNSResourceRecord], name: &[DNSLabel]) -> Option<Vec<DNSLabel>> {
+    records.iter().find_map(|record| match &record.data {
+        DNSRecord::CNAME(CNAMERecord(target)) if record.name == name => Some(target.clone()),
+        _ => None,
+    })
+}
+
+/// answers with the DNAME and the CNAME it stands for, which points
+/// the name at the same name below the DNAME target (RFC 6672 3.3)
+fn synthesize_cname(dname: DNSResourceRecord, name: &[DNSLabel], response: &mut DNSPacket) {
+    let DNSRecord::DNAME(DNAMERecord(target)) = &dname.data else {
+        return;
+    };
+    let mut alias = name[..name.len() - dname.name.len()].to_vec();
+    alias.extend_from_slice(target);
+
+    let length: usize = alias.iter().map(|label| label.value.len() + 1).sum();
+    if length + 1 > MAX_NAME_LENGTH {
+        // the name would become too long to exist
+        response.header.flags.rcode = DNSFlags::YXDOMAIN;
+        response.answers.push(dname);
+        return;
+    }
+
+    let cname = DNSResourceRecord {
+        name: name.to_vec(),
+        rtype: CNAMERecord::TYPE,
+        class: dname.class,
+        ttl: dname.ttl,
+        rdlength: 0,
+        data: DNSRecord::CNAME(CNAMERecord(alias)),
+    };
+    response.answers.push(dname);
+    response.answers.push(cname);
+}
+
+/// the SOA record sent along with negative answers. Its TTL is how
+/// long resolvers may cache the negative answer (RFC 2308 section 3).
+fn negative_soa(soa: &DNSResourceRecord) -> DNSResourceRecord {
+    let mut soa = soa.clone();
+    if let DNSRecord::SOA(data) = &soa.data {
+        soa.ttl = soa.ttl.min(data.minimum_ttl);
+    }
+    soa
+}
+
+/// every record in zone file format, sorted by name
+impl fmt::Display for RecordStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for records in self.names.values() {
+            for record in records.iter() {
+                writeln!(f, "{}", record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<DNSResourceRecord> for RecordStore {
+    fn from_iter<T: IntoIterator<Item = DNSResourceRecord>>(records: T) -> Self {
+        let mut store = Self::new();
+        for record in records {
+            store.insert(record);
+        }
+        store
+    }
+}
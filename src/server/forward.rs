@@ -0,0 +1,312 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinSet;
+use tokio::time::{self, timeout};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::structs::dns_packet_buffer::DNSLabel;
+use crate::structs::presentation::rcode_name;
+use crate::{DNSFlags, DNSPacket, OPTRecord, EDNS_UDP_PAYLOAD_SIZE};
+
+use super::upstreams::{ask, is_failure, UpstreamPool, UpstreamStrategy};
+
+/// How many more rounds over the upstreams are made by default
+/// when none of them answered
+pub const UPSTREAM_RETRIES: u32 = 1;
+
+/// How many random source ports are tried before
+/// leaving the choice to the system
+const BIND_ATTEMPTS: usize = 8;
+
+/// sends the query to the upstreams for its name, in the order their
+/// strategy picks, returning the first answer. Every upstream is tried
+/// once per round, and the rounds are repeated `upstream_retries` times
+/// before giving up. A SERVFAIL or REFUSED is only returned when no
+/// upstream gave a better answer. The answer carries the id of the query.
+pub async fn forward(
+    query: &DNSPacket,
+    config: &Config,
+    pool: &UpstreamPool,
+) -> io::Result<DNSPacket> {
+    let (upstreams, strategy) = match query.questions.first() {
+        Some(question) => config.route(&question.qname),
+        None => (&config.upstreams[..], config.upstream_strategy),
+    };
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no upstream configured");
+    let mut last_failure = None;
+    for round in 0..=config.upstream_retries {
+        let upstreams = pool.order(upstreams, strategy, Instant::now());
+        if strategy == UpstreamStrategy::Parallel && upstreams.len() > 1 {
+            match race(query, &upstreams, config, pool).await {
+                Ok(response) if is_failure(&response) => last_failure = Some(response),
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    debug!("no upstream answered (round {}): {}", round + 1, e);
+                    last_error = e;
+                }
+            }
+            continue;
+        }
+        for upstream in upstreams {
+            match ask(query, upstream, config, pool).await {
+                Ok(response) if is_failure(&response) => {
+                    debug!(
+                        "upstream {} answered {} (round {})",
+                        upstream,
+                        rcode_name(response.rcode()),
+                        round + 1
+                    );
+                    last_failure = Some(response);
+                }
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    debug!("upstream {} failed (round {}): {}", upstream, round + 1, e);
+                    last_error = e;
+                }
+            }
+        }
+    }
+    last_failure.ok_or(last_error)
+}
+
+/// asks all the upstreams at once, the first answer wins. A SERVFAIL
+/// or REFUSED only wins when all of them gave one.
+/// The exchanges still running are dropped.
+async fn race(
+    query: &DNSPacket,
+    upstreams: &[SocketAddr],
+    config: &Config,
+    pool: &UpstreamPool,
+) -> io::Result<DNSPacket> {
+    let mut exchanges = JoinSet::new();
+    for upstream in upstreams.iter().copied() {
+        let query = query.clone();
+        let upstream_timeout = config.upstream_timeout;
+        exchanges.spawn(async move {
+            let started = Instant::now();
+            let result = exchange(&query, upstream, upstream_timeout).await;
+            (upstream, started.elapsed(), result)
+        });
+    }
+
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no upstream configured");
+    let mut last_failure = None;
+    while let Some(joined) = exchanges.join_next().await {
+        let (upstream, rtt, result) = joined.map_err(io::Error::other)?;
+        match result {
+            Ok(response) if is_failure(&response) => {
+                debug!(
+                    "upstream {} answered {}",
+                    upstream,
+                    rcode_name(response.rcode())
+                );
+                pool.failed(upstream, &config.upstream_health, Instant::now());
+                last_failure = Some(response);
+            }
+            Ok(response) => {
+                pool.succeeded(upstream, rtt);
+                return Ok(response);
+            }
+            Err(e) => {
+                debug!("upstream {} failed: {}", upstream, e);
+                pool.failed(upstream, &config.upstream_health, Instant::now());
+                last_error = e;
+            }
+        }
+    }
+    last_failure.ok_or(last_error)
+}
+
+/// asks one upstream, over UDP from a fresh socket with a random
+/// id, source port and case of the query name (the 0x20 trick), so
+/// answers are hard to spoof. Truncated answers are asked for again
+/// over TCP. The answer comes back in the case the query was asked in.
+pub async fn exchange(
+    query: &DNSPacket,
+    upstream: SocketAddr,
+    upstream_timeout: Duration,
+) -> io::Result<DNSPacket> {
+    let mut request = query.clone();
+    request.header.id = rand::thread_rng().gen();
+    for question in request.questions.iter_mut() {
+        randomise_case(&mut question.qname);
+    }
+    // we take larger answers than the client might
+    let mut edns = OPTRecord::new(EDNS_UDP_PAYLOAD_SIZE);
+    edns.dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+    request.set_edns(Some(edns));
+    request.update_counts();
+    let bytes = request.to_bytes().map_err(invalid_input)?;
+
+    let mut response = exchange_udp(&request, &bytes, upstream, upstream_timeout).await?;
+    if response.header.flags.tc {
+        debug!("answer from {} was truncated, asking over TCP", upstream);
+        response = exchange_tcp(&request, &bytes, upstream, upstream_timeout).await?;
+    }
+    if response.questions.is_empty() && !query.questions.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} answered with {}",
+            upstream,
+            rcode_name(response.rcode())
+        )));
+    }
+    response.header.id = query.header.id;
+    restore_case(&mut response, query);
+    Ok(response)
+}
+
+/// flips the case of every letter in the name at random
+fn randomise_case(name: &mut [DNSLabel]) {
+    let mut rng = rand::thread_rng();
+    for label in name.iter_mut() {
+        for byte in label.value.iter_mut() {
+            *byte = match rng.gen() {
+                true => byte.to_ascii_uppercase(),
+                false => byte.to_ascii_lowercase(),
+            };
+        }
+    }
+}
+
+/// puts the names of the query back the way the client wrote them,
+/// in the question and wherever they are the owner of a record
+fn restore_case(response: &mut DNSPacket, query: &DNSPacket) {
+    for question in query.questions.iter() {
+        let records = response
+            .answers
+            .iter_mut()
+            .chain(response.authorities.iter_mut())
+            .chain(response.additionals.iter_mut());
+        for record in records {
+            if record.name == question.qname {
+                record.name = question.qname.clone();
+            }
+        }
+    }
+    response.questions = query.questions.clone();
+}
+
+/// whether two names are written exactly alike, case included
+fn same_spelling(name: &[DNSLabel], other: &[DNSLabel]) -> bool {
+    name.len() == other.len()
+        && name
+            .iter()
+            .zip(other.iter())
+            .all(|(label, other)| label.value == other.value)
+}
+
+async fn exchange_udp(
+    request: &DNSPacket,
+    bytes: &[u8],
+    upstream: SocketAddr,
+    upstream_timeout: Duration,
+) -> io::Result<DNSPacket> {
+    let socket = bind_random(upstream).await?;
+    debug!("forwarding query to {}", upstream);
+    socket.send_to(bytes, upstream).await?;
+
+    let deadline = time::Instant::now() + upstream_timeout;
+    // no answer is larger than the payload size we advertise
+    let mut buffer = vec![0u8; EDNS_UDP_PAYLOAD_SIZE as usize];
+    loop {
+        let (length, source) = time::timeout_at(deadline, socket.recv_from(&mut buffer))
+            .await
+            .map_err(|_| timed_out())??;
+        // anything else is ignored, it could be an attempt
+        // to slip a forged answer in
+        if source != upstream {
+            debug!("ignoring a datagram from {}", source);
+            continue;
+        }
+        match DNSPacket::parse(&buffer[..length]) {
+            Ok(response) if answers(request, &response) => return Ok(response),
+            Ok(_) => debug!("ignoring an answer to another query from {}", source),
+            Err(e) => debug!("ignoring a malformed answer from {}: {}", source, e),
+        }
+    }
+}
+
+async fn exchange_tcp(
+    request: &DNSPacket,
+    bytes: &[u8],
+    upstream: SocketAddr,
+    upstream_timeout: Duration,
+) -> io::Result<DNSPacket> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(upstream).await?;
+        let mut message = (bytes.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(bytes);
+        stream.write_all(&message).await?;
+
+        let length = stream.read_u16().await? as usize;
+        let mut buffer = vec![0u8; length];
+        stream.read_exact(&mut buffer).await?;
+        Ok::<_, io::Error>(buffer)
+    };
+    let buffer = timeout(upstream_timeout, exchange)
+        .await
+        .map_err(|_| timed_out())??;
+
+    let response = DNSPacket::parse(&buffer)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if !answers(request, &response) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "answer to another query",
+        ));
+    }
+    Ok(response)
+}
+
+/// whether the response is the answer to the request: a response
+/// with the same id and the same question, its name echoed in
+/// exactly the case it was asked in. Errors like FORMERR or REFUSED
+/// may come without the question, the id has to do for those.
+pub fn answers(request: &DNSPacket, response: &DNSPacket) -> bool {
+    if !response.header.flags.qr || response.header.id != request.header.id {
+        return false;
+    }
+    if response.questions.is_empty() && response.header.flags.rcode != DNSFlags::NOERROR {
+        return true;
+    }
+    response.questions.len() == request.questions.len()
+        && response
+            .questions
+            .iter()
+            .zip(request.questions.iter())
+            .all(|(answered, asked)| {
+                answered.qtype == asked.qtype
+                    && answered.qclass == asked.qclass
+                    && same_spelling(&answered.qname, &asked.qname)
+            })
+}
+
+/// a UDP socket on a random port, of the same family as the upstream
+async fn bind_random(upstream: SocketAddr) -> io::Result<UdpSocket> {
+    let mut address: SocketAddr = match upstream {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    for _ in 0..BIND_ATTEMPTS {
+        address.set_port(rand::thread_rng().gen_range(1024..=u16::MAX));
+        if let Ok(socket) = UdpSocket::bind(address).await {
+            return Ok(socket);
+        }
+    }
+    address.set_port(0);
+    UdpSocket::bind(address).await
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "upstream timed out")
+}
+
+fn invalid_input(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+}
@@ -0,0 +1,322 @@
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, trace, warn};
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::records::alias_target;
+use crate::server::forward::forward;
+use crate::server::resolver::Resolver;
+use crate::server::upstreams::{is_failure, UpstreamPool};
+use crate::{
+    DNSFlags, DNSHeader, DNSLabel, DNSPacket, DNSPacketBuffer, DNSQuestion, OPTRecord,
+    EDNS_UDP_PAYLOAD_SIZE, MAX_MESSAGE_SIZE, UDP_MESSAGE_SIZE,
+};
+
+/// The transport a query came in on, which decides
+/// how large the response may be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// How long to wait for an upstream server to answer by default
+pub const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many queries are answered at once by default
+pub const MAX_QUERIES: usize = 1024;
+
+/// Answers raw queries. Shared by the UDP and TCP front ends,
+/// so both answer the same way.
+pub struct Handler {
+    /// swapped as a whole on reload, every query
+    /// is answered with the config it started with
+    config: RwLock<Arc<Config>>,
+    /// answers from upstream, kept across reloads. Shared
+    /// with the tasks refreshing answers in the background
+    cache: Arc<Cache>,
+    /// how the upstreams have been doing, kept across reloads
+    upstreams: Arc<UpstreamPool>,
+    /// used instead of the upstreams in recursive mode
+    resolver: Arc<Resolver>,
+    /// a permit per query being answered, sized at start-up
+    queries: Arc<Semaphore>,
+    /// a permit per open TCP connection, sized at start-up
+    connections: Arc<Semaphore>,
+}
+
+impl Handler {
+    pub fn new(config: Config) -> Self {
+        Self::with_resolver(config, Resolver::default())
+    }
+
+    /// a handler resolving with the given resolver in recursive mode
+    pub fn with_resolver(config: Config, resolver: Resolver) -> Self {
+        resolver.set_qname_minimisation(config.qname_minimisation);
+        Self {
+            cache: Arc::new(Cache::new(config.cache)),
+            upstreams: Arc::new(UpstreamPool::new()),
+            resolver: Arc::new(resolver),
+            queries: Arc::new(Semaphore::new(config.max_queries)),
+            connections: Arc::new(Semaphore::new(config.max_tcp_connections)),
+            config: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    /// the configuration queries are currently answered with
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// replaces the configuration, queries in flight
+    /// finish with the old one
+    pub fn set_config(&self, config: Config) {
+        self.cache.set_limits(config.cache);
+        self.resolver
+            .set_qname_minimisation(config.qname_minimisation);
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    pub fn upstreams(&self) -> &UpstreamPool {
+        &self.upstreams
+    }
+
+    pub fn resolver(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    /// a slot to answer one more query in, none when
+    /// `max_queries` are being answered already
+    pub fn try_start_query(&self) -> Option<OwnedSemaphorePermit> {
+        self.queries.clone().try_acquire_owned().ok()
+    }
+
+    /// a slot to answer one more query in, waiting for one to free up
+    pub async fn start_query(&self) -> OwnedSemaphorePermit {
+        // the semaphore is never closed
+        self.queries.clone().acquire_owned().await.unwrap()
+    }
+
+    /// a slot for one more TCP connection, none when
+    /// `max_tcp_connections` are open already
+    pub fn try_open_connection(&self) -> Option<OwnedSemaphorePermit> {
+        self.connections.clone().try_acquire_owned().ok()
+    }
+
+    /// whether the client may query the server at all
+    pub fn allows(&self, client: IpAddr) -> bool {
+        self.config().acl.allows(client)
+    }
+
+    /// answers a raw query with the raw response to send back,
+    /// or `None` when the query should be dropped
+    pub async fn handle(&self, query: &[u8], transport: Transport) -> Option<Vec<u8>> {
+        let config = self.config();
+        // responses are never answered, two servers could
+        // otherwise keep answering each other
+        if DNSHeader::parse(&mut DNSPacketBuffer::new(query)).is_ok_and(|header| header.flags.qr) {
+            debug!("Dropping a response");
+            return None;
+        }
+        let packet = match DNSPacket::parse(query) {
+            Ok(packet) => packet,
+            Err(e) => {
+                debug!("Error parsing packet: {}", e);
+
+                // let the client know the query was malformed
+                let response = DNSPacket::error_response(query, DNSFlags::FORMERR)?;
+                return response.to_udp_bytes(UDP_MESSAGE_SIZE).ok();
+            }
+        };
+        trace!("query:\n{}", packet);
+
+        // honour the client's EDNS buffer size, but never go
+        // beyond what we advertise ourselves
+        let max_size = match transport {
+            Transport::Udp => packet
+                .max_udp_response_size()
+                .min(EDNS_UDP_PAYLOAD_SIZE as usize),
+            Transport::Tcp => MAX_MESSAGE_SIZE,
+        };
+        let edns = packet
+            .edns
+            .as_ref()
+            .map(|_| OPTRecord::new(EDNS_UDP_PAYLOAD_SIZE));
+
+        let response = if packet.header.flags.opcode != DNSFlags::QUERY {
+            // IQUERY, STATUS, NOTIFY and UPDATE are not supported
+            let mut response = DNSPacket::error_response(query, DNSFlags::NOTIMP)?;
+            response.set_edns(edns);
+            response
+        } else if packet.questions.len() != 1 {
+            // there is nothing to answer without a question,
+            // and no server takes more than one
+            let mut response = DNSPacket::error_response(query, DNSFlags::FORMERR)?;
+            response.set_edns(edns);
+            response
+        } else if packet.edns.as_ref().is_some_and(|edns| edns.version > 0) {
+            // only EDNS version 0 exists
+            let mut response = DNSPacket::error_response(query, DNSFlags::NOERROR)?;
+            response.set_edns(edns);
+            response.set_rcode(OPTRecord::BADVERS);
+            response
+        } else if packet
+            .questions
+            .iter()
+            .any(|question| config.is_blocked(&question.qname))
+        {
+            let mut response = DNSPacket::error_response(query, DNSFlags::NXDOMAIN)?;
+            response.set_edns(edns);
+            response
+        } else if let Some(mut response) = config.records.answer(&packet) {
+            if let Some(target) = alias_target(&response) {
+                self.finish_chain(&mut response, target, &config).await;
+            }
+            // we also offer recursion, by forwarding
+            response.header.flags.ra = true;
+            response.set_edns(edns);
+            response
+        } else if let Some(mut response) = self.cache.get(&packet, Instant::now()) {
+            if self.cache.take_prefetch(&packet, Instant::now()) {
+                self.prefetch(&packet, config.clone());
+            }
+            response.header.flags.ra = true;
+            response.set_edns(edns);
+            response
+        } else {
+            let upstream = match lookup(&packet, &config, &self.upstreams, &self.resolver).await {
+                Ok(response) => {
+                    trace!("upstream response:\n{}", response);
+                    self.cache.insert(&packet, &response, Instant::now());
+                    Some(response)
+                }
+                Err(e) => {
+                    warn!("Error resolving packet: {}", e);
+                    None
+                }
+            };
+            // resolving failed, an expired answer beats none at all
+            let failed = upstream.as_ref().is_none_or(is_failure);
+            let stale = if failed {
+                self.cache.get_stale(&packet, Instant::now())
+            } else {
+                None
+            };
+            let mut response = match (stale, upstream) {
+                (Some(stale), _) => {
+                    debug!("serving a stale answer");
+                    stale
+                }
+                (None, Some(response)) => response,
+                (None, None) => DNSPacket::error_response(query, DNSFlags::SERVFAIL)?,
+            };
+            response.header.flags.ra = true;
+            response.set_edns(edns);
+            response
+        };
+
+        trace!("response:\n{}", response);
+        match response.to_udp_bytes(max_size) {
+            Ok(response_bytes) => Some(response_bytes),
+            Err(e) => {
+                warn!("Error writing packet: {}", e);
+                None
+            }
+        }
+    }
+
+    /// resolves the end of a CNAME chain that left the local records
+    /// upstream, adding what the upstream knows about it. The chain
+    /// alone is no answer, so a failing upstream is a SERVFAIL.
+    async fn finish_chain(&self, response: &mut DNSPacket, target: Vec<DNSLabel>, config: &Config) {
+        let question = &response.questions[0];
+        let mut query = DNSPacket::new();
+        query.header.id = response.header.id;
+        query.header.flags.rd = true;
+        query.push_questions(DNSQuestion {
+            qname: target,
+            qtype: question.qtype,
+            qclass: question.qclass,
+        });
+        query.update_counts();
+
+        let upstream = match self.cache.get(&query, Instant::now()) {
+            Some(cached) => Ok(cached),
+            None => {
+                resolve_upstream(&query, config, &self.cache, &self.upstreams, &self.resolver).await
+            }
+        };
+        match upstream {
+            Ok(upstream) => {
+                response.header.flags.rcode = upstream.header.flags.rcode;
+                response.answers.extend(upstream.answers);
+                response.authorities.extend(upstream.authorities);
+            }
+            Err(e) => {
+                warn!("Error resolving alias target: {}", e);
+                response.header.flags.rcode = DNSFlags::SERVFAIL;
+            }
+        }
+        response.update_counts();
+    }
+
+    /// refreshes the cached answer to the query in the background,
+    /// so clients keep being answered from the cache
+    fn prefetch(&self, query: &DNSPacket, config: Arc<Config>) {
+        let query = query.clone();
+        let cache = self.cache.clone();
+        let upstreams = self.upstreams.clone();
+        let resolver = self.resolver.clone();
+        tokio::spawn(async move {
+            match resolve_upstream(&query, &config, &cache, &upstreams, &resolver).await {
+                // not cached, the next query tries again
+                Ok(response) if is_failure(&response) => cache.prefetch_failed(&query),
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("Error prefetching: {}", e);
+                    cache.prefetch_failed(&query);
+                }
+            }
+        });
+    }
+}
+
+/// resolves a query of our own, caching the answer
+async fn resolve_upstream(
+    query: &DNSPacket,
+    config: &Config,
+    cache: &Cache,
+    upstreams: &UpstreamPool,
+    resolver: &Resolver,
+) -> io::Result<DNSPacket> {
+    let response = lookup(query, config, upstreams, resolver).await?;
+    cache.insert(query, &response, Instant::now());
+    Ok(response)
+}
+
+/// asks the upstreams for the answer, or in recursive mode the name
+/// servers of the name, unless a forwarding rule covers it
+async fn lookup(
+    query: &DNSPacket,
+    config: &Config,
+    upstreams: &UpstreamPool,
+    resolver: &Resolver,
+) -> io::Result<DNSPacket> {
+    let forwarded = query
+        .questions
+        .first()
+        .is_some_and(|question| config.forward_rule(&question.qname).is_some());
+    if config.recursive && !forwarded {
+        resolver.resolve(query, config.upstream_timeout).await
+    } else {
+        forward(query, config, upstreams).await
+    }
+}
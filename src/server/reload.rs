@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::interval;
+use tracing::{error, info, warn};
+
+use crate::config::{Config, ConfigError};
+use crate::options::Options;
+
+use super::handler::Handler;
+
+/// How often the config file is checked for changes by default
+pub const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// reloads the config file on SIGHUP or whenever it changes on
+/// disk. A file that fails to load is logged and ignored, the
+/// server keeps running with the last good configuration.
+pub async fn watch_config(
+    path: PathBuf,
+    options: Options,
+    handler: Arc<Handler>,
+    poll_interval: Duration,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!("Can not listen for SIGHUP, only watching the file: {}", e);
+            None
+        }
+    };
+    let mut poll = interval(poll_interval);
+    let mut last_modified = modified(&path);
+
+    loop {
+        tokio::select! {
+            Some(_) = async { hangup.as_mut()?.recv().await } => {
+                info!("SIGHUP received, reloading {}", path.display());
+            }
+            _ = poll.tick() => {
+                let modified = modified(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                info!("{} changed, reloading", path.display());
+            }
+        }
+
+        if let Err(e) = reload(&path, &options, &handler) {
+            error!("Keeping the running configuration: {}", e);
+        }
+    }
+}
+
+/// loads the config file and swaps it in
+pub fn reload(path: &Path, options: &Options, handler: &Handler) -> Result<(), ConfigError> {
+    let config = Config::load(path, options)?;
+
+    let running = handler.config();
+    if config.listen != running.listen
+        || config.tcp_idle_timeout != running.tcp_idle_timeout
+        || config.max_queries != running.max_queries
+        || config.max_tcp_connections != running.max_tcp_connections
+    {
+        warn!(
+            "Changes to listen, tcp_idle_timeout, max_queries and \
+             max_tcp_connections only apply after a restart"
+        );
+    }
+
+    handler.set_config(config);
+    info!("Configuration reloaded from {}", path.display());
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::cache::{CACHE_MAX_TTL, CACHE_SIZE};
+use crate::options::DNS_PORT;
+use crate::records::{chain_end, MAX_ALIAS_CHAIN, QTYPE_ANY};
+use crate::structs::presentation::NameDisplay;
+use crate::structs::record_types::a_record::ARecord;
+use crate::structs::record_types::aaaa_record::AAAARecord;
+use crate::structs::record_types::cname_record::CNAMERecord;
+use crate::structs::record_types::ns_record::NSRecord;
+use crate::{DNSFlags, DNSLabel, DNSPacket, DNSQuestion, DNSRecord, DNSResourceRecord, Name};
+
+use super::forward::exchange;
+
+/// The root servers the resolver starts from, a.root-servers.net
+/// to m.root-servers.net
+pub const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+/// How many referrals are followed for one name before giving up
+const MAX_REFERRALS: usize = 16;
+/// How deep lookups of name server addresses may nest
+const MAX_DEPTH: usize = 4;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// How the resolver reaches name servers. The server uses the
+/// network, tests can answer from servers of their own.
+pub trait ResolverTransport: Send + Sync {
+    /// sends the query to the server, returning its answer
+    fn query<'a>(
+        &'a self,
+        query: &'a DNSPacket,
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> BoxFuture<'a, io::Result<DNSPacket>>;
+}
+
+/// Asks name servers over UDP, and TCP when answers are truncated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetworkTransport;
+
+impl ResolverTransport for NetworkTransport {
+    fn query<'a>(
+        &'a self,
+        query: &'a DNSPacket,
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> BoxFuture<'a, io::Result<DNSPacket>> {
+        Box::pin(exchange(query, server, timeout))
+    }
+}
+
+/// Resolves names by itself, starting from the root servers and
+/// following referrals down to the servers that are authoritative
+/// for the name. The addresses of name servers are resolved the same
+/// way when the referral comes without them, and CNAMEs are followed.
+///
+/// Delegations are kept for the TTL of their NS records, so the next
+/// name in a zone starts from the servers of the zone.
+///
+/// With QNAME minimisation (RFC 9156), servers are only shown one label
+/// more than the zone they serve, until the servers of the name itself
+/// are found.
+pub struct Resolver {
+    transport: Arc<dyn ResolverTransport>,
+    hints: Vec<SocketAddr>,
+    minimise: AtomicBool,
+    /// name servers of zones by the name of the zone
+    delegations: Mutex<HashMap<Name, Delegation>>,
+}
+
+#[derive(Debug, Clone)]
+struct Delegation {
+    servers: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+/// a referral to the name servers of a zone closer to the name
+struct Referral {
+    zone: Name,
+    names: Vec<Name>,
+    ttl: u32,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        let hints = ROOT_HINTS
+            .iter()
+            .map(|address| SocketAddr::new(IpAddr::V4(*address), DNS_PORT))
+            .collect();
+        Self::new(Arc::new(NetworkTransport), hints)
+    }
+}
+
+impl Resolver {
+    /// a resolver starting from the servers in `hints`
+    pub fn new(transport: Arc<dyn ResolverTransport>, hints: Vec<SocketAddr>) -> Self {
+        Self {
+            transport,
+            hints,
+            minimise: AtomicBool::new(true),
+            delegations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// turns QNAME minimisation on or off, it is on by default
+    pub fn set_qname_minimisation(&self, minimise: bool) {
+        self.minimise.store(minimise, Ordering::Relaxed);
+    }
+
+    /// how many delegations are known
+    pub fn delegations(&self) -> usize {
+        self.delegations.lock().unwrap().len()
+    }
+
+    /// answers the query, which has to have a single question.
+    /// Errors when no server could give an answer.
+    pub async fn resolve(&self, query: &DNSPacket, timeout: Duration) -> io::Result<DNSPacket> {
+        let [question] = &query.questions[..] else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only queries with one question are resolved",
+            ));
+        };
+        let mut response = DNSPacket::response_to(query);
+        let mut name = question.qname.clone();
+        for _ in 0..=MAX_ALIAS_CHAIN {
+            let (zone, mut answer) = self
+                .lookup(&name, question.qtype, question.qclass, timeout, 0)
+                .await?;
+            in_zone(&mut answer.answers, &zone);
+            response.header.flags.rcode = answer.header.flags.rcode;
+            response.answers.extend(answer.answers);
+            response.authorities = answer.authorities;
+
+            if question.qtype == CNAMERecord::TYPE
+                || question.qtype == QTYPE_ANY
+                || response.header.flags.rcode != DNSFlags::NOERROR
+            {
+                break;
+            }
+            match chain_end(&response.answers, &name, question.qtype) {
+                Some(target) => {
+                    debug!("following the CNAME to {}", NameDisplay(&target));
+                    name = target;
+                }
+                None => break,
+            }
+        }
+
+        response.update_counts();
+        Ok(response)
+    }
+
+    /// asks for the name, from the closest servers known down to
+    /// the servers authoritative for it. Returns the zone of the
+    /// servers that answered along with their answer.
+    fn lookup<'a>(
+        &'a self,
+        name: &'a [DNSLabel],
+        qtype: u16,
+        qclass: u16,
+        timeout: Duration,
+        depth: usize,
+    ) -> BoxFuture<'a, io::Result<(Name, DNSPacket)>> {
+        Box::pin(async move {
+            let (mut zone, mut servers) = self.closest_servers(name, Instant::now());
+            let mut minimise = self.minimise.load(Ordering::Relaxed);
+            // how many labels below the zone are shown to its servers
+            let mut shown = 1;
+            for _ in 0..MAX_REFERRALS + name.len() {
+                let minimised = minimise && zone.label_count() + shown < name.len();
+                let query = if minimised {
+                    // the type does not give the real question away,
+                    // and A queries trip up fewer servers than NS ones
+                    question(
+                        &name[name.len() - zone.label_count() - shown..],
+                        ARecord::TYPE,
+                        qclass,
+                    )
+                } else {
+                    question(name, qtype, qclass)
+                };
+                let response = match self.ask(&query, &servers, &zone, timeout).await {
+                    Ok(response) => response,
+                    Err(e) if minimised => {
+                        debug!("minimised query failed, asking for the whole name: {}", e);
+                        minimise = false;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                if minimised && response.header.flags.rcode == DNSFlags::NXDOMAIN {
+                    // nothing should exist below the name (RFC 8020), but some
+                    // servers say so for empty non-terminals (RFC 9156 section 2.3)
+                    debug!("NXDOMAIN for a minimised query, asking for the whole name");
+                    minimise = false;
+                    continue;
+                }
+                if response.header.flags.rcode == DNSFlags::NXDOMAIN
+                    || !response.answers.is_empty()
+                    || response.header.flags.aa
+                {
+                    if !minimised {
+                        return Ok((zone, response));
+                    }
+                    // no zone cut there, the same servers get one more label
+                    shown += 1;
+                    continue;
+                }
+
+                let Some(referral) = referral(&response, name, &zone) else {
+                    return Err(io::Error::other(format!(
+                        "no answer and no referral for {}",
+                        NameDisplay(name)
+                    )));
+                };
+                debug!("referred to {}", referral.zone);
+                servers = glue(&response, &referral, &zone);
+                if servers.is_empty() {
+                    servers = self
+                        .name_server_addresses(&referral, qclass, timeout, depth)
+                        .await;
+                }
+                if servers.is_empty() {
+                    return Err(io::Error::other(format!(
+                        "no address for the name servers of {}",
+                        referral.zone
+                    )));
+                }
+                self.delegate(&referral, &servers, Instant::now());
+                zone = referral.zone;
+                shown = 1;
+            }
+            Err(io::Error::other(format!(
+                "too many referrals for {}",
+                NameDisplay(name)
+            )))
+        })
+    }
+
+    /// asks the servers of the zone in turn until one gives an answer.
+    /// Servers that are lame for the zone are passed over like ones
+    /// that fail.
+    async fn ask(
+        &self,
+        query: &DNSPacket,
+        servers: &[SocketAddr],
+        zone: &Name,
+        timeout: Duration,
+    ) -> io::Result<DNSPacket> {
+        let mut last_error = io::Error::other("no name server to ask");
+        for server in servers.iter() {
+            match self.transport.query(query, *server, timeout).await {
+                Ok(response) if is_lame(&response, query, zone) => {
+                    debug!("{} is lame for {}", server, zone);
+                    last_error = io::Error::other(format!("{} is lame for {}", server, zone));
+                }
+                Ok(response)
+                    if response.header.flags.rcode == DNSFlags::NOERROR
+                        || response.header.flags.rcode == DNSFlags::NXDOMAIN =>
+                {
+                    return Ok(response)
+                }
+                Ok(response) => {
+                    debug!(
+                        "{} answered with rcode {}",
+                        server, response.header.flags.rcode
+                    );
+                    last_error = io::Error::other(format!("{} failed to answer", server));
+                }
+                Err(e) => {
+                    debug!("{} failed: {}", server, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// resolves the addresses of name servers that came without glue,
+    /// stopping at the first one that has any
+    async fn name_server_addresses(
+        &self,
+        referral: &Referral,
+        qclass: u16,
+        timeout: Duration,
+        depth: usize,
+    ) -> Vec<SocketAddr> {
+        if depth >= MAX_DEPTH {
+            return Vec::new();
+        }
+        for name in referral.names.iter() {
+            // without glue, names in the zone itself can not be reached
+            if name.is_subdomain_of(&referral.zone) {
+                continue;
+            }
+            let labels = name.to_labels();
+            for qtype in [ARecord::TYPE, AAAARecord::TYPE] {
+                match self
+                    .lookup(&labels, qtype, qclass, timeout, depth + 1)
+                    .await
+                {
+                    Ok((zone, mut response)) => {
+                        in_zone(&mut response.answers, &zone);
+                        let addresses = addresses(&response.answers, &labels);
+                        if !addresses.is_empty() {
+                            return addresses;
+                        }
+                    }
+                    Err(e) => debug!("could not resolve name server {}: {}", name, e),
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    /// the servers of the closest zone above the name
+    /// with a known delegation, or the root hints
+    fn closest_servers(&self, name: &[DNSLabel], now: Instant) -> (Name, Vec<SocketAddr>) {
+        let delegations = self.delegations.lock().unwrap();
+        for i in 0..name.len() {
+            let Ok(zone) = Name::from_labels(&name[i..]) else {
+                continue;
+            };
+            if let Some(delegation) = delegations.get(&zone) {
+                if delegation.expires > now {
+                    return (zone, delegation.servers.clone());
+                }
+            }
+        }
+        (Name::root(), self.hints.clone())
+    }
+
+    fn delegate(&self, referral: &Referral, servers: &[SocketAddr], now: Instant) {
+        let mut delegations = self.delegations.lock().unwrap();
+        if delegations.len() >= CACHE_SIZE {
+            delegations.retain(|_, delegation| delegation.expires > now);
+            if delegations.len() >= CACHE_SIZE {
+                delegations.clear();
+            }
+        }
+        let ttl = referral.ttl.min(CACHE_MAX_TTL);
+        delegations.insert(
+            referral.zone.clone(),
+            Delegation {
+                servers: servers.to_vec(),
+                expires: now + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+}
+
+/// a query of our own, without recursion desired
+fn question(name: &[DNSLabel], qtype: u16, qclass: u16) -> DNSPacket {
+    let mut query = DNSPacket::new();
+    query.push_questions(DNSQuestion {
+        qname: name.to_vec(),
+        qtype,
+        qclass,
+    });
+    query.update_counts();
+    query
+}
+
+/// the NS records in the authority section, when they delegate
+/// to a zone the name is in and that is below the zone asked
+fn referral(response: &DNSPacket, name: &[DNSLabel], zone: &Name) -> Option<Referral> {
+    let name = Name::from_labels(name).ok()?;
+    let child = response
+        .authorities
+        .iter()
+        .filter(|record| record.rtype == NSRecord::TYPE)
+        .filter_map(|record| Name::from_labels(&record.name).ok())
+        .find(|owner| {
+            owner.label_count() > zone.label_count()
+                && name.is_subdomain_of(owner)
+                && owner.is_subdomain_of(zone)
+        })?;
+
+    let owner = child.to_labels();
+    let records: Vec<&DNSResourceRecord> = response
+        .authorities
+        .iter()
+        .filter(|record| record.name == owner)
+        .collect();
+    let names = records
+        .iter()
+        .filter_map(|record| match &record.data {
+            DNSRecord::NS(NSRecord(target)) => Name::from_labels(target).ok(),
+            _ => None,
+        })
+        .collect();
+    let ttl = records.iter().map(|record| record.ttl).min().unwrap_or(0);
+    Some(Referral {
+        zone: child,
+        names,
+        ttl,
+    })
+}
+
+/// whether the server knows nothing about the zone it was asked as a
+/// server of: a non-authoritative NOERROR without an answer and
+/// without a referral further down
+fn is_lame(response: &DNSPacket, query: &DNSPacket, zone: &Name) -> bool {
+    let [question] = &query.questions[..] else {
+        return false;
+    };
+    response.header.flags.rcode == DNSFlags::NOERROR
+        && !response.header.flags.aa
+        && response.answers.is_empty()
+        && referral(response, &question.qname, zone).is_none()
+}
+
+/// drops the answers the servers of the zone have no say over, so a
+/// server can not answer for names elsewhere. Aliases leading out of
+/// the zone are followed by asking the servers of their targets.
+fn in_zone(answers: &mut Vec<DNSResourceRecord>, zone: &Name) {
+    answers.retain(|record| {
+        Name::from_labels(&record.name).is_ok_and(|name| name.is_subdomain_of(zone))
+    });
+}
+
+/// the addresses of the name servers given along with the referral.
+/// Only addresses for names in the zone that was asked are taken, a
+/// server has no say over names outside of it.
+fn glue(response: &DNSPacket, referral: &Referral, zone: &Name) -> Vec<SocketAddr> {
+    referral
+        .names
+        .iter()
+        .filter(|name| name.is_subdomain_of(zone))
+        .flat_map(|name| addresses(&response.additionals, &name.to_labels()))
+        .collect()
+}
+
+/// the IPv4 and IPv6 addresses of the name among the records
+fn addresses(records: &[DNSResourceRecord], name: &[DNSLabel]) -> Vec<SocketAddr> {
+    records
+        .iter()
+        .filter(|record| record.name == name)
+        .filter_map(|record| match &record.data {
+            DNSRecord::A(ARecord(address)) => Some(IpAddr::V4(*address)),
+            DNSRecord::AAAA(AAAARecord(address)) => Some(IpAddr::V6(*address)),
+            _ => None,
+        })
+        .map(|address| SocketAddr::new(address, DNS_PORT))
+        .collect()
+}
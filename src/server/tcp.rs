@@ -0,0 +1,116 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+use super::handler::{Handler, Transport};
+
+/// How long a connection may sit without a new query before
+/// it is closed by default (RFC 7766 recommends a few seconds)
+pub const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many TCP connections can be open at once by default
+pub const MAX_TCP_CONNECTIONS: usize = 128;
+
+pub async fn handle_tcp(tcp_listener: TcpListener, handler: Arc<Handler>, idle_timeout: Duration) {
+    loop {
+        let (stream, peer) = match tcp_listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("TCP connection error: {}", e);
+                continue;
+            }
+        };
+        debug!("Accepted TCP connection from {}", peer);
+        if !handler.allows(peer.ip()) {
+            debug!("Closing connection from {}, not allowed", peer);
+            continue;
+        }
+        let Some(permit) = handler.try_open_connection() else {
+            debug!("Closing connection from {}, too many open", peer);
+            continue;
+        };
+
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, handler, idle_timeout).await {
+                debug!("TCP connection closed with error: {}", e);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// answers queries on one connection until the client closes it or
+/// stays idle for too long. Queries sent back to back are answered
+/// concurrently, so the answers can come back in any order. They
+/// count towards `max_queries` like queries over UDP do.
+pub async fn handle_connection(
+    stream: TcpStream,
+    handler: Arc<Handler>,
+    idle_timeout: Duration,
+) -> io::Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    let (responses, mut pending) = mpsc::channel::<Vec<u8>>(32);
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(response) = pending.recv().await {
+            write_message(&mut writer, &response).await?;
+        }
+        writer.shutdown().await
+    });
+
+    loop {
+        let query = match timeout(idle_timeout, read_message(&mut reader)).await {
+            Ok(Ok(Some(query))) => query,
+            // closed by the client or idle for too long
+            Ok(Ok(None)) | Err(_) => break,
+            Ok(Err(e)) => return Err(e),
+        };
+        debug!("Received TCP packet of size {}", query.len());
+
+        // no more is read from the client until a query slot frees up
+        let permit = handler.start_query().await;
+        let handler = handler.clone();
+        let responses = responses.clone();
+        tokio::spawn(async move {
+            if let Some(response) = handler.handle(&query, Transport::Tcp).await {
+                let _ = responses.send(response).await;
+            }
+            drop(permit);
+        });
+    }
+
+    // the writer finishes once every query in flight was answered
+    drop(responses);
+    writer_task.await?
+}
+
+/// reads one message prefixed with its two byte length. Returns
+/// `None` when the client closed the connection between messages.
+pub async fn read_message(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 2];
+    match stream.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+pub async fn write_message(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &[u8],
+) -> io::Result<()> {
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed).await
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use tokio::net::UdpSocket;
+use tracing::{debug, trace, warn};
+
+use crate::MAX_MESSAGE_SIZE;
+
+use super::handler::{Handler, Transport};
+
+/// receives queries on the socket and answers each one in
+/// its own task, so a slow answer does not hold up the rest.
+/// Queries beyond `max_queries` in flight are dropped.
+pub async fn handle_udp(udp_socket: UdpSocket, handler: Arc<Handler>) {
+    let udp_socket = Arc::new(udp_socket);
+    let mut udp_buffer = vec![0u8; MAX_MESSAGE_SIZE];
+
+    loop {
+        let (amt, src) = match udp_socket.recv_from(&mut udp_buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("UDP receive error: {}", e);
+                continue;
+            }
+        };
+
+        debug!("Received UDP packet of size {} from {}", amt, src);
+        if !handler.allows(src.ip()) {
+            debug!("Dropping query from {}, not allowed", src);
+            continue;
+        }
+        trace!("{:02x?}", &udp_buffer[..amt]);
+        // shed the load when too many queries are being answered
+        let Some(permit) = handler.try_start_query() else {
+            debug!("Dropping query from {}, too many in flight", src);
+            continue;
+        };
+
+        let query = udp_buffer[..amt].to_vec();
+        let udp_socket = udp_socket.clone();
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Some(response) = handler.handle(&query, Transport::Udp).await {
+                if let Err(err) = udp_socket.send_to(&response, src).await {
+                    warn!("Error sending packet: {}", err);
+                }
+            }
+            drop(permit);
+        });
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::structs::presentation::rcode_name;
+use crate::structs::record_types::ns_record::NSRecord;
+use crate::{DNSFlags, DNSPacket, DNSQuestion};
+
+use super::forward::exchange;
+use super::handler::Handler;
+
+/// How many failures in a row take an upstream out of use by default
+pub const UPSTREAM_MAX_FAILURES: u32 = 3;
+/// How long a failing upstream is left out by default
+pub const UPSTREAM_EJECT_TIME: Duration = Duration::from_secs(30);
+/// How often upstreams are probed by default
+pub const UPSTREAM_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How the upstreams are picked for a query
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpstreamStrategy {
+    /// in the configured order, the next one only when one fails
+    #[default]
+    Failover,
+    /// starting with the next upstream on every query
+    RoundRobin,
+    /// the one that has been answering quickest first
+    Fastest,
+    /// all at once, the first answer wins
+    Parallel,
+}
+
+impl FromStr for UpstreamStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "failover" => Ok(UpstreamStrategy::Failover),
+            "round-robin" => Ok(UpstreamStrategy::RoundRobin),
+            "fastest" => Ok(UpstreamStrategy::Fastest),
+            "parallel" => Ok(UpstreamStrategy::Parallel),
+            _ => Err(format!(
+                "{:?} is not one of failover, round-robin, fastest or parallel",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UpstreamStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            UpstreamStrategy::Failover => "failover",
+            UpstreamStrategy::RoundRobin => "round-robin",
+            UpstreamStrategy::Fastest => "fastest",
+            UpstreamStrategy::Parallel => "parallel",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// When upstreams are taken out of use and how they are checked on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthLimits {
+    /// failures in a row before an upstream is left out,
+    /// 0 never leaves one out
+    pub max_failures: u32,
+    /// how long a failing upstream is left out
+    pub eject_time: Duration,
+    /// how often every upstream is probed, 0 turns probing off
+    pub check_interval: Duration,
+}
+
+impl Default for HealthLimits {
+    fn default() -> Self {
+        Self {
+            max_failures: UPSTREAM_MAX_FAILURES,
+            eject_time: UPSTREAM_EJECT_TIME,
+            check_interval: UPSTREAM_CHECK_INTERVAL,
+        }
+    }
+}
+
+/// How an upstream has been doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpstreamStats {
+    /// smoothed round trip time, once the upstream answered
+    pub rtt: Option<Duration>,
+    pub queries: u64,
+    pub failures: u64,
+    /// failures since the last answer
+    pub failures_in_a_row: u32,
+    /// until when the upstream is left out
+    pub ejected_until: Option<Instant>,
+}
+
+/// The health of the upstream servers, kept across reloads.
+///
+/// Every exchange is recorded: upstreams that fail too often in a
+/// row are left out for a while, and answers keep a smoothed round
+/// trip time to pick the fastest upstream by. Once its time is up an
+/// upstream is tried again, and a single failure leaves it out again.
+#[derive(Debug, Default)]
+pub struct UpstreamPool {
+    health: Mutex<HashMap<SocketAddr, UpstreamStats>>,
+    /// where the next round robin starts
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self, upstream: SocketAddr) -> UpstreamStats {
+        self.health
+            .lock()
+            .unwrap()
+            .get(&upstream)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// the upstreams to ask, in the order the strategy asks them.
+    /// Upstreams that are left out are skipped, unless all of them
+    /// are, then they are all asked as configured.
+    pub fn order(
+        &self,
+        upstreams: &[SocketAddr],
+        strategy: UpstreamStrategy,
+        now: Instant,
+    ) -> Vec<SocketAddr> {
+        let health = self.health.lock().unwrap();
+        let stats = |upstream: &SocketAddr| health.get(upstream).copied().unwrap_or_default();
+        let mut order: Vec<SocketAddr> = upstreams
+            .iter()
+            .filter(|upstream| {
+                stats(upstream)
+                    .ejected_until
+                    .is_none_or(|until| until <= now)
+            })
+            .copied()
+            .collect();
+        if order.is_empty() {
+            return upstreams.to_vec();
+        }
+
+        match strategy {
+            UpstreamStrategy::Failover | UpstreamStrategy::Parallel => {}
+            UpstreamStrategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % order.len();
+                order.rotate_left(start);
+            }
+            UpstreamStrategy::Fastest => {
+                // upstreams that never answered go first, to be measured
+                order.sort_by_key(|upstream| stats(upstream).rtt.unwrap_or_default());
+            }
+        }
+        order
+    }
+
+    /// records an answer from the upstream, taking it back into use
+    pub fn succeeded(&self, upstream: SocketAddr, rtt: Duration) {
+        let mut health = self.health.lock().unwrap();
+        let stats = health.entry(upstream).or_default();
+        stats.queries += 1;
+        // weighs the new sample by an eighth, like TCP does (RFC 6298)
+        stats.rtt = Some(match stats.rtt {
+            Some(smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        });
+        if stats.ejected_until.is_some() {
+            info!("upstream {} is answering again", upstream);
+        }
+        stats.failures_in_a_row = 0;
+        stats.ejected_until = None;
+    }
+
+    /// records a failed exchange, leaving the upstream out
+    /// when it failed too often in a row
+    pub fn failed(&self, upstream: SocketAddr, limits: &HealthLimits, now: Instant) {
+        let mut health = self.health.lock().unwrap();
+        let stats = health.entry(upstream).or_default();
+        stats.queries += 1;
+        stats.failures += 1;
+        stats.failures_in_a_row = stats.failures_in_a_row.saturating_add(1);
+        if limits.max_failures == 0 || stats.failures_in_a_row < limits.max_failures {
+            return;
+        }
+        if stats.ejected_until.is_none_or(|until| until <= now) {
+            warn!(
+                "upstream {} failed {} times in a row, leaving it out for {:?}",
+                upstream, stats.failures_in_a_row, limits.eject_time
+            );
+            stats.ejected_until = Some(now + limits.eject_time);
+        }
+    }
+}
+
+/// whether the upstream gave up on the query instead of answering it.
+/// A SERVFAIL or REFUSED counts as a failure of the upstream, another
+/// one may well answer.
+pub fn is_failure(response: &DNSPacket) -> bool {
+    matches!(
+        response.header.flags.rcode,
+        DNSFlags::SERVFAIL | DNSFlags::REFUSED
+    )
+}
+
+/// asks one upstream and records how it went
+pub async fn ask(
+    query: &DNSPacket,
+    upstream: SocketAddr,
+    config: &Config,
+    pool: &UpstreamPool,
+) -> std::io::Result<DNSPacket> {
+    let started = Instant::now();
+    let result = exchange(query, upstream, config.upstream_timeout).await;
+    record(pool, upstream, started.elapsed(), &result, config);
+    result
+}
+
+/// records an exchange with the upstream in the pool
+fn record(
+    pool: &UpstreamPool,
+    upstream: SocketAddr,
+    rtt: Duration,
+    result: &std::io::Result<DNSPacket>,
+    config: &Config,
+) {
+    match result {
+        Ok(response) if !is_failure(response) => pool.succeeded(upstream, rtt),
+        _ => pool.failed(upstream, &config.upstream_health, Instant::now()),
+    }
+}
+
+/// probes every upstream, the ones of the forwarding rules too,
+/// once with a query for the root name servers. Upstreams that
+/// answer are taken back into use, ones that do not or that answer
+/// with SERVFAIL or REFUSED count a failure.
+pub async fn check_upstreams(config: &Config, pool: &UpstreamPool) {
+    let mut probe = DNSPacket::new();
+    probe.header.flags.rd = true;
+    probe.push_questions(DNSQuestion {
+        qname: vec![],
+        qtype: NSRecord::TYPE,
+        qclass: 1,
+    });
+    probe.update_counts();
+
+    let mut probes = JoinSet::new();
+    for upstream in config.all_upstreams() {
+        let probe = probe.clone();
+        let upstream_timeout = config.upstream_timeout;
+        probes.spawn(async move {
+            let started = Instant::now();
+            let result = exchange(&probe, upstream, upstream_timeout).await;
+            (upstream, started.elapsed(), result)
+        });
+    }
+    while let Some(Ok((upstream, rtt, result))) = probes.join_next().await {
+        match &result {
+            Ok(response) if is_failure(response) => debug!(
+                "health check of {} failed: {}",
+                upstream,
+                rcode_name(response.rcode())
+            ),
+            Ok(_) => {}
+            Err(e) => debug!("health check of {} failed: {}", upstream, e),
+        }
+        record(pool, upstream, rtt, &result, config);
+    }
+}
+
+/// probes the upstreams of the running configuration every
+/// `check_interval`, for as long as the server runs
+pub async fn watch_upstreams(handler: Arc<Handler>) {
+    loop {
+        let check_interval = handler.config().upstream_health.check_interval;
+        if check_interval.is_zero() {
+            // probing is off, until a reload turns it back on
+            sleep(UPSTREAM_CHECK_INTERVAL).await;
+            continue;
+        }
+        sleep(check_interval).await;
+        check_upstreams(&handler.config(), handler.upstreams()).await;
+    }
+}
@@ -1,6 +1,6 @@
-use super::{error::ParseError, dns_packet_buffer::DNSPacketBuffer};
+use super::{dns_packet_buffer::DNSPacketBuffer, error::ParseError};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSFlags {
     /// specifies if this is a query or a response
     /// false(0) for a query and true(1) for a response
@@ -48,6 +48,19 @@ pub struct DNSFlags {
 }
 
 impl DNSFlags {
+    /// the opcode of a standard query, the only one answered
+    pub const QUERY: u8 = 0;
+
+    pub const NOERROR: u8 = 0;
+    pub const FORMERR: u8 = 1;
+    pub const SERVFAIL: u8 = 2;
+    pub const NXDOMAIN: u8 = 3;
+    pub const NOTIMP: u8 = 4;
+    pub const REFUSED: u8 = 5;
+    /// a name that should not exist does (RFC 2136), also used when
+    /// a DNAME would make a name too long (RFC 6672)
+    pub const YXDOMAIN: u8 = 6;
+
     pub fn new() -> Self {
         DNSFlags {
             qr: false,
@@ -65,7 +78,7 @@ impl DNSFlags {
     /// So this function parses the flags from a list bytes
     /// which is of length 2
     pub fn parse(buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
-        let chunk = buffer.next_chunk(2); // get two bytes
+        let chunk = buffer.next_chunk(2)?; // get two bytes
 
         // bit wise oprations
         // let qr = chunk[0] & 0b1000_0000 == 0b1000_0000;
@@ -83,7 +96,7 @@ impl DNSFlags {
         let aa = chunk[0] & 0x04 == 0x04;
         let tc = chunk[0] & 0x02 == 0x02;
         let rd = chunk[0] & 0x01 == 0x01;
-        let ra = chunk[1] & 0x80 == 0x08;
+        let ra = chunk[1] & 0x80 == 0x80;
         let z = (chunk[1] >> 4) & 0x07;
         let rcode = chunk[1] & 0x0F;
 
@@ -102,16 +115,21 @@ impl DNSFlags {
     pub fn to_u8(&self) -> [u8; 2] {
         let mut buffer: [u8; 2] = [0, 0];
         buffer[0] |= (self.qr as u8) << 7;
-        buffer[0] |= (self.opcode as u8) << 3;
+        buffer[0] |= self.opcode << 3;
         buffer[0] |= (self.aa as u8) << 2;
         buffer[0] |= (self.tc as u8) << 1;
         buffer[0] |= self.rd as u8;
 
         buffer[1] |= (self.ra as u8) << 7;
-        buffer[1] |= (self.z as u8) << 4;
-        buffer[1] |= self.rcode as u8;
+        buffer[1] |= self.z << 4;
+        buffer[1] |= self.rcode;
 
         buffer
     }
+}
 
+impl Default for DNSFlags {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -1,9 +1,11 @@
 use super::{
-    dns_flags::DNSFlags, dns_packet::DNSPacket, dns_packet_buffer::DNSPacketBuffer,
-    error::ParseError,
+    dns_flags::DNSFlags,
+    dns_packet::DNSPacket,
+    dns_packet_buffer::DNSPacketBuffer,
+    error::{ParseError, WriteError},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSHeader {
     /// A 16 bit identifier assigned by the program that
     /// generates any kind of query.
@@ -30,17 +32,18 @@ impl DNSHeader {
     /// contain 12 bytes exactly
     pub fn parse(buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         if buffer.remaining_bytes(Self::SIZE) {
-            return Err(ParseError::InvalidHeader {
-                msg: "Packet is too short".to_string(),
+            return Err(ParseError::Truncated {
+                offset: buffer.offset,
+                needed: Self::SIZE,
             });
         }
-        let id = buffer.read_u16();
+        let id = buffer.read_u16()?;
         let flags = DNSFlags::parse(buffer)?;
 
-        let questions_count: u16 = buffer.read_u16();
-        let answers_count: u16 = buffer.read_u16();
-        let authorities_count: u16 = buffer.read_u16();
-        let additionals: u16 = buffer.read_u16();
+        let questions_count: u16 = buffer.read_u16()?;
+        let answers_count: u16 = buffer.read_u16()?;
+        let authorities_count: u16 = buffer.read_u16()?;
+        let additionals: u16 = buffer.read_u16()?;
 
         Ok(DNSHeader {
             id,
@@ -52,39 +55,26 @@ impl DNSHeader {
         })
     }
 
+    /// a header for the packet with the given id, keeping the flags
+    /// of the packet and counting the records in each section
     pub fn from_dns_struct(dns: &DNSPacket, id: u16) -> Self {
-        let mut flags = DNSFlags::new();
-
-        if (dns.answers.len() > 0) || (dns.authorities.len() > 0) || (dns.additionals.len() > 0) {
-            flags.qr = false;
-        } else {
-            flags.qr = true;
-        }
-
-        flags.opcode = 0;
-        flags.aa = true;
-        flags.tc = false;
-        flags.rd = true;
-        flags.ra = true;
-        flags.z = 0;
-        flags.rcode = 0;
-
         DNSHeader {
             id,
-            flags,
+            flags: dns.header.flags.clone(),
             questions_count: dns.questions.len() as u16,
             answers_count: dns.answers.len() as u16,
             authorities_count: dns.authorities.len() as u16,
-            additionals_count: dns.additionals.len() as u16,
+            additionals_count: dns.additionals.len() as u16 + dns.edns.is_some() as u16,
         }
     }
 
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) {
-        buffer.write_u16(self.id);
-        buffer.write_bytes(&self.flags.to_u8());
-        buffer.write_u16(self.questions_count);
-        buffer.write_u16(self.answers_count);
-        buffer.write_u16(self.authorities_count);
-        buffer.write_u16(self.additionals_count);
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        buffer.write_u16(self.id)?;
+        buffer.write_bytes(&self.flags.to_u8())?;
+        buffer.write_u16(self.questions_count)?;
+        buffer.write_u16(self.answers_count)?;
+        buffer.write_u16(self.authorities_count)?;
+        buffer.write_u16(self.additionals_count)?;
+        Ok(())
     }
 }
@@ -1,13 +1,17 @@
-use crate::utils::contains_domain;
+use std::fmt;
 
 use super::dns_flags::DNSFlags;
 use super::dns_header::DNSHeader;
-use super::dns_packet_buffer::{DNSLabel, DNSPacketBuffer};
+use super::dns_packet_buffer::{DNSLabel, DNSPacketBuffer, MAX_MESSAGE_SIZE, UDP_MESSAGE_SIZE};
 use super::dns_question::DNSQuestion;
+use super::dns_record_types::DNSRecord;
 use super::dns_resource_record::DNSResourceRecord;
-use super::error::ParseError;
+use super::error::{ParseError, WriteError};
+use super::name::Name;
+use super::presentation::{opcode_name, rcode_name};
+use super::record_types::opt_record::OPTRecord;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSPacket {
     /// contains the information about the packet
     /// The header contains the following fields:
@@ -35,6 +39,9 @@ pub struct DNSPacket {
     pub authorities: Vec<DNSResourceRecord>,
     /// contains the RRs containing additional information
     pub additionals: Vec<DNSResourceRecord>,
+    /// the EDNS(0) OPT pseudo-record. It is sent in the additional
+    /// section, but kept apart from the other additionals
+    pub edns: Option<OPTRecord>,
 }
 
 impl DNSPacket {
@@ -61,9 +68,96 @@ impl DNSPacket {
             answers: Vec::new(),
             authorities: Vec::new(),
             additionals: Vec::new(),
+            edns: None,
         }
     }
 
+    /// builds an empty response that only carries a response code,
+    /// for queries that can not be answered at all. The id and the
+    /// question are copied when they could be read from the query.
+    pub fn error_response(query: &[u8], rcode: u8) -> Option<Self> {
+        let mut buffer = DNSPacketBuffer::new(query);
+        let id = buffer.read_u16().ok()?;
+
+        let mut response = DNSPacket::new();
+        response.header.id = id;
+
+        buffer.offset = 0;
+        if let Ok(header) = DNSHeader::parse(&mut buffer) {
+            response.header.flags.opcode = header.flags.opcode;
+            response.header.flags.rd = header.flags.rd;
+            // the question is only echoed when it can be read cleanly
+            if header.questions_count == 1 {
+                if let Ok(questions) = DNSQuestion::parse_questions(1, &mut buffer) {
+                    response.questions = questions;
+                }
+            }
+        }
+        response.header.flags.qr = true;
+        response.header.flags.rcode = rcode;
+        response.header.questions_count = response.questions.len() as u16;
+
+        Some(response)
+    }
+
+    /// starts the response to a query, with the id, opcode,
+    /// recursion desired flag and questions copied over
+    pub fn response_to(query: &DNSPacket) -> Self {
+        let mut response = DNSPacket::new();
+        response.header.id = query.header.id;
+        response.header.flags.qr = true;
+        response.header.flags.opcode = query.header.flags.opcode;
+        response.header.flags.rd = query.header.flags.rd;
+        response.questions = query.questions.clone();
+        response.update_counts();
+        response
+    }
+
+    /// sets the section counts of the header to the
+    /// number of records in each section
+    pub fn update_counts(&mut self) {
+        self.header.questions_count = self.questions.len() as u16;
+        self.header.answers_count = self.answers.len() as u16;
+        self.header.authorities_count = self.authorities.len() as u16;
+        self.header.additionals_count = self.additionals.len() as u16 + self.edns.is_some() as u16;
+    }
+
+    /// the full 12 bit response code, combining the header
+    /// rcode with the upper bits stored in the OPT record
+    pub fn rcode(&self) -> u16 {
+        let extended = self.edns.as_ref().map_or(0, |edns| edns.extended_rcode);
+        ((extended as u16) << 4) | self.header.flags.rcode as u16
+    }
+
+    /// sets the response code, moving the upper bits into the OPT
+    /// record. Codes above 15 need EDNS, so an OPT record is added
+    /// when the packet has none.
+    pub fn set_rcode(&mut self, rcode: u16) {
+        self.header.flags.rcode = (rcode & 0x0F) as u8;
+        let extended = (rcode >> 4) as u8;
+        if extended > 0 && self.edns.is_none() {
+            self.set_edns(Some(OPTRecord::new(UDP_MESSAGE_SIZE as u16)));
+        }
+        if let Some(edns) = &mut self.edns {
+            edns.extended_rcode = extended;
+        }
+    }
+
+    /// attaches or removes the OPT record, keeping the
+    /// additionals count of the header in sync
+    pub fn set_edns(&mut self, edns: Option<OPTRecord>) {
+        self.header.additionals_count = self.additionals.len() as u16 + edns.is_some() as u16;
+        self.edns = edns;
+    }
+
+    /// the size a UDP response to this query may take up, taken
+    /// from the OPT record or 512 bytes for clients without EDNS
+    pub fn max_udp_response_size(&self) -> usize {
+        self.edns
+            .as_ref()
+            .map_or(UDP_MESSAGE_SIZE, |edns| edns.max_udp_size())
+    }
+
     pub fn push_questions(&mut self, questions: DNSQuestion) -> &mut Self {
         self.questions.push(questions);
         self
@@ -84,7 +178,13 @@ impl DNSPacket {
         self
     }
 
-    pub fn parse(packet: [u8; 512]) -> Result<Self, ParseError> {
+    /// parses a whole DNS message of any size up to 65535 bytes
+    pub fn parse(packet: &[u8]) -> Result<Self, ParseError> {
+        if packet.len() > MAX_MESSAGE_SIZE {
+            return Err(ParseError::InvalidHeader {
+                msg: "Packet is larger than 65535 bytes".to_string(),
+            });
+        }
         let mut buffer = DNSPacketBuffer::new(packet);
         let header = DNSHeader::parse(&mut buffer)?;
 
@@ -94,21 +194,35 @@ impl DNSPacket {
             DNSResourceRecord::parse_multiple(header.answers_count as usize, &mut buffer)?;
         let authorities: Vec<DNSResourceRecord> =
             DNSResourceRecord::parse_multiple(header.authorities_count as usize, &mut buffer)?;
-        let additionals: Vec<DNSResourceRecord> =
+        let mut additionals: Vec<DNSResourceRecord> =
             DNSResourceRecord::parse_multiple(header.additionals_count as usize, &mut buffer)?;
 
+        let mut edns = None;
+        for additional in additionals.iter() {
+            if let DNSRecord::OPT(record) = &additional.data {
+                if edns.is_some() {
+                    return Err(ParseError::InvalidResourceRecord {
+                        msg: "Invalid OPT record: more than one OPT record".to_string(),
+                    });
+                }
+                edns = Some(record.clone());
+            }
+        }
+        additionals.retain(|additional| !matches!(additional.data, DNSRecord::OPT(_)));
+
         Ok(Self {
             header,
             questions,
             answers,
             authorities,
             additionals,
+            edns,
         })
     }
 
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), String> {
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
         // writing the header
-        self.header.write_to_buffer(buffer);
+        self.header.write_to_buffer(buffer)?;
 
         // writing the questions
         for question in self.questions.iter() {
@@ -130,28 +244,54 @@ impl DNSPacket {
             additional.write_to_buffer(buffer)?;
         }
 
+        // the OPT record goes last in the additionals
+        if let Some(edns) = &self.edns {
+            edns.to_resource_record().write_to_buffer(buffer)?;
+        }
+
         Ok(())
     }
 
-    pub fn contains_question(&self, domain: Vec<DNSLabel>) -> bool {
-        let mut found_match = false;
-        let domain_size = domain.len();
+    /// writes the packet into a new buffer and returns the message
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError> {
+        let mut buffer = DNSPacketBuffer::empty();
+        self.write_to_buffer(&mut buffer)?;
+        Ok(buffer.response_bytes().to_vec())
+    }
 
-        for question_check in &self.questions {
-            if question_check.qname.len() != domain_size {
-                continue;
-            }
+    /// writes the packet for a datagram transport that can carry at
+    /// most `max_size` bytes. When the full packet does not fit, only
+    /// the header and questions are sent with the TC flag set, so the
+    /// client knows to retry over TCP.
+    pub fn to_udp_bytes(&self, max_size: usize) -> Result<Vec<u8>, WriteError> {
+        let mut buffer = DNSPacketBuffer::with_max_size(max_size);
+        match self.write_to_buffer(&mut buffer) {
+            Ok(_) => return Ok(buffer.response_bytes().to_vec()),
+            Err(WriteError::BufferFull { .. }) => {}
+            Err(e) => return Err(e),
+        }
 
-            if contains_domain(&question_check.qname, &domain, domain_size) {
-                found_match = true;
-            }
+        let mut truncated = DNSPacket::new();
+        truncated.header = self.header.clone();
+        truncated.header.flags.tc = true;
+        truncated.header.answers_count = 0;
+        truncated.header.authorities_count = 0;
+        truncated.header.additionals_count = self.edns.is_some() as u16;
+        truncated.questions = self.questions.clone();
+        truncated.edns = self.edns.clone();
 
-            if found_match {
-                break;
-            }
-        }
+        let mut buffer = DNSPacketBuffer::with_max_size(max_size);
+        truncated.write_to_buffer(&mut buffer)?;
+        Ok(buffer.response_bytes().to_vec())
+    }
 
-        return found_match;
+    pub fn contains_question(&self, domain: Vec<DNSLabel>) -> bool {
+        let Ok(domain) = Name::from_labels(&domain) else {
+            return false;
+        };
+        self.questions
+            .iter()
+            .any(|question| Name::from_labels(&question.qname).is_ok_and(|name| name == domain))
     }
 
     pub fn contains_resource_record(
@@ -159,28 +299,92 @@ impl DNSPacket {
         domain: Vec<DNSLabel>,
         rrtype: PacketRecordType,
     ) -> Option<DNSResourceRecord> {
-        let mut found_match = false;
-        let mut result = None::<DNSResourceRecord>;
-        let domain_size = domain.len();
-
+        let domain = Name::from_labels(&domain).ok()?;
         let check_rrs = match rrtype {
             PacketRecordType::Answer => &self.answers,
             PacketRecordType::Authority => &self.authorities,
             PacketRecordType::Additional => &self.additionals,
         };
 
-        for check in check_rrs {
-            if contains_domain(&check.name, &domain, domain_size) {
-                found_match = true;
-                result = Some(check.clone());
+        check_rrs
+            .iter()
+            .find(|check| Name::from_labels(&check.name).is_ok_and(|name| name == domain))
+            .cloned()
+    }
+}
+
+/// the packet laid out like the output of `dig`
+impl fmt::Display for DNSPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = &self.header.flags;
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            opcode_name(flags.opcode),
+            rcode_name(self.rcode()),
+            self.header.id
+        )?;
+
+        let names = [
+            (flags.qr, "qr"),
+            (flags.aa, "aa"),
+            (flags.tc, "tc"),
+            (flags.rd, "rd"),
+            (flags.ra, "ra"),
+        ];
+        let set: Vec<&str> = names
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| *name)
+            .collect();
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            set.join(" "),
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len() + self.edns.is_some() as usize
+        )?;
+
+        if let Some(edns) = &self.edns {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            let flags = if edns.dnssec_ok { " do" } else { "" };
+            writeln!(
+                f,
+                "; EDNS: version: {}, flags:{}; udp: {}",
+                edns.version, flags, edns.udp_payload_size
+            )?;
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in self.questions.iter() {
+                writeln!(f, ";{}", question)?;
             }
+        }
 
-            if found_match {
-                break;
+        let sections = [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ];
+        for (name, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", name)?;
+            for record in records.iter() {
+                writeln!(f, "{}", record)?;
             }
         }
+        Ok(())
+    }
+}
 
-        result
+impl Default for DNSPacket {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
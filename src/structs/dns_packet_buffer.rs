@@ -1,88 +1,157 @@
-use super::error::ParseError;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
+use super::error::{ParseError, WriteError};
+
+/// One label of a name, the raw bytes it has on the wire. Labels
+/// keep their case, but compare and hash without regard to it.
 #[derive(Debug, Clone)]
 pub struct DNSLabel {
-    pub value: String,
-    pub offset: Option<usize>,
+    pub value: Vec<u8>,
+}
+
+impl PartialEq for DNSLabel {
+    fn eq(&self, other: &Self) -> bool {
+        self.value.eq_ignore_ascii_case(&other.value)
+    }
+}
+
+impl Eq for DNSLabel {}
+
+impl Hash for DNSLabel {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.value.len());
+        for byte in self.value.iter() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+    }
 }
 
+/// The largest message DNS can carry, limited by the two byte
+/// length prefix used on TCP.
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+/// The largest message a plain UDP response may contain.
+pub const UDP_MESSAGE_SIZE: usize = 512;
+/// The longest a single label can be
+pub const MAX_LABEL_LENGTH: usize = 63;
+/// The longest a name can be on the wire, including
+/// the length bytes and the terminating null byte
+pub const MAX_NAME_LENGTH: usize = 255;
+/// The highest offset a 14 bit compression pointer can address
+pub const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
 pub struct DNSPacketBuffer {
-    pub buffer: [u8; 512],
+    pub buffer: Vec<u8>,
     pub offset: usize,
-    pub domains: Vec<Vec<DNSLabel>>,
+    /// the names written so far, for compression. Maps every
+    /// suffix of a written name, as spelled, to its offset
+    pub domains: HashMap<Vec<Vec<u8>>, usize>,
+    /// the number of bytes a written message may take up.
+    /// The buffer itself grows as needed
+    pub max_size: usize,
 }
 
 impl DNSPacketBuffer {
-    pub fn new(buffer: [u8; 512]) -> Self {
+    /// creates a buffer to read the given message from
+    pub fn new(buffer: &[u8]) -> Self {
         Self {
-            buffer,
+            buffer: buffer.to_vec(),
             offset: 0,
-            domains: Vec::new(),
+            domains: HashMap::new(),
+            max_size: MAX_MESSAGE_SIZE,
         }
     }
 
-    pub fn small(buffer: &[u8]) -> Self {
-        let mut nbuffer = [0u8; 512];
-        for i in 0..buffer.len() {
-            nbuffer[i] = buffer[i];
-        }
+    /// creates an empty buffer to write a message into
+    pub fn empty() -> Self {
+        Self::with_max_size(MAX_MESSAGE_SIZE)
+    }
 
+    /// creates an empty buffer for a message that may not grow
+    /// beyond `max_size` bytes, e.g. 512 for plain UDP
+    pub fn with_max_size(max_size: usize) -> Self {
         Self {
-            buffer: nbuffer,
+            buffer: Vec::new(),
             offset: 0,
-            domains: Vec::new(),
+            domains: HashMap::new(),
+            max_size: max_size.min(MAX_MESSAGE_SIZE),
         }
     }
 
-    pub fn next_chunk(&mut self, size: usize) -> &[u8] {
+    /// makes sure `size` more bytes can be read from the current offset
+    fn check_read(&self, size: usize) -> Result<(), ParseError> {
+        if self.remaining_bytes(size) {
+            return Err(ParseError::Truncated {
+                offset: self.offset,
+                needed: size,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn next_chunk(&mut self, size: usize) -> Result<&[u8], ParseError> {
+        self.check_read(size)?;
         let val = &self.buffer[self.offset..self.offset + size];
         self.offset += size;
-        val
+        Ok(val)
     }
 
-    pub fn read_u16(&mut self) -> u16 {
+    pub fn read_u8(&mut self) -> Result<u8, ParseError> {
+        self.check_read(1)?;
+        let val = self.buffer[self.offset];
+        self.offset += 1;
+        Ok(val)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ParseError> {
+        self.check_read(2)?;
         let val = ((self.buffer[self.offset] as u16) << 8) | (self.buffer[self.offset + 1] as u16);
         self.offset += 2;
-        val
+        Ok(val)
     }
 
-    pub fn read_u32(&mut self) -> u32 {
+    pub fn read_u32(&mut self) -> Result<u32, ParseError> {
+        self.check_read(4)?;
         let val = ((self.buffer[self.offset] as u32) << 24)
             | ((self.buffer[self.offset + 1] as u32) << 16)
             | ((self.buffer[self.offset + 2] as u32) << 8)
             | (self.buffer[self.offset + 3] as u32);
         self.offset += 4;
-        val
+        Ok(val)
     }
 
-    pub fn write_u8(&mut self, val: u8) {
-        self.buffer[self.offset] = val;
-        self.offset += 1;
+    pub fn write_u8(&mut self, val: u8) -> Result<(), WriteError> {
+        self.write_bytes(&[val])
     }
 
-    pub fn write_u16(&mut self, val: u16) {
-        self.buffer[self.offset] = (val >> 8) as u8;
-        self.offset += 1;
-        self.buffer[self.offset] = (val & 0x00FF) as u8;
-        self.offset += 1;
+    pub fn write_u16(&mut self, val: u16) -> Result<(), WriteError> {
+        self.write_bytes(&val.to_be_bytes())
     }
 
-    pub fn write_u32(&mut self, val: u32) {
-        self.buffer[self.offset] = (val >> 24) as u8;
-        self.offset += 1;
-        self.buffer[self.offset] = (val >> 16) as u8;
-        self.offset += 1;
-        self.buffer[self.offset] = (val >> 8) as u8;
-        self.offset += 1;
-        self.buffer[self.offset] = (val & 0x0000_00FF) as u8;
-        self.offset += 1;
+    pub fn write_u32(&mut self, val: u32) -> Result<(), WriteError> {
+        self.write_bytes(&val.to_be_bytes())
     }
 
-    pub fn write_bytes(&mut self, bytes: &[u8]) {
-        for i in 0..bytes.len() {
-            self.buffer[self.offset + i] = bytes[i].clone();
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), WriteError> {
+        let end = self.offset + bytes.len();
+        if end > self.max_size {
+            return Err(WriteError::BufferFull {
+                max_size: self.max_size,
+            });
         }
-        self.offset += bytes.len();
+        if end > self.buffer.len() {
+            self.buffer.resize(end, 0);
+        }
+        self.buffer[self.offset..end].copy_from_slice(bytes);
+        self.offset = end;
+        Ok(())
+    }
+
+    /// overwrites a u16 at an already written position without
+    /// moving the offset, used to fill in lengths after the fact
+    pub fn set_u16(&mut self, position: usize, val: u16) {
+        self.buffer[position] = (val >> 8) as u8;
+        self.buffer[position + 1] = (val & 0x00FF) as u8;
     }
 
     /// this requires the full packet to be passed
@@ -90,159 +159,185 @@ impl DNSPacketBuffer {
     pub fn parse_labels(&mut self) -> Result<Vec<DNSLabel>, ParseError> {
         let mut labels: Vec<DNSLabel> = vec![];
 
+        let name_offset = self.offset;
         let max_pointers: usize = 10;
         let mut is_pointer: bool = false;
         let mut pointer_count: usize = 0;
+        // the length of the name as it would be written out
+        // uncompressed, counting the final null byte
+        let mut name_length: usize = 1;
 
         // the offset before jumping
         let mut jump_prev_offset: usize = 0;
-        while self.buffer[self.offset] != 0 {
+        loop {
+            let label_offset = self.offset;
+            let label_length = self.read_u8()?;
+            if label_length == 0 {
+                break;
+            }
+
             // jump
-            if (self.buffer[self.offset] & 0xC0) == 0xC0 {
-                if pointer_count > max_pointers {
-                    return Err(ParseError::InvalidQuestion {
-                        msg: "Maximum pointers exceeded. 
-                              To avoid an overflow, the maximum number of pointers is 10"
-                            .to_string(),
+            if (label_length & 0xC0) == 0xC0 {
+                if pointer_count >= max_pointers {
+                    return Err(ParseError::PointerLoop {
+                        offset: label_offset,
+                    });
+                }
+                self.offset = label_offset;
+                let pointer = (self.read_u16()? & 0x3FFF) as usize;
+                // pointers may only refer to data that came before,
+                // which rules out loops
+                if pointer >= label_offset {
+                    return Err(ParseError::PointerLoop {
+                        offset: label_offset,
                     });
                 }
+
+                // only the first jump decides where the name ends
+                if !is_pointer {
+                    jump_prev_offset = label_offset;
+                }
                 is_pointer = true;
                 pointer_count += 1;
-                jump_prev_offset = self.offset;
 
-                let mut pointer = self.read_u16();
-                pointer = (pointer & 0x3FFF) as u16;
-                self.offset = pointer as usize;
+                self.offset = pointer;
                 continue;
             }
 
-            let mut current_label = String::new();
-
-            let label_length = &self.buffer[self.offset];
-            self.offset += 1;
-
-            let label = &self.buffer[self.offset..(self.offset + *label_length as usize)];
-            self.offset += *label_length as usize;
+            if label_length as usize > MAX_LABEL_LENGTH {
+                return Err(ParseError::LabelTooLong {
+                    offset: label_offset,
+                    length: label_length,
+                });
+            }
 
-            for item in label {
-                current_label.push(*item as char);
+            name_length += label_length as usize + 1;
+            if name_length > MAX_NAME_LENGTH {
+                return Err(ParseError::NameTooLong {
+                    offset: name_offset,
+                });
             }
 
-            // the offset must be none for future purposes
+            let label = self.next_chunk(label_length as usize)?;
             labels.push(DNSLabel {
-                value: current_label,
-                offset: None,
+                value: label.to_vec(),
             });
         }
 
         if is_pointer {
-            self.offset = jump_prev_offset;
+            // skip the two byte pointer
+            self.offset = jump_prev_offset + 2;
         }
 
-        // now the current offset is a null byte
-        // so we skip a byte
-        // if jumps we are skipping the pointer
-        self.offset += 1;
-
         Ok(labels)
     }
 
-    pub fn push_labels(&mut self, labels: &Vec<DNSLabel>) -> Result<(), String> {
-        let mut pointer_offset = 0;
-        let mut pointer_accuracy = 0;
-
-        for domain_index in 0..self.domains.len() {
-            let domain = &self.domains[domain_index];
-
-            let mut new_pointer_offset = 0;
-            let mut new_pointer_accuracy = 0;
-
-            let mut i = domain.len() - 1;
-            for label in labels.iter().rev() {
-                if i >= domain.len() {
-                    break;
-                }
-                // the main compression logic
-                let clabel = domain[i].clone();
-                match clabel.offset {
-                    Some(offset) => {
-                        if clabel.value == label.value {
-                            new_pointer_accuracy += 1;
-                            new_pointer_offset = offset;
-                        }
-                    }
-                    None => {}
-                }
+    /// writes the labels without compression. The name can
+    /// still be pointed to by names written later on
+    pub fn write_labels(&mut self, labels: &[DNSLabel]) -> Result<(), WriteError> {
+        Self::check_labels(labels)?;
+        self.write_uncompressed(labels, labels.len())?;
+        self.write_u8(0)
+    }
 
-                if i > 0 {
-                    i -= 1;
-                } else {
-                    break;
-                }
+    /// writes the first `count` labels one by one, remembering
+    /// where each suffix of the name starts
+    fn write_uncompressed(&mut self, labels: &[DNSLabel], count: usize) -> Result<(), WriteError> {
+        for i in 0..count {
+            if self.offset <= MAX_POINTER_OFFSET {
+                self.domains
+                    .entry(Self::compression_key(&labels[i..]))
+                    .or_insert(self.offset);
             }
 
-            if new_pointer_accuracy > pointer_accuracy {
-                pointer_accuracy = new_pointer_accuracy;
-                pointer_offset = new_pointer_offset;
-            }
+            let label = &labels[i].value;
+            self.write_u8(label.len() as u8)?;
+            self.write_bytes(label)?;
         }
+        Ok(())
+    }
 
-        // we found pointers for the labels
-        if pointer_accuracy > 0 {
-            // found full domain
-            if pointer_accuracy == labels.len() {
-                if pointer_offset > 63 {
-                    // :( pointer cannot be made
-                    return Err("label could not be made. Greater than 63 characters".to_string());
-                }
-                let mut pointer = 0xC000;
-                pointer |= pointer_offset as u16;
-                self.write_u16(pointer);
-            } else if pointer_accuracy < labels.len() {
-                // not using this condition for future pointer
-                // to avoid nested pointers
-                for i in 0..(labels.len() - pointer_accuracy) {
-                    let label = &labels[i];
-                    self.write_u8(label.value.len() as u8);
-                    self.write_bytes(label.value.as_bytes());
-                }
-                let pointer_bytes = 0xC000 | (pointer_offset as u16);
-                self.write_u16(pointer_bytes);
-            } else {
-                // this should not exist
+    /// names compare case-insensitively, so they are looked up lowercased
+    fn compression_key(labels: &[DNSLabel]) -> Vec<Vec<u8>> {
+        labels
+            .iter()
+            .map(|label| label.value.to_ascii_lowercase())
+            .collect()
+    }
+
+    /// checks the labels before a name is written: an empty label
+    /// would end the name early, and the length limits
+    fn check_labels(labels: &[DNSLabel]) -> Result<(), WriteError> {
+        let mut name_length = 1;
+        for label in labels.iter() {
+            let length = label.value.len();
+            if length == 0 {
+                return Err(WriteError::EmptyLabel {
+                    name: Self::name_text(labels),
+                });
             }
-        } else {
-            let mut new_domain: Vec<DNSLabel> = vec![];
-            // no pointers found
-            for label in labels {
-                // TODO: optimise this
-                let mut new_label = label.clone();
-                new_label.offset = Some(self.offset);
-                new_domain.push(new_label);
-
-                self.write_u8(label.value.len() as u8);
-                self.write_bytes(label.value.as_bytes());
+            if length > MAX_LABEL_LENGTH {
+                return Err(WriteError::LabelTooLong {
+                    label: String::from_utf8_lossy(&label.value).into_owned(),
+                });
             }
+            name_length += length + 1;
+        }
 
-            self.domains.push(new_domain);
+        if name_length > MAX_NAME_LENGTH {
+            return Err(WriteError::NameTooLong {
+                name: Self::name_text(labels),
+            });
         }
+        Ok(())
+    }
 
-        // end with empty bytes
-        if pointer_accuracy <= 0 {
-            self.write_u8(0);
+    /// the name for error messages, its labels joined with dots
+    fn name_text(labels: &[DNSLabel]) -> String {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|label| String::from_utf8_lossy(&label.value).into_owned())
+            .collect();
+        labels.join(".")
+    }
+
+    /// writes a name using RFC 1035 message compression. The longest
+    /// suffix of the name that was already written is replaced by a
+    /// pointer to it, the labels in front of it are written out.
+    pub fn push_labels(&mut self, labels: &[DNSLabel]) -> Result<(), WriteError> {
+        Self::check_labels(labels)?;
+
+        for i in 0..labels.len() {
+            let key = Self::compression_key(&labels[i..]);
+            if let Some(&pointer_offset) = self.domains.get(&key) {
+                self.write_uncompressed(labels, i)?;
+                return self.write_u16(0xC000 | pointer_offset as u16);
+            }
         }
 
-        Ok(())
+        // no pointers found
+        self.write_uncompressed(labels, labels.len())?;
+        // end with empty bytes
+        self.write_u8(0)
     }
 
-    pub fn read_character_string(&mut self) -> Result<String, ParseError> {
-        let mut string = String::new();
+    /// a <character-string> is a single length byte followed
+    /// by that many bytes of data, which need not be text
+    pub fn read_character_string(&mut self) -> Result<Vec<u8>, ParseError> {
+        let length = self.read_u8()? as usize;
+        Ok(self.next_chunk(length)?.to_vec())
+    }
 
-        Ok(string)
+    pub fn write_character_string(&mut self, data: &[u8]) -> Result<(), WriteError> {
+        if data.len() > 255 {
+            return Err(WriteError::CharacterStringTooLong { length: data.len() });
+        }
+        self.write_u8(data.len() as u8)?;
+        self.write_bytes(data)
     }
 
     pub fn remaining_bytes(&self, size: usize) -> bool {
-        self.offset + size >= self.buffer.len()
+        self.offset + size > self.buffer.len()
     }
 
     pub fn response_bytes(&self) -> &[u8] {
@@ -250,14 +345,6 @@ impl DNSPacketBuffer {
     }
 
     pub fn base_packet(&self) -> &[u8] {
-        let mut offset = 0;
-
-        for i in 0..self.buffer.len() {
-            if self.buffer[i] != 0 {
-                offset = i;
-            }
-        }
-
-        &self.buffer[0..(offset + 1)]
+        &self.buffer
     }
 }
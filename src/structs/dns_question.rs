@@ -1,9 +1,13 @@
+use std::fmt;
+
 use super::{
     dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
-    error::ParseError,
+    error::{NameError, ParseError, WriteError},
+    name::Name,
+    presentation::{class_name, type_name, write_name},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSQuestion {
     /// The name of the domain represented in the form of
     /// labels (subdomains) seperated by dots. Each label
@@ -72,50 +76,50 @@ impl DNSQuestion {
         count: usize,
         buffer: &mut DNSPacketBuffer,
     ) -> Result<Vec<DNSQuestion>, ParseError> {
-        let mut i = 0; // we are dealing with question cout
         let mut questions: Vec<DNSQuestion> = vec![];
 
-        loop {
-            if i >= count as usize {
-                break;
-            }
-
+        for _ in 0..count {
             let labels = buffer.parse_labels()?;
-            let q_type = buffer.read_u16();
-            let q_class = buffer.read_u16();
+            let q_type = buffer.read_u16()?;
+            let q_class = buffer.read_u16()?;
 
             questions.push(DNSQuestion {
                 qname: labels,
                 qtype: q_type,
                 qclass: q_class,
             });
-
-            i = i + 1;
         }
 
         Ok(questions)
     }
 
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), String> {
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
         buffer.push_labels(&self.qname)?;
-        buffer.write_u16(self.qtype);
-        buffer.write_u16(self.qclass);
+        buffer.write_u16(self.qtype)?;
+        buffer.write_u16(self.qclass)?;
 
         Ok(())
     }
 
-    pub fn from_simple_string(domain: &str, q_type: u16, q_class: u16) -> Self {
-        let labels_raw: Vec<&str> = domain.split(".").collect();
-
-        let lables = labels_raw.iter().map(|m| DNSLabel {
-            value: m.to_string(),
-            offset: None,
-        }).collect();
-
-        return Self {
-            qname: lables,
+    /// a question for a name in presentation format, like
+    /// `www.example.com`. The name is checked as [`Name`] checks it
+    pub fn from_simple_string(domain: &str, q_type: u16, q_class: u16) -> Result<Self, NameError> {
+        Ok(Self {
+            qname: domain.parse::<Name>()?.to_labels(),
             qclass: q_class,
             qtype: q_type,
-        };
+        })
+    }
+}
+
+impl fmt::Display for DNSQuestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.qname)?;
+        write!(
+            f,
+            "\t{}\t{}",
+            class_name(self.qclass),
+            type_name(self.qtype)
+        )
     }
 }
@@ -1,20 +1,18 @@
+use std::fmt;
+
 use super::{
     dns_packet_buffer::DNSPacketBuffer,
-    error::ParseError,
+    error::{ParseError, WriteError},
+    presentation::write_generic,
     record_types::{
-        a_record::ARecord,
-        aaaa_record::AAAARecord,
-        cname_record::CNAMERecord,
-        mx_record::MXRecord,
-        ns_record::{NSRecord, PTRRecord},
-        soa_record::SOARecord,
-        srv_record::SRVRecord,
-        txt_record::TXTRecord,
+        a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord,
+        dname_record::DNAMERecord, mx_record::MXRecord, ns_record::NSRecord, opt_record::OPTRecord,
+        ptr_record::PTRRecord, soa_record::SOARecord, srv_record::SRVRecord, txt_record::TXTRecord,
     },
 };
 
 // Define an enum for DNS record types
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DNSRecord {
     A(ARecord),
     AAAA(AAAARecord),
@@ -25,6 +23,8 @@ pub enum DNSRecord {
     NS(NSRecord),
     PTR(PTRRecord),
     SOA(SOARecord),
+    DNAME(DNAMERecord),
+    OPT(OPTRecord),
     Unknown {
         record_type: u16,
         data_length: usize,
@@ -33,92 +33,99 @@ pub enum DNSRecord {
 }
 
 impl DNSRecord {
+    /// the type number for a mnemonic like `AAAA`, or the
+    /// `TYPE1234` form used for types without one
+    pub fn type_from_name(name: &str) -> Option<u16> {
+        let name = name.to_ascii_uppercase();
+        let rtype = match name.as_str() {
+            "A" => ARecord::TYPE,
+            "AAAA" => AAAARecord::TYPE,
+            "CNAME" => CNAMERecord::TYPE,
+            "MX" => MXRecord::TYPE,
+            "TXT" => TXTRecord::TYPE,
+            "SRV" => SRVRecord::TYPE,
+            "NS" => NSRecord::TYPE,
+            "PTR" => PTRRecord::TYPE,
+            "SOA" => SOARecord::TYPE,
+            "DNAME" => DNAMERecord::TYPE,
+            "OPT" => OPTRecord::TYPE,
+            _ => return name.strip_prefix("TYPE")?.parse().ok(),
+        };
+        Some(rtype)
+    }
+
+    /// The buffer has to be positioned at the start of the record
+    /// data. Names inside the data can be compressed, so the whole
+    /// packet needs to be available in the buffer.
     pub fn parse_dns_record_data(
         record_type: u16,
         data_length: usize,
-        data: &[u8],
+        buffer: &mut DNSPacketBuffer,
     ) -> Result<DNSRecord, ParseError> {
-        if data.len() < data_length as usize {
-            return Err(ParseError::InvalidResourceRecord {
-                msg: "Invalid DNS record: Data length does not match actual data".to_string(),
+        if buffer.remaining_bytes(data_length) {
+            return Err(ParseError::Truncated {
+                offset: buffer.offset,
+                needed: data_length,
             });
         }
 
         match record_type {
             // Handle known record types
-            1 => Ok(DNSRecord::A(ARecord::parse(data_length as usize, &data)?)),
-            28 => Ok(DNSRecord::AAAA(AAAARecord::parse(
-                data_length as usize,
-                &data,
-            )?)),
-            // Handle other known record types here...
-            5 => Ok(DNSRecord::CNAME(CNAMERecord::parse(
-                data_length as usize,
-                &data,
-            )?)),
-            15 => {
-                // Parse MX record
-                Ok(DNSRecord::MX(MXRecord::parse(data_length as usize, &data)?))
-            }
-            16 => Ok(DNSRecord::TXT(TXTRecord::parse(
-                data_length as usize,
-                &data,
-            )?)),
-            33 => Ok(DNSRecord::SRV(SRVRecord::parse(
-                data_length as usize,
-                &data,
-            )?)),
-            2 => Ok(DNSRecord::NS(NSRecord::parse(data_length as usize, &data)?)),
-            12 => Ok(DNSRecord::PTR(PTRRecord::parse(
-                data_length as usize,
-                &data,
-            )?)),
-            6 => Ok(DNSRecord::SOA(SOARecord::parse(
-                data_length as usize,
-                &data,
-            )?)),
+            ARecord::TYPE => Ok(DNSRecord::A(ARecord::parse(data_length, buffer)?)),
+            AAAARecord::TYPE => Ok(DNSRecord::AAAA(AAAARecord::parse(data_length, buffer)?)),
+            CNAMERecord::TYPE => Ok(DNSRecord::CNAME(CNAMERecord::parse(data_length, buffer)?)),
+            MXRecord::TYPE => Ok(DNSRecord::MX(MXRecord::parse(data_length, buffer)?)),
+            TXTRecord::TYPE => Ok(DNSRecord::TXT(TXTRecord::parse(data_length, buffer)?)),
+            SRVRecord::TYPE => Ok(DNSRecord::SRV(SRVRecord::parse(data_length, buffer)?)),
+            NSRecord::TYPE => Ok(DNSRecord::NS(NSRecord::parse(data_length, buffer)?)),
+            PTRRecord::TYPE => Ok(DNSRecord::PTR(PTRRecord::parse(data_length, buffer)?)),
+            SOARecord::TYPE => Ok(DNSRecord::SOA(SOARecord::parse(data_length, buffer)?)),
+            DNAMERecord::TYPE => Ok(DNSRecord::DNAME(DNAMERecord::parse(data_length, buffer)?)),
+            OPTRecord::TYPE => Ok(DNSRecord::OPT(OPTRecord::parse(data_length, buffer)?)),
             // For unknown record types, store raw data
             _ => Ok(DNSRecord::Unknown {
                 record_type,
                 data_length,
-                raw_data: data.to_vec(),
+                raw_data: buffer.next_chunk(data_length)?.to_vec(),
             }),
         }
     }
-    pub fn write_to_bytes(&self, buffer: &mut DNSPacketBuffer) {
+
+    pub fn write_to_bytes(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
         match self {
-            DNSRecord::A(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::AAAA(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::CNAME(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::MX(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::TXT(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::SRV(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::NS(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::PTR(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::SOA(record) => {
-                record.write_to_buffer(buffer);
-            }
-            DNSRecord::Unknown {
-                record_type,
-                data_length,
-                raw_data,
-            } => todo!(),
+            DNSRecord::A(record) => record.write_to_buffer(buffer),
+            DNSRecord::AAAA(record) => record.write_to_buffer(buffer),
+            DNSRecord::CNAME(record) => record.write_to_buffer(buffer),
+            DNSRecord::MX(record) => record.write_to_buffer(buffer),
+            DNSRecord::TXT(record) => record.write_to_buffer(buffer),
+            DNSRecord::SRV(record) => record.write_to_buffer(buffer),
+            DNSRecord::NS(record) => record.write_to_buffer(buffer),
+            DNSRecord::PTR(record) => record.write_to_buffer(buffer),
+            DNSRecord::SOA(record) => record.write_to_buffer(buffer),
+            DNSRecord::DNAME(record) => record.write_to_buffer(buffer),
+            DNSRecord::OPT(record) => record.write_to_buffer(buffer),
+            DNSRecord::Unknown { raw_data, .. } => buffer.write_bytes(raw_data),
+        }
+    }
+}
+
+/// the record data in presentation format, as it
+/// appears after the type in a zone file
+impl fmt::Display for DNSRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DNSRecord::A(record) => record.fmt(f),
+            DNSRecord::AAAA(record) => record.fmt(f),
+            DNSRecord::CNAME(record) => record.fmt(f),
+            DNSRecord::MX(record) => record.fmt(f),
+            DNSRecord::TXT(record) => record.fmt(f),
+            DNSRecord::SRV(record) => record.fmt(f),
+            DNSRecord::NS(record) => record.fmt(f),
+            DNSRecord::PTR(record) => record.fmt(f),
+            DNSRecord::SOA(record) => record.fmt(f),
+            DNSRecord::DNAME(record) => record.fmt(f),
+            DNSRecord::OPT(record) => record.fmt(f),
+            DNSRecord::Unknown { raw_data, .. } => write_generic(f, raw_data),
         }
     }
 }
@@ -1,9 +1,14 @@
+use std::fmt;
+
 use super::{
     dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
-    error::ParseError, dns_record_types::DNSRecord, record_types::a_record::ARecord,
+    dns_record_types::DNSRecord,
+    error::{ParseError, WriteError},
+    presentation::{class_name, type_name, write_name},
+    record_types::a_record::ARecord,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSResourceRecord {
     /// domain name to which this RR applies
     /// in the form of labels ["www", "example", "com"]
@@ -64,10 +69,9 @@ impl DNSResourceRecord {
         }
     }
 
-    pub fn push_name_label(mut self, name: String) -> Self  {
+    pub fn push_name_label(mut self, name: String) -> Self {
         self.name.push(DNSLabel {
-            value: name,
-            offset: None
+            value: name.into_bytes(),
         });
         self
     }
@@ -99,16 +103,23 @@ impl DNSResourceRecord {
 
     pub fn parse(buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         let labels = buffer.parse_labels()?;
-        let r_type = buffer.read_u16();
-        let r_class = buffer.read_u16();
-        let ttl = buffer.read_u32();
-        let rdlength = buffer.read_u16();
+        let r_type = buffer.read_u16()?;
+        let r_class = buffer.read_u16()?;
+        let ttl = buffer.read_u32()?;
+        let rdlength = buffer.read_u16()?;
 
-        let data = DNSRecord::parse_dns_record_data(
-            r_type,
-            rdlength as usize,
-            buffer.next_chunk(rdlength as usize),
-        )?;
+        let data_start = buffer.offset;
+        let mut data = DNSRecord::parse_dns_record_data(r_type, rdlength as usize, buffer)?;
+        if let DNSRecord::OPT(record) = &mut data {
+            record.set_header_fields(r_class, ttl);
+        }
+        if buffer.offset > data_start + rdlength as usize {
+            return Err(ParseError::InvalidResourceRecord {
+                msg: "Invalid DNS record: Data is longer than the rdlength".to_string(),
+            });
+        }
+        // always continue after the rdata, whatever the record parser consumed
+        buffer.offset = data_start + rdlength as usize;
 
         Ok(Self {
             name: labels,
@@ -131,14 +142,42 @@ impl DNSResourceRecord {
         Ok(records)
     }
 
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), String> {
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
         buffer.push_labels(&self.name)?;
-        buffer.write_u16(self.rtype);
-        buffer.write_u16(self.class);
-        buffer.write_u32(self.ttl);
-        buffer.write_u16(self.rdlength);
-        self.data.write_to_bytes(buffer);
+        buffer.write_u16(self.rtype)?;
+        buffer.write_u16(self.class)?;
+        buffer.write_u32(self.ttl)?;
+
+        // the length of compressed data is only known once it
+        // is written, so the rdlength is filled in afterwards
+        let rdlength_offset = buffer.offset;
+        buffer.write_u16(0)?;
+        self.data.write_to_bytes(buffer)?;
+        let rdlength = buffer.offset - rdlength_offset - 2;
+        buffer.set_u16(rdlength_offset, rdlength as u16);
 
         Ok(())
     }
 }
+
+impl Default for DNSResourceRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// one line of a zone file, like
+/// `www.example.com.\t300\tIN\tA\t192.0.2.1`
+impl fmt::Display for DNSResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.name)?;
+        write!(
+            f,
+            "\t{}\t{}\t{}\t{}",
+            self.ttl,
+            class_name(self.class),
+            type_name(self.rtype),
+            self.data
+        )
+    }
+}
@@ -1,7 +1,128 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseError {
     // InvalidPacket { msg: String },
-    InvalidHeader { msg: String },
-    InvalidQuestion { msg: String },
-    InvalidResourceRecord { msg: String },
+    InvalidHeader {
+        msg: String,
+    },
+    InvalidQuestion {
+        msg: String,
+    },
+    InvalidResourceRecord {
+        msg: String,
+    },
+    /// the packet ended before `needed` bytes could be read at `offset`
+    Truncated {
+        offset: usize,
+        needed: usize,
+    },
+    /// the compression pointers of a name formed a loop,
+    /// pointed forward or were chained too deep
+    PointerLoop {
+        offset: usize,
+    },
+    /// a label length byte used the reserved values 64 to 191
+    LabelTooLong {
+        offset: usize,
+        length: u8,
+    },
+    /// the name took up more than 255 bytes
+    NameTooLong {
+        offset: usize,
+    },
 }
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidHeader { msg }
+            | ParseError::InvalidQuestion { msg }
+            | ParseError::InvalidResourceRecord { msg } => write!(f, "{}", msg),
+            ParseError::Truncated { offset, needed } => {
+                write!(
+                    f,
+                    "packet truncated: {} bytes needed at offset {}",
+                    needed, offset
+                )
+            }
+            ParseError::PointerLoop { offset } => {
+                write!(f, "invalid compression pointer at offset {}", offset)
+            }
+            ParseError::LabelTooLong { offset, length } => {
+                write!(f, "label of length {} at offset {}", length, offset)
+            }
+            ParseError::NameTooLong { offset } => {
+                write!(f, "name at offset {} is longer than 255 bytes", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteError {
+    /// the message would grow beyond `max_size` bytes
+    BufferFull { max_size: usize },
+    /// labels can be at most 63 bytes long
+    LabelTooLong { label: String },
+    /// only the root ends a name with an empty label, which
+    /// is written for it, so the labels can not have any
+    EmptyLabel { name: String },
+    /// names can be at most 255 bytes long on the wire
+    NameTooLong { name: String },
+    /// a <character-string> can be at most 255 bytes long
+    CharacterStringTooLong { length: usize },
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::BufferFull { max_size } => {
+                write!(f, "message does not fit in {} bytes", max_size)
+            }
+            WriteError::LabelTooLong { label } => {
+                write!(f, "label {:?} is longer than 63 bytes", label)
+            }
+            WriteError::EmptyLabel { name } => {
+                write!(f, "name {:?} has an empty label", name)
+            }
+            WriteError::NameTooLong { name } => {
+                write!(f, "name {:?} is longer than 255 bytes", name)
+            }
+            WriteError::CharacterStringTooLong { length } => {
+                write!(f, "character-string of {} bytes is longer than 255", length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// the text is not a domain name, it has an empty
+    /// label or an escape that is cut short
+    Invalid { text: String },
+    /// labels can be at most 63 bytes long
+    LabelTooLong { label: String },
+    /// names can be at most 255 bytes long on the wire
+    NameTooLong { name: String },
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Invalid { text } => write!(f, "{:?} is not a valid domain name", text),
+            NameError::LabelTooLong { label } => {
+                write!(f, "label {:?} is longer than 63 bytes", label)
+            }
+            NameError::NameTooLong { name } => {
+                write!(f, "name {:?} is longer than 255 bytes", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
@@ -0,0 +1,304 @@
+//! Domain names as a type of their own. A [`Name`] is always valid:
+//! no label is empty or longer than 63 bytes and the whole name fits
+//! in 255 bytes on the wire. Labels are raw bytes and keep the case
+//! they were written in, but names compare, hash and sort without
+//! regard to ASCII case.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use super::dns_packet_buffer::{DNSLabel, MAX_LABEL_LENGTH, MAX_NAME_LENGTH};
+use super::error::NameError;
+use super::presentation::{read_escape, write_label};
+
+/// A validated domain name, `www.example.com.` is the labels
+/// `www`, `example` and `com` followed by the root.
+///
+/// ```
+/// use joyboy_dns::Name;
+///
+/// let name: Name = "WWW.Example.com".parse().unwrap();
+/// assert_eq!(name, "www.example.com.".parse().unwrap());
+/// assert_eq!(name.to_string(), "WWW.Example.com.");
+/// assert!(name.is_subdomain_of(&"example.COM".parse().unwrap()));
+/// ```
+#[derive(Clone, Default)]
+pub struct Name {
+    /// from the leftmost label to the one under the root
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    /// the root name `.`
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    /// a name of the given labels, leftmost first
+    pub fn new(labels: Vec<Vec<u8>>) -> Result<Self, NameError> {
+        let name = Self { labels };
+        name.check()?;
+        Ok(name)
+    }
+
+    /// the name the codec labels stand for
+    pub fn from_labels(labels: &[DNSLabel]) -> Result<Self, NameError> {
+        Self::new(labels.iter().map(|label| label.value.clone()).collect())
+    }
+
+    /// the labels for the codec
+    pub fn to_labels(&self) -> Vec<DNSLabel> {
+        self.labels
+            .iter()
+            .map(|label| DNSLabel {
+                value: label.clone(),
+            })
+            .collect()
+    }
+
+    /// reads a name in presentation format. Names ending in a dot
+    /// are absolute, others are relative to `origin`. `.` is the
+    /// root, and characters can be escaped as `\.` or `\DDD`.
+    pub fn parse_relative(text: &str, origin: &Name) -> Result<Self, NameError> {
+        if text == "." {
+            return Ok(Self::root());
+        }
+
+        let invalid = || NameError::Invalid {
+            text: text.to_string(),
+        };
+        let mut labels = vec![];
+        let mut label = vec![];
+        let mut absolute = false;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    if label.is_empty() {
+                        return Err(invalid());
+                    }
+                    labels.push(std::mem::take(&mut label));
+                    absolute = chars.as_str().is_empty();
+                }
+                '\\' => read_escape(&mut chars, &mut label).ok_or_else(invalid)?,
+                _ => label.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
+        if labels.is_empty() {
+            return Err(invalid());
+        }
+
+        if !absolute {
+            labels.extend(origin.labels.iter().cloned());
+        }
+        Self::new(labels)
+    }
+
+    /// the labels, leftmost first
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    /// the number of labels, 0 for the root
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// the bytes the name takes up on the wire, uncompressed
+    pub fn wire_length(&self) -> usize {
+        wire_length(&self.labels)
+    }
+
+    /// the name one label up, none for the root
+    pub fn parent(&self) -> Option<Name> {
+        if self.is_root() {
+            return None;
+        }
+        Some(self.suffix(self.label_count() - 1))
+    }
+
+    /// the name with `label` in front
+    pub fn child(&self, label: &[u8]) -> Result<Name, NameError> {
+        let mut labels = Vec::with_capacity(self.label_count() + 1);
+        labels.push(label.to_vec());
+        labels.extend(self.labels.iter().cloned());
+        Self::new(labels)
+    }
+
+    /// the last `count` labels of the name, all of it when
+    /// the name has fewer
+    pub fn suffix(&self, count: usize) -> Name {
+        let start = self.label_count().saturating_sub(count);
+        Self {
+            labels: self.labels[start..].to_vec(),
+        }
+    }
+
+    /// the name with `origin` appended, making a relative name absolute
+    pub fn append(&self, origin: &Name) -> Result<Name, NameError> {
+        let mut labels = self.labels.clone();
+        labels.extend(origin.labels.iter().cloned());
+        Self::new(labels)
+    }
+
+    /// whether the name is `parent` or a name below it
+    pub fn is_subdomain_of(&self, parent: &Name) -> bool {
+        self.label_count() >= parent.label_count()
+            && self
+                .labels
+                .iter()
+                .rev()
+                .zip(parent.labels.iter().rev())
+                .all(|(label, parent)| label.eq_ignore_ascii_case(parent))
+    }
+
+    /// the labels in front of `parent`, none when
+    /// the name is not in that domain
+    pub fn strip_suffix(&self, parent: &Name) -> Option<Name> {
+        if !self.is_subdomain_of(parent) {
+            return None;
+        }
+        Some(Self {
+            labels: self.labels[..self.label_count() - parent.label_count()].to_vec(),
+        })
+    }
+
+    /// the name with every ASCII letter in lowercase
+    pub fn to_lowercase(&self) -> Name {
+        Self {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    fn check(&self) -> Result<(), NameError> {
+        for label in self.labels.iter() {
+            if label.is_empty() {
+                return Err(NameError::Invalid {
+                    text: self.to_string(),
+                });
+            }
+            if label.len() > MAX_LABEL_LENGTH {
+                return Err(NameError::LabelTooLong {
+                    label: String::from_utf8_lossy(label).into_owned(),
+                });
+            }
+        }
+        if wire_length(&self.labels) > MAX_NAME_LENGTH {
+            return Err(NameError::NameTooLong {
+                name: self.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Name {
+    type Err = NameError;
+
+    /// reads an absolute name, the final dot can be left out
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse_relative(text, &Name::root())
+    }
+}
+
+impl fmt::Display for Name {
+    /// writes the name in presentation format, ending in a dot
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str(".");
+        }
+        for label in self.labels.iter() {
+            write_label(f, label)?;
+            f.write_str(".")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Name").field(&self.to_string()).finish()
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.label_count() == other.label_count()
+            && self
+                .labels
+                .iter()
+                .zip(other.labels.iter())
+                .all(|(label, other)| label.eq_ignore_ascii_case(other))
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.label_count());
+        for label in self.labels.iter() {
+            state.write_usize(label.len());
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    /// the canonical DNSSEC order (RFC 4034 section 6.1): by the
+    /// labels from the root down, each compared as lowercase bytes,
+    /// a name sorting before the names below it
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (label, other) in self.labels.iter().rev().zip(other.labels.iter().rev()) {
+            match lowercase(label).cmp(lowercase(other)) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        self.label_count().cmp(&other.label_count())
+    }
+}
+
+impl TryFrom<&[DNSLabel]> for Name {
+    type Error = NameError;
+
+    fn try_from(labels: &[DNSLabel]) -> Result<Self, Self::Error> {
+        Self::from_labels(labels)
+    }
+}
+
+impl From<&Name> for Vec<DNSLabel> {
+    fn from(name: &Name) -> Self {
+        name.to_labels()
+    }
+}
+
+fn lowercase(label: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    label.iter().map(u8::to_ascii_lowercase)
+}
+
+/// the length bytes and labels, and the null byte ending the name
+fn wire_length(labels: &[Vec<u8>]) -> usize {
+    labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1
+}
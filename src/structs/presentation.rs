@@ -0,0 +1,154 @@
+//! Helpers for writing DNS data in presentation format, the text
+//! form used by zone files and tools like `dig` (RFC 1035 section 5),
+//! and for reading its escapes back.
+
+use std::fmt;
+
+use super::dns_packet_buffer::DNSLabel;
+use super::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord,
+    dname_record::DNAMERecord, mx_record::MXRecord, ns_record::NSRecord, opt_record::OPTRecord,
+    ptr_record::PTRRecord, soa_record::SOARecord, srv_record::SRVRecord, txt_record::TXTRecord,
+};
+
+/// the mnemonic of a record type, or `TYPE1234` for types without one
+pub fn type_name(rtype: u16) -> String {
+    let name = match rtype {
+        ARecord::TYPE => "A",
+        AAAARecord::TYPE => "AAAA",
+        CNAMERecord::TYPE => "CNAME",
+        MXRecord::TYPE => "MX",
+        TXTRecord::TYPE => "TXT",
+        SRVRecord::TYPE => "SRV",
+        NSRecord::TYPE => "NS",
+        PTRRecord::TYPE => "PTR",
+        SOARecord::TYPE => "SOA",
+        DNAMERecord::TYPE => "DNAME",
+        OPTRecord::TYPE => "OPT",
+        255 => "ANY",
+        _ => return format!("TYPE{}", rtype),
+    };
+    name.to_string()
+}
+
+/// the mnemonic of a class, or `CLASS1234` for classes without one
+pub fn class_name(class: u16) -> String {
+    let name = match class {
+        1 => "IN",
+        2 => "CS",
+        3 => "CH",
+        4 => "HS",
+        255 => "ANY",
+        _ => return format!("CLASS{}", class),
+    };
+    name.to_string()
+}
+
+pub fn opcode_name(opcode: u8) -> String {
+    let name = match opcode {
+        0 => "QUERY",
+        1 => "IQUERY",
+        2 => "STATUS",
+        4 => "NOTIFY",
+        5 => "UPDATE",
+        _ => return format!("OPCODE{}", opcode),
+    };
+    name.to_string()
+}
+
+/// the name of a (possibly extended) response code
+pub fn rcode_name(rcode: u16) -> String {
+    let name = match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        OPTRecord::BADVERS => "BADVERS",
+        _ => return format!("RCODE{}", rcode),
+    };
+    name.to_string()
+}
+
+/// writes a name as an absolute domain name, ending in a dot.
+/// Characters with a special meaning in zone files are escaped.
+pub fn write_name(f: &mut fmt::Formatter<'_>, labels: &[DNSLabel]) -> fmt::Result {
+    if labels.is_empty() {
+        return f.write_str(".");
+    }
+    for label in labels.iter() {
+        write_label(f, &label.value)?;
+        f.write_str(".")?;
+    }
+    Ok(())
+}
+
+/// writes the bytes of one label, escaping the ones that are
+/// special in zone files and the ones that are not printable
+pub fn write_label(f: &mut fmt::Formatter<'_>, label: &[u8]) -> fmt::Result {
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                write!(f, "\\{}", byte as char)?
+            }
+            _ if byte.is_ascii_graphic() => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+/// writes a <character-string> in quotes. Printable ASCII
+/// is written as is, every other byte as `\DDD`
+pub fn write_character_string(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &byte in data {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+            // spaces are fine inside quotes
+            b' ' => f.write_str(" ")?,
+            _ if byte.is_ascii_graphic() => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// writes data in the RFC 3597 form `\# <length> <hex>`,
+/// which can stand for the data of any record type
+pub fn write_generic(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    write!(f, "\\# {}", data.len())?;
+    if !data.is_empty() {
+        f.write_str(" ")?;
+        for byte in data {
+            write!(f, "{:02X}", byte)?;
+        }
+    }
+    Ok(())
+}
+
+/// reads what follows a backslash into `data`: `\DDD` is
+/// a byte in decimal, anything else stands for itself
+pub fn read_escape(chars: &mut std::str::Chars, data: &mut Vec<u8>) -> Option<()> {
+    let c = chars.next()?;
+    if !c.is_ascii_digit() {
+        data.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        return Some(());
+    }
+    let digits: String = [Some(c), chars.next(), chars.next()]
+        .into_iter()
+        .collect::<Option<String>>()?;
+    data.push(digits.parse().ok()?);
+    Some(())
+}
+
+/// a name written with [`write_name`], for use with `format!`
+pub struct NameDisplay<'a>(pub &'a [DNSLabel]);
+
+impl fmt::Display for NameDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, self.0)
+    }
+}
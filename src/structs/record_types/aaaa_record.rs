@@ -1,12 +1,17 @@
-use crate::structs::{dns_packet_buffer::DNSPacketBuffer, error::ParseError};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::structs::{
+    dns_packet_buffer::DNSPacketBuffer,
+    error::{ParseError, WriteError},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AAAARecord(pub std::net::Ipv6Addr);
 
 impl AAAARecord {
-    // pub const TYPE: u16 = 28;
+    pub const TYPE: u16 = 28;
 
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse AAAA record (IPv6)
         if data_length != 16 {
             return Err(ParseError::InvalidResourceRecord {
@@ -14,13 +19,20 @@ impl AAAARecord {
             });
         }
         let mut ipv6_bytes = [0; 16];
-        ipv6_bytes.copy_from_slice(&raw_data);
+        ipv6_bytes.copy_from_slice(buffer.next_chunk(16)?);
         let ip_address = std::net::Ipv6Addr::from(ipv6_bytes);
         Ok(AAAARecord(ip_address))
     }
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) {
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
         for item in self.0.octets() {
-            buffer.write_u8(item);
+            buffer.write_u8(item)?;
         }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AAAARecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
@@ -1,23 +1,37 @@
-use crate::structs::{dns_packet_buffer::{DNSPacketBuffer, DNSLabel}, error::ParseError};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::structs::{
+    dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
+    error::{ParseError, WriteError},
+    presentation::write_name,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CNAMERecord(pub Vec<DNSLabel>);
 
 impl CNAMERecord {
-    // pub const TYPE: u16 = 5;
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub const TYPE: u16 = 5;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse CNAME record
-        if data_length < 2 {
+        if data_length < 1 {
             return Err(ParseError::InvalidResourceRecord {
-                msg: "Invalid CNAME record: Data length should be at least 2 bytes".to_string(),
+                msg: "Invalid CNAME record: Data length should be at least 1 byte".to_string(),
             });
         }
-        let alias = DNSPacketBuffer::small(raw_data).parse_labels()?;
+        // the alias may point back into the packet, so it is
+        // read from the full buffer instead of the rdata alone
+        let alias = buffer.parse_labels()?;
         Ok(CNAMERecord(alias))
     }
 
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), String> {
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
         buffer.push_labels(&self.0)?;
         Ok(())
     }
 }
+
+impl fmt::Display for CNAMERecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.0)
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+
+use crate::structs::{
+    dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
+    error::{ParseError, WriteError},
+    presentation::write_name,
+};
+
+/// Redirects every name below its owner to the same name below
+/// the target, where a CNAME only aliases the owner itself (RFC 6672)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNAMERecord(pub Vec<DNSLabel>);
+
+impl DNAMERecord {
+    pub const TYPE: u16 = 39;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
+        if data_length < 1 {
+            return Err(ParseError::InvalidResourceRecord {
+                msg: "Invalid DNAME record: Data length should be at least 1 byte".to_string(),
+            });
+        }
+        // senders must not compress the target, but receivers
+        // are asked to cope with it anyway
+        let target = buffer.parse_labels()?;
+        Ok(DNAMERecord(target))
+    }
+
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        buffer.write_labels(&self.0)
+    }
+}
+
+impl fmt::Display for DNAMERecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.0)
+    }
+}
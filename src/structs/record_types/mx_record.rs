@@ -1,34 +1,45 @@
+use std::fmt;
+
 use crate::structs::{
     dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
-    error::ParseError,
+    error::{ParseError, WriteError},
+    presentation::write_name,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MXRecord {
-    preference: u16,
-    mail_exchange: Vec<DNSLabel>,
+    /// lower values are preferred over higher ones
+    pub preference: u16,
+    pub mail_exchange: Vec<DNSLabel>,
 }
 
 impl MXRecord {
-    // pub const TYPE: u16 = 15;
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub const TYPE: u16 = 15;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse MX record
-        if data_length != 6 {
+        if data_length < 3 {
             return Err(ParseError::InvalidResourceRecord {
-                msg: "Invalid MX record: Data length should be 6 bytes".to_string(),
+                msg: "Invalid MX record: Data length should be at least 3 bytes".to_string(),
             });
         }
-        let preference = u16::from_be_bytes([raw_data[0], raw_data[1]]);
-        let mail_exchange = DNSPacketBuffer::small(raw_data).parse_labels()?;
+        let preference = buffer.read_u16()?;
+        let mail_exchange = buffer.parse_labels()?;
         Ok(MXRecord {
             preference,
             mail_exchange,
         })
     }
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), String> {
-        buffer.write_u16(self.preference);
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        buffer.write_u16(self.preference)?;
         buffer.push_labels(&self.mail_exchange)?;
 
         Ok(())
     }
 }
+
+impl fmt::Display for MXRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.preference)?;
+        write_name(f, &self.mail_exchange)
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use crate::structs::{
+    dns_packet_buffer::{DNSPacketBuffer, UDP_MESSAGE_SIZE},
+    dns_record_types::DNSRecord,
+    dns_resource_record::DNSResourceRecord,
+    error::{ParseError, WriteError},
+    presentation::write_generic,
+};
+
+/// The payload size joyboy advertises, small enough to avoid
+/// IP fragmentation on common networks (DNS flag day 2020)
+pub const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// A single option in the rdata of an OPT record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EDNSOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+/// The EDNS(0) OPT pseudo-record (RFC 6891). It reuses the fields of
+/// a resource record for other purposes:
+///
+/// +------------+--------------+------------------------------+
+/// | Field      | Type         | Description                  |
+/// +------------+--------------+------------------------------+
+/// | NAME       | domain name  | MUST be 0 (root domain)      |
+/// | TYPE       | u_int16_t    | OPT (41)                     |
+/// | CLASS      | u_int16_t    | requestor's UDP payload size |
+/// | TTL        | u_int32_t    | extended RCODE and flags     |
+/// | RDLEN      | u_int16_t    | length of all RDATA          |
+/// | RDATA      | octet stream | {attribute,value} pairs      |
+/// +------------+--------------+------------------------------+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OPTRecord {
+    /// the largest UDP message the sender can receive
+    pub udp_payload_size: u16,
+    /// the upper 8 bits of the 12 bit response code
+    pub extended_rcode: u8,
+    /// the EDNS version, only 0 is defined
+    pub version: u8,
+    /// DNSSEC OK, the sender can handle DNSSEC records
+    pub dnssec_ok: bool,
+    /// the remaining flag bits, reserved and set to 0
+    pub z: u16,
+    pub options: Vec<EDNSOption>,
+}
+
+impl OPTRecord {
+    pub const TYPE: u16 = 41;
+    /// extended RCODE sent back for unsupported EDNS versions
+    pub const BADVERS: u16 = 16;
+
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            z: 0,
+            options: vec![],
+        }
+    }
+
+    /// only the options are stored in the rdata, the other fields
+    /// are filled in from the class and ttl by `set_header_fields`
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
+        let end = buffer.offset + data_length;
+        let mut options = vec![];
+        while buffer.offset < end {
+            let code = buffer.read_u16()?;
+            let length = buffer.read_u16()?;
+            let data = buffer.next_chunk(length as usize)?.to_vec();
+            options.push(EDNSOption { code, data });
+        }
+
+        let mut record = Self::new(0);
+        record.options = options;
+        Ok(record)
+    }
+
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        for option in self.options.iter() {
+            buffer.write_u16(option.code)?;
+            buffer.write_u16(option.data.len() as u16)?;
+            buffer.write_bytes(&option.data)?;
+        }
+        Ok(())
+    }
+
+    /// reads the fields that OPT stores in the class and ttl
+    pub fn set_header_fields(&mut self, class: u16, ttl: u32) {
+        self.udp_payload_size = class;
+        self.extended_rcode = (ttl >> 24) as u8;
+        self.version = (ttl >> 16) as u8;
+        self.dnssec_ok = ttl & 0x8000 == 0x8000;
+        self.z = (ttl & 0x7FFF) as u16;
+    }
+
+    /// packs the extended rcode, version and flags into a ttl
+    pub fn ttl(&self) -> u32 {
+        ((self.extended_rcode as u32) << 24)
+            | ((self.version as u32) << 16)
+            | ((self.dnssec_ok as u32) << 15)
+            | (self.z as u32 & 0x7FFF)
+    }
+
+    /// the size a UDP response to this sender may take up.
+    /// Values below 512 are treated as 512
+    pub fn max_udp_size(&self) -> usize {
+        (self.udp_payload_size as usize).max(UDP_MESSAGE_SIZE)
+    }
+
+    pub fn to_resource_record(&self) -> DNSResourceRecord {
+        DNSResourceRecord::new()
+            .rtype(Self::TYPE)
+            .class(self.udp_payload_size)
+            .ttl(self.ttl())
+            .data(DNSRecord::OPT(self.clone()))
+    }
+}
+
+/// the options in their generic form, the other fields
+/// are shown in the OPT pseudosection of a packet
+impl fmt::Display for OPTRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = vec![];
+        for option in self.options.iter() {
+            data.extend_from_slice(&option.code.to_be_bytes());
+            data.extend_from_slice(&(option.data.len() as u16).to_be_bytes());
+            data.extend_from_slice(&option.data);
+        }
+        write_generic(f, &data)
+    }
+}
@@ -1,20 +1,34 @@
-use crate::structs::{dns_packet_buffer::DNSPacketBuffer, error::ParseError};
+use std::fmt;
 
-#[derive(Debug, Clone)]
-pub struct PTRRecord(pub String);
+use crate::structs::{
+    dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
+    error::{ParseError, WriteError},
+    presentation::write_name,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PTRRecord(pub Vec<DNSLabel>);
 
 impl PTRRecord {
-    // pub const TYPE: u16 = 12;
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub const TYPE: u16 = 12;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse PTR record
-        if data_length < 2 {
+        if data_length < 1 {
             return Err(ParseError::InvalidResourceRecord {
-                msg: "Invalid PTR record: Data length should be at least 2 bytes".to_string(),
+                msg: "Invalid PTR record: Data length should be at least 1 byte".to_string(),
             });
         }
-        let domain_name = String::from_utf8_lossy(&raw_data[2..]).to_string();
+        let domain_name = buffer.parse_labels()?;
         Ok(PTRRecord(domain_name))
     }
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) {}
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        buffer.push_labels(&self.0)?;
+        Ok(())
+    }
 }
 
+impl fmt::Display for PTRRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.0)
+    }
+}
@@ -1,57 +1,41 @@
-use crate::structs::{dns_packet_buffer::DNSPacketBuffer, error::ParseError};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::structs::{
+    dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
+    error::{ParseError, WriteError},
+    presentation::write_name,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SOARecord {
-    primary_ns: String,
-    responsible_email: String,
-    serial: u32,
-    refresh: u32,
-    retry: u32,
-    expire: u32,
-    minimum_ttl: u32,
+    pub primary_ns: Vec<DNSLabel>,
+    /// the mailbox of the person responsible for the zone,
+    /// with the `@` written as the first dot
+    pub responsible_email: Vec<DNSLabel>,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum_ttl: u32,
 }
 
 impl SOARecord {
-    // pub const TYPE: u16 = 6;
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub const TYPE: u16 = 6;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse SOA record
+        // two names of at least one byte each and five u32s
         if data_length < 22 {
             return Err(ParseError::InvalidResourceRecord {
                 msg: "Invalid SOA record: Data length should be at least 22 bytes".to_string(),
             });
         }
-        let primary_ns = String::from_utf8_lossy(&raw_data[0..]).to_string();
-        let responsible_email = String::from_utf8_lossy(&raw_data[primary_ns.len()..]).to_string();
-        let serial = u32::from_be_bytes([
-            raw_data[primary_ns.len() + responsible_email.len()],
-            raw_data[primary_ns.len() + responsible_email.len() + 1],
-            raw_data[primary_ns.len() + responsible_email.len() + 2],
-            raw_data[primary_ns.len() + responsible_email.len() + 3],
-        ]);
-        let refresh = u32::from_be_bytes([
-            raw_data[primary_ns.len() + responsible_email.len() + 4],
-            raw_data[primary_ns.len() + responsible_email.len() + 5],
-            raw_data[primary_ns.len() + responsible_email.len() + 6],
-            raw_data[primary_ns.len() + responsible_email.len() + 7],
-        ]);
-        let retry = u32::from_be_bytes([
-            raw_data[primary_ns.len() + responsible_email.len() + 8],
-            raw_data[primary_ns.len() + responsible_email.len() + 9],
-            raw_data[primary_ns.len() + responsible_email.len() + 10],
-            raw_data[primary_ns.len() + responsible_email.len() + 11],
-        ]);
-        let expire = u32::from_be_bytes([
-            raw_data[primary_ns.len() + responsible_email.len() + 12],
-            raw_data[primary_ns.len() + responsible_email.len() + 13],
-            raw_data[primary_ns.len() + responsible_email.len() + 14],
-            raw_data[primary_ns.len() + responsible_email.len() + 15],
-        ]);
-        let minimum_ttl = u32::from_be_bytes([
-            raw_data[primary_ns.len() + responsible_email.len() + 16],
-            raw_data[primary_ns.len() + responsible_email.len() + 17],
-            raw_data[primary_ns.len() + responsible_email.len() + 18],
-            raw_data[primary_ns.len() + responsible_email.len() + 19],
-        ]);
+        let primary_ns = buffer.parse_labels()?;
+        let responsible_email = buffer.parse_labels()?;
+        let serial = buffer.read_u32()?;
+        let refresh = buffer.read_u32()?;
+        let retry = buffer.read_u32()?;
+        let expire = buffer.read_u32()?;
+        let minimum_ttl = buffer.read_u32()?;
 
         Ok(SOARecord {
             primary_ns,
@@ -63,5 +47,28 @@ impl SOARecord {
             minimum_ttl,
         })
     }
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) {}
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        buffer.push_labels(&self.primary_ns)?;
+        buffer.push_labels(&self.responsible_email)?;
+        buffer.write_u32(self.serial)?;
+        buffer.write_u32(self.refresh)?;
+        buffer.write_u32(self.retry)?;
+        buffer.write_u32(self.expire)?;
+        buffer.write_u32(self.minimum_ttl)?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SOARecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.primary_ns)?;
+        f.write_str(" ")?;
+        write_name(f, &self.responsible_email)?;
+        write!(
+            f,
+            " {} {} {} {} {}",
+            self.serial, self.refresh, self.retry, self.expire, self.minimum_ttl
+        )
+    }
 }
@@ -1,26 +1,32 @@
-use crate::structs::{dns_packet_buffer::DNSPacketBuffer, error::ParseError};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::structs::{
+    dns_packet_buffer::{DNSLabel, DNSPacketBuffer},
+    error::{ParseError, WriteError},
+    presentation::write_name,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SRVRecord {
-    priority: u16,
-    weight: u16,
-    port: u16,
-    target: String,
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: Vec<DNSLabel>,
 }
 
 impl SRVRecord {
-    // pub const TYPE: u16 = 33;
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub const TYPE: u16 = 33;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse SRV record
-        if data_length != 16 {
+        if data_length < 7 {
             return Err(ParseError::InvalidResourceRecord {
-                msg: "Invalid SRV record: Data length should be 16 bytes".to_string(),
+                msg: "Invalid SRV record: Data length should be at least 7 bytes".to_string(),
             });
         }
-        let priority = u16::from_be_bytes([raw_data[0], raw_data[1]]);
-        let weight = u16::from_be_bytes([raw_data[2], raw_data[3]]);
-        let port = u16::from_be_bytes([raw_data[4], raw_data[5]]);
-        let target = String::from_utf8_lossy(&raw_data[6..]).to_string();
+        let priority = buffer.read_u16()?;
+        let weight = buffer.read_u16()?;
+        let port = buffer.read_u16()?;
+        let target = buffer.parse_labels()?;
         Ok(SRVRecord {
             priority,
             weight,
@@ -28,5 +34,20 @@ impl SRVRecord {
             target,
         })
     }
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) {}
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        buffer.write_u16(self.priority)?;
+        buffer.write_u16(self.weight)?;
+        buffer.write_u16(self.port)?;
+        // RFC 2782 forbids compressing the target
+        buffer.write_labels(&self.target)?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SRVRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} ", self.priority, self.weight, self.port)?;
+        write_name(f, &self.target)
+    }
 }
@@ -1,20 +1,47 @@
-use crate::structs::{dns_packet_buffer::DNSPacketBuffer, error::ParseError};
+use std::fmt;
 
-#[derive(Debug, Clone)]
-pub struct TXTRecord(pub String);
+use crate::structs::{
+    dns_packet_buffer::DNSPacketBuffer,
+    error::{ParseError, WriteError},
+    presentation::write_character_string,
+};
+
+/// one or more <character-string>s, kept as the bytes they are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TXTRecord(pub Vec<Vec<u8>>);
 
 impl TXTRecord {
-    // pub const TYPE: u16 = 16;
-    pub fn parse(data_length: usize, raw_data: &[u8]) -> Result<Self, ParseError> {
+    pub const TYPE: u16 = 16;
+    pub fn parse(data_length: usize, buffer: &mut DNSPacketBuffer) -> Result<Self, ParseError> {
         // Parse TXT record
-        if data_length < 2 {
+        if data_length < 1 {
             return Err(ParseError::InvalidResourceRecord {
-                msg: "Invalid TXT record: Data length should be at least 2 bytes".to_string(),
+                msg: "Invalid TXT record: Data length should be at least 1 byte".to_string(),
             });
         }
-        let text_data = String::from_utf8_lossy(&raw_data[2..]).to_string();
+        let end = buffer.offset + data_length;
+        let mut text_data = vec![];
+        while buffer.offset < end {
+            text_data.push(buffer.read_character_string()?);
+        }
         Ok(TXTRecord(text_data))
     }
-    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) {}
+    pub fn write_to_buffer(&self, buffer: &mut DNSPacketBuffer) -> Result<(), WriteError> {
+        for text in self.0.iter() {
+            buffer.write_character_string(text)?;
+        }
+        Ok(())
+    }
 }
 
+impl fmt::Display for TXTRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, text) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write_character_string(f, text)?;
+        }
+        Ok(())
+    }
+}
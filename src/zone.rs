@@ -0,0 +1,576 @@
+use std::fmt;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use crate::records::CLASS_IN;
+use crate::structs::presentation::read_escape;
+use crate::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord,
+    dname_record::DNAMERecord, mx_record::MXRecord, ns_record::NSRecord, opt_record::OPTRecord,
+    ptr_record::PTRRecord, soa_record::SOARecord, srv_record::SRVRecord, txt_record::TXTRecord,
+};
+use crate::{DNSLabel, DNSPacketBuffer, DNSRecord, DNSResourceRecord, Name};
+
+/// How deep `$INCLUDE` files may nest
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// An error in a zone file, with the line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneError {
+    /// the file the error is in, `None` when parsing text
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}: {}", file.display(), self.line, self.msg),
+            None => write!(f, "line {}: {}", self.line, self.msg),
+        }
+    }
+}
+
+impl std::error::Error for ZoneError {}
+
+/// reads an RFC 1035 master file. Relative names are completed with
+/// `origin` until a `$ORIGIN` changes it. `$INCLUDE` paths are taken
+/// relative to the working directory.
+pub fn parse_zone(text: &str, origin: &[DNSLabel]) -> Result<Vec<DNSResourceRecord>, ZoneError> {
+    let mut parser = ZoneParser::new(origin.to_vec(), None, 0);
+    parser.parse(text)?;
+    Ok(parser.records)
+}
+
+/// reads an RFC 1035 master file from disk. `$INCLUDE` paths
+/// are taken relative to the directory of the file. The zone
+/// must have an SOA record at `origin`, its apex.
+pub fn load_zone(path: &Path, origin: &[DNSLabel]) -> Result<Vec<DNSResourceRecord>, ZoneError> {
+    let mut parser = ZoneParser::new(origin.to_vec(), Some(path.to_path_buf()), 0);
+    let text = fs::read_to_string(path).map_err(|e| ZoneError {
+        file: Some(path.to_path_buf()),
+        line: 0,
+        msg: e.to_string(),
+    })?;
+    parser.parse(&text)?;
+
+    // without it the zone has no serial and
+    // no TTL for negative answers
+    let has_soa = parser
+        .records
+        .iter()
+        .any(|record| record.rtype == SOARecord::TYPE && record.name == origin);
+    if !has_soa {
+        let apex = Name::from_labels(origin)
+            .map(|apex| apex.to_string())
+            .unwrap_or_default();
+        return Err(ZoneError {
+            file: Some(path.to_path_buf()),
+            line: 0,
+            msg: format!("no SOA record at {}", apex),
+        });
+    }
+    Ok(parser.records)
+}
+
+/// reads the data of a record in presentation format, like
+/// `10 mail.example.com.` for an MX record
+pub fn parse_rdata(rtype: u16, rdata: &str, origin: &[DNSLabel]) -> Result<DNSRecord, String> {
+    let lines = tokenize(rdata).map_err(|(_, msg)| msg)?;
+    let tokens: Vec<Token> = lines.into_iter().flat_map(|line| line.tokens).collect();
+    rdata_from_tokens(rtype, &tokens, origin)
+}
+
+/// reads a domain name. Names ending in a dot are absolute, others
+/// are relative to `origin`. `@` is the origin itself. Dots and
+/// other characters can be escaped as `\.` or `\DDD`.
+pub fn parse_name(text: &str, origin: &[DNSLabel]) -> Result<Vec<DNSLabel>, String> {
+    if text == "@" {
+        return Ok(origin.to_vec());
+    }
+    let origin = Name::from_labels(origin).map_err(|e| e.to_string())?;
+    let name = Name::parse_relative(text, &origin).map_err(|e| e.to_string())?;
+    Ok(name.to_labels())
+}
+
+/// reads a TTL, either in seconds or with units like `1h30m`
+pub fn parse_ttl(text: &str) -> Option<u32> {
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(seconds) = text.parse() {
+        return Some(seconds);
+    }
+
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let value: u32 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+/// one word of a zone file. Escapes are kept as they were
+/// written, since names and text decode them differently
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// an entry of the file, which can span several lines in parentheses
+#[derive(Debug)]
+struct Line {
+    number: usize,
+    /// the line started with whitespace, so the
+    /// owner is the one of the previous record
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+/// splits the file into entries and their words, dropping comments
+fn tokenize(text: &str) -> Result<Vec<Line>, (usize, String)> {
+    let mut lines = vec![];
+    let mut tokens: Vec<Token> = vec![];
+    let mut current = String::new();
+    let mut line_number = 1;
+    let mut start_line = 1;
+    let mut blank_owner = false;
+    let mut at_line_start = true;
+    let mut depth = 0;
+
+    fn finish(current: &mut String, tokens: &mut Vec<Token>) {
+        if !current.is_empty() {
+            tokens.push(Token {
+                text: std::mem::take(current),
+                quoted: false,
+            });
+        }
+    }
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if at_line_start && c != '\n' {
+            start_line = line_number;
+            blank_owner = c == ' ' || c == '\t';
+            at_line_start = false;
+        }
+
+        match c {
+            '\n' => {
+                finish(&mut current, &mut tokens);
+                if depth == 0 {
+                    if !tokens.is_empty() {
+                        lines.push(Line {
+                            number: start_line,
+                            blank_owner,
+                            tokens: std::mem::take(&mut tokens),
+                        });
+                    }
+                    at_line_start = true;
+                }
+                line_number += 1;
+            }
+            ';' => {
+                finish(&mut current, &mut tokens);
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            '"' => {
+                finish(&mut current, &mut tokens);
+                let mut quoted = String::new();
+                let quote_line = line_number;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            quoted.push('\\');
+                            if let Some(escaped) = chars.next() {
+                                quoted.push(escaped);
+                            }
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                line_number += 1;
+                            }
+                            quoted.push(c);
+                        }
+                        None => return Err((quote_line, "unterminated quote".to_string())),
+                    }
+                }
+                tokens.push(Token {
+                    text: quoted,
+                    quoted: true,
+                });
+            }
+            '(' => {
+                finish(&mut current, &mut tokens);
+                depth += 1;
+            }
+            ')' => {
+                finish(&mut current, &mut tokens);
+                if depth == 0 {
+                    return Err((line_number, "unbalanced parenthesis".to_string()));
+                }
+                depth -= 1;
+            }
+            ' ' | '\t' | '\r' => finish(&mut current, &mut tokens),
+            '\\' => {
+                current.push('\\');
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+
+    if depth > 0 {
+        return Err((start_line, "unbalanced parenthesis".to_string()));
+    }
+    finish(&mut current, &mut tokens);
+    if !tokens.is_empty() {
+        lines.push(Line {
+            number: start_line,
+            blank_owner,
+            tokens,
+        });
+    }
+    Ok(lines)
+}
+
+/// decodes the escapes of a <character-string> into its bytes
+fn decode_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = vec![];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => read_escape(&mut chars, &mut decoded)
+                .ok_or_else(|| format!("invalid escape in {:?}", text))?,
+            _ => decoded.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Ok(decoded)
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("{:?} is not a valid number", text))
+}
+
+fn parse_time(text: &str) -> Result<u32, String> {
+    parse_ttl(text).ok_or_else(|| format!("{:?} is not a valid time", text))
+}
+
+fn rdata_from_tokens(
+    rtype: u16,
+    tokens: &[Token],
+    origin: &[DNSLabel],
+) -> Result<DNSRecord, String> {
+    if tokens
+        .first()
+        .is_some_and(|token| !token.quoted && token.text == "\\#")
+    {
+        return generic_rdata(rtype, &tokens[1..]);
+    }
+
+    let fields: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+    let wrong_fields = |usage: &str| Err(format!("expected {}", usage));
+
+    let record = match rtype {
+        ARecord::TYPE => match fields[..] {
+            [address] => DNSRecord::A(ARecord(
+                address
+                    .parse::<Ipv4Addr>()
+                    .map_err(|_| format!("{:?} is not an IPv4 address", address))?,
+            )),
+            _ => return wrong_fields("an IPv4 address"),
+        },
+        AAAARecord::TYPE => match fields[..] {
+            [address] => DNSRecord::AAAA(AAAARecord(
+                address
+                    .parse::<Ipv6Addr>()
+                    .map_err(|_| format!("{:?} is not an IPv6 address", address))?,
+            )),
+            _ => return wrong_fields("an IPv6 address"),
+        },
+        CNAMERecord::TYPE | DNAMERecord::TYPE | NSRecord::TYPE | PTRRecord::TYPE => {
+            match fields[..] {
+                [name] => {
+                    let name = parse_name(name, origin)?;
+                    match rtype {
+                        CNAMERecord::TYPE => DNSRecord::CNAME(CNAMERecord(name)),
+                        DNAMERecord::TYPE => DNSRecord::DNAME(DNAMERecord(name)),
+                        NSRecord::TYPE => DNSRecord::NS(NSRecord(name)),
+                        _ => DNSRecord::PTR(PTRRecord(name)),
+                    }
+                }
+                _ => return wrong_fields("a domain name"),
+            }
+        }
+        MXRecord::TYPE => match fields[..] {
+            [preference, mail_exchange] => DNSRecord::MX(MXRecord {
+                preference: parse_number(preference)?,
+                mail_exchange: parse_name(mail_exchange, origin)?,
+            }),
+            _ => return wrong_fields("<preference> <exchange>"),
+        },
+        SRVRecord::TYPE => match fields[..] {
+            [priority, weight, port, target] => DNSRecord::SRV(SRVRecord {
+                priority: parse_number(priority)?,
+                weight: parse_number(weight)?,
+                port: parse_number(port)?,
+                target: parse_name(target, origin)?,
+            }),
+            _ => return wrong_fields("<priority> <weight> <port> <target>"),
+        },
+        SOARecord::TYPE => match fields[..] {
+            [primary_ns, responsible_email, serial, refresh, retry, expire, minimum_ttl] => {
+                DNSRecord::SOA(SOARecord {
+                    primary_ns: parse_name(primary_ns, origin)?,
+                    responsible_email: parse_name(responsible_email, origin)?,
+                    serial: parse_number(serial)?,
+                    refresh: parse_time(refresh)?,
+                    retry: parse_time(retry)?,
+                    expire: parse_time(expire)?,
+                    minimum_ttl: parse_time(minimum_ttl)?,
+                })
+            }
+            _ => {
+                return wrong_fields(
+                    "<mname> <rname> <serial> <refresh> <retry> <expire> <minimum>",
+                )
+            }
+        },
+        TXTRecord::TYPE => {
+            if fields.is_empty() {
+                return wrong_fields("at least one string");
+            }
+            let mut strings = vec![];
+            for field in fields {
+                let text = decode_text(field)?;
+                if text.len() > 255 {
+                    return Err("strings can be at most 255 bytes".to_string());
+                }
+                strings.push(text);
+            }
+            DNSRecord::TXT(TXTRecord(strings))
+        }
+        OPTRecord::TYPE => return Err("OPT records can not be written in zone files".to_string()),
+        _ => return Err(format!("TYPE{} records need the generic \\# syntax", rtype)),
+    };
+    Ok(record)
+}
+
+/// reads the RFC 3597 `\# <length> <hex>` form, which works for any type
+fn generic_rdata(rtype: u16, tokens: &[Token]) -> Result<DNSRecord, String> {
+    let (length, hex) = tokens
+        .split_first()
+        .ok_or("expected \\# <length> <hex data>")?;
+    let length: usize = parse_number(&length.text)?;
+
+    let hex: String = hex.iter().map(|token| token.text.as_str()).collect();
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{:?} is not valid hex data", hex));
+    }
+    let data: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    if data.len() != length {
+        return Err(format!(
+            "the data is {} bytes long, not {}",
+            data.len(),
+            length
+        ));
+    }
+
+    let mut buffer = DNSPacketBuffer::new(&data);
+    let record =
+        DNSRecord::parse_dns_record_data(rtype, length, &mut buffer).map_err(|e| e.to_string())?;
+    if buffer.offset != length {
+        return Err(format!("the data does not fit a TYPE{} record", rtype));
+    }
+    Ok(record)
+}
+
+struct ZoneParser {
+    /// the top of the zone, every owner has to be in it
+    apex: Vec<DNSLabel>,
+    /// what relative names are completed with, set by `$ORIGIN`
+    origin: Vec<DNSLabel>,
+    /// set by `$TTL`
+    default_ttl: Option<u32>,
+    /// the TTL of the last record that had one
+    last_ttl: Option<u32>,
+    last_owner: Option<Vec<DNSLabel>>,
+    file: Option<PathBuf>,
+    depth: usize,
+    records: Vec<DNSResourceRecord>,
+}
+
+impl ZoneParser {
+    fn new(origin: Vec<DNSLabel>, file: Option<PathBuf>, depth: usize) -> Self {
+        Self {
+            apex: origin.clone(),
+            origin,
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            file,
+            depth,
+            records: vec![],
+        }
+    }
+
+    fn error(&self, line: usize, msg: String) -> ZoneError {
+        ZoneError {
+            file: self.file.clone(),
+            line,
+            msg,
+        }
+    }
+
+    fn parse(&mut self, text: &str) -> Result<(), ZoneError> {
+        let lines = tokenize(text).map_err(|(line, msg)| self.error(line, msg))?;
+        for line in lines.iter() {
+            let first = &line.tokens[0];
+            if !line.blank_owner && !first.quoted && first.text.starts_with('$') {
+                self.parse_directive(line)?;
+            } else {
+                let record = self
+                    .parse_record(line)
+                    .map_err(|msg| self.error(line.number, msg))?;
+                self.records.push(record);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_directive(&mut self, line: &Line) -> Result<(), ZoneError> {
+        let error = |msg: String| self.error(line.number, msg);
+        let fields: Vec<&str> = line
+            .tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect();
+
+        match (fields[0].to_ascii_uppercase().as_str(), &fields[1..]) {
+            ("$ORIGIN", [origin]) => {
+                self.origin = parse_name(origin, &self.origin).map_err(error)?;
+            }
+            ("$TTL", [ttl]) => {
+                self.default_ttl =
+                    Some(parse_ttl(ttl).ok_or_else(|| error(format!("{:?} is not a TTL", ttl)))?);
+            }
+            ("$INCLUDE", [file]) | ("$INCLUDE", [file, _]) => {
+                if self.depth >= MAX_INCLUDE_DEPTH {
+                    return Err(error("$INCLUDE files are nested too deep".to_string()));
+                }
+                let origin = match fields.get(2) {
+                    Some(origin) => parse_name(origin, &self.origin).map_err(error)?,
+                    None => self.origin.clone(),
+                };
+                let path = match self.file.as_ref().and_then(|file| file.parent()) {
+                    Some(directory) => directory.join(file),
+                    None => PathBuf::from(file),
+                };
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| error(format!("could not read {}: {}", path.display(), e)))?;
+
+                // the included file starts with the current state, but
+                // changes to the origin do not carry back
+                let mut parser = ZoneParser::new(origin, Some(path), self.depth + 1);
+                parser.apex = self.apex.clone();
+                parser.default_ttl = self.default_ttl;
+                parser.last_ttl = self.last_ttl;
+                parser.last_owner = self.last_owner.clone();
+                parser.parse(&text)?;
+                self.records.append(&mut parser.records);
+            }
+            ("$ORIGIN", _) | ("$TTL", _) | ("$INCLUDE", _) => {
+                return Err(error(format!("wrong number of fields for {}", fields[0])));
+            }
+            (directive, _) => return Err(error(format!("unknown directive {}", directive))),
+        }
+        Ok(())
+    }
+
+    fn parse_record(&mut self, line: &Line) -> Result<DNSResourceRecord, String> {
+        let mut tokens = line.tokens.iter().peekable();
+
+        let name = if line.blank_owner {
+            self.last_owner
+                .clone()
+                .ok_or("the first record needs an owner name")?
+        } else {
+            parse_name(&tokens.next().unwrap().text, &self.origin)?
+        };
+        let owner = Name::from_labels(&name).map_err(|e| e.to_string())?;
+        if !Name::from_labels(&self.apex).is_ok_and(|apex| owner.is_subdomain_of(&apex)) {
+            return Err("the owner is outside of the zone".to_string());
+        }
+
+        // the TTL and class can come in either order
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = tokens.peek() {
+            let text = token.text.to_ascii_uppercase();
+            if class.is_none() && text == "IN" {
+                class = Some(CLASS_IN);
+            } else if class.is_none() && ["CS", "CH", "HS"].contains(&text.as_str()) {
+                return Err(format!("only the IN class is supported, not {}", text));
+            } else if ttl.is_none() && parse_ttl(&text).is_some() {
+                ttl = parse_ttl(&text);
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+
+        let rtype = tokens.next().ok_or("the record has no type")?;
+        let rtype = DNSRecord::type_from_name(&rtype.text)
+            .ok_or_else(|| format!("unknown record type {}", rtype.text))?;
+        let rdata: Vec<Token> = tokens.cloned().collect();
+        let data = rdata_from_tokens(rtype, &rdata, &self.origin)?;
+
+        let ttl = match ttl {
+            Some(ttl) => {
+                self.last_ttl = Some(ttl);
+                ttl
+            }
+            None => self
+                .default_ttl
+                .or(self.last_ttl)
+                .ok_or("no TTL given and no $TTL set")?,
+        };
+        self.last_owner = Some(name.clone());
+
+        Ok(DNSResourceRecord {
+            name,
+            rtype,
+            class: class.unwrap_or(CLASS_IN),
+            ttl,
+            rdlength: 0,
+            data,
+        })
+    }
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use joyboy_dns::config::Config;
+use joyboy_dns::records::{alias_target, RecordStore};
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, cname_record::CNAMERecord, dname_record::DNAMERecord, soa_record::SOARecord,
+};
+use joyboy_dns::zone::parse_zone;
+use joyboy_dns::{DNSFlags, DNSPacket, DNSRecord, DNSResourceRecord};
+
+use common::{labels, question, silent_upstream};
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+$TTL 3600
+@               SOA     ns1 hostmaster 1 7200 900 1209600 300
+                NS      ns1
+ns1             A       192.0.2.1
+www             CNAME   web
+web             CNAME   host
+host            A       192.0.2.10
+dangling        CNAME   missing
+away            CNAME   www.example.net.
+loop1           CNAME   loop2
+loop2           CNAME   loop1
+old             DNAME   new.example.com.
+new             DNAME   example.net.
+host.new        A       192.0.2.20
+";
+
+fn store() -> RecordStore {
+    parse_zone(ZONE, &[]).unwrap().into_iter().collect()
+}
+
+fn ask(name: &str, qtype: u16) -> DNSPacket {
+    store().answer(&question(1, name, qtype)).unwrap()
+}
+
+fn chain(response: &DNSPacket) -> Vec<(String, u16)> {
+    response
+        .answers
+        .iter()
+        .map(|record| {
+            let labels: Vec<&str> = record
+                .name
+                .iter()
+                .map(|l| std::str::from_utf8(&l.value).unwrap())
+                .collect();
+            (labels.join("."), record.rtype)
+        })
+        .collect()
+}
+
+#[test]
+fn follows_local_cnames() {
+    let response = ask("www.example.com", ARecord::TYPE);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(
+        chain(&response),
+        vec![
+            ("www.example.com".to_string(), CNAMERecord::TYPE),
+            ("web.example.com".to_string(), CNAMERecord::TYPE),
+            ("host.example.com".to_string(), ARecord::TYPE),
+        ]
+    );
+    assert_eq!(alias_target(&response), None);
+
+    // asking for the CNAME itself does not follow it
+    let response = ask("www.example.com", CNAMERecord::TYPE);
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn a_missing_target_is_nxdomain() {
+    let response = ask("dangling.example.com", ARecord::TYPE);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.authorities[0].rtype, SOARecord::TYPE);
+    assert_eq!(alias_target(&response), None);
+}
+
+#[test]
+fn stops_at_loops() {
+    let response = ask("loop1.example.com", ARecord::TYPE);
+    assert_eq!(response.answers.len(), 2);
+    assert_eq!(alias_target(&response), None);
+}
+
+#[test]
+fn leaves_foreign_targets_for_upstream() {
+    let response = ask("away.example.com", ARecord::TYPE);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(alias_target(&response), Some(labels("www.example.net")));
+}
+
+#[test]
+fn dnames_synthesize_cnames() {
+    let response = ask("host.old.example.com", ARecord::TYPE);
+    assert!(response.header.flags.aa);
+    assert_eq!(
+        chain(&response),
+        vec![
+            ("old.example.com".to_string(), DNAMERecord::TYPE),
+            ("host.old.example.com".to_string(), CNAMERecord::TYPE),
+            ("new.example.com".to_string(), DNAMERecord::TYPE),
+            ("host.new.example.com".to_string(), CNAMERecord::TYPE),
+        ]
+    );
+    assert_eq!(
+        response.answers[1].data,
+        DNSRecord::CNAME(CNAMERecord(labels("host.new.example.com")))
+    );
+    assert_eq!(response.answers[1].ttl, 3600);
+    // the records below a DNAME are hidden by it
+    assert_eq!(alias_target(&response), Some(labels("host.example.net")));
+
+    // the owner itself is not redirected
+    let response = ask("old.example.com", DNAMERecord::TYPE);
+    assert_eq!(response.answers.len(), 1);
+    let response = ask("old.example.com", ARecord::TYPE);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities[0].rtype, SOARecord::TYPE);
+}
+
+#[test]
+fn dnames_that_overflow_the_name_are_yxdomain() {
+    let long = format!("{}.{}", "a".repeat(63), "b".repeat(63));
+    let mut records = parse_zone(ZONE, &[]).unwrap();
+    let mut dname = DNSResourceRecord::new()
+        .rtype(DNAMERecord::TYPE)
+        .class(1)
+        .ttl(60)
+        .data(DNSRecord::DNAME(DNAMERecord(labels(&format!(
+            "{}.{}.example.net",
+            long,
+            "c".repeat(63)
+        )))));
+    dname.name = labels("short.example.com");
+    records.push(dname);
+    let store: RecordStore = records.into_iter().collect();
+
+    let query = question(
+        1,
+        &format!("{}.{}.short.example.com", "d".repeat(63), "e".repeat(40)),
+        1,
+    );
+    let response = store.answer(&query).unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::YXDOMAIN);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].rtype, DNAMERecord::TYPE);
+}
+
+/// an upstream answering every A query with 203.0.113.7
+async fn address_upstream() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((amt, src)) = socket.recv_from(&mut buffer).await {
+            let query = DNSPacket::parse(&buffer[..amt]).unwrap();
+            let mut response = DNSPacket::response_to(&query);
+            let mut answer = DNSResourceRecord::new()
+                .rtype(ARecord::TYPE)
+                .class(1)
+                .ttl(30)
+                .data(DNSRecord::A(ARecord(Ipv4Addr::new(203, 0, 113, 7))));
+            answer.name = query.questions[0].qname.clone();
+            response.answers.push(answer);
+            response.update_counts();
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn resolves_the_rest_of_the_chain_upstream() {
+    let handler = Handler::new(Config {
+        upstreams: vec![address_upstream().await],
+        records: store(),
+        ..Config::default()
+    });
+    let mut query = question(1, "away.example.com", ARecord::TYPE);
+    query.header.id = 77;
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+
+    assert_eq!(response.header.id, 77);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.questions, query.questions);
+    assert_eq!(
+        chain(&response),
+        vec![
+            ("away.example.com".to_string(), CNAMERecord::TYPE),
+            ("www.example.net".to_string(), ARecord::TYPE),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_failing_upstream_is_servfail() {
+    let handler = Handler::new(Config {
+        upstreams: vec![silent_upstream().await],
+        upstream_timeout: Duration::from_millis(100),
+        records: store(),
+        ..Config::default()
+    });
+    let query = question(1, "away.example.com", ARecord::TYPE);
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::SERVFAIL);
+    assert_eq!(response.answers.len(), 1);
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use joyboy_dns::records::RecordStore;
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, mx_record::MXRecord, ns_record::NSRecord,
+    soa_record::SOARecord,
+};
+use joyboy_dns::zone::parse_zone;
+use joyboy_dns::{DNSFlags, DNSHeader, DNSPacket, DNSQuestion, Name};
+
+use common::labels;
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+$TTL 3600
+@               SOA     ns1 hostmaster 1 7200 900 1209600 300
+                NS      ns1
+ns1             A       192.0.2.1
+www             A       192.0.2.10
+a.b.c           A       192.0.2.11
+; a delegation with glue, and one without
+sub             NS      ns.sub
+sub             NS      ns.example.net.
+ns.sub          A       192.0.2.53
+ns.sub          AAAA    2001:db8::53
+other           NS      ns.example.org.
+";
+
+fn store() -> RecordStore {
+    parse_zone(ZONE, &[]).unwrap().into_iter().collect()
+}
+
+fn ask(store: &RecordStore, name: &str, qtype: u16) -> DNSPacket {
+    let mut query = DNSPacket::new();
+    query.header.id = 99;
+    query.header.flags.rd = true;
+    query.push_questions(DNSQuestion::from_simple_string(name, qtype, 1).unwrap());
+    query.update_counts();
+    store
+        .answer(&query)
+        .expect("names in the zone are answered")
+}
+
+fn assert_negative(response: &DNSPacket) {
+    assert!(response.header.flags.aa);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities.len(), 1);
+    let soa = &response.authorities[0];
+    assert_eq!(soa.rtype, SOARecord::TYPE);
+    assert_eq!(soa.name, labels("example.com"));
+    // capped at the minimum field for negative caching
+    assert_eq!(soa.ttl, 300);
+}
+
+#[test]
+fn answers_existing_names() {
+    let response = ask(&store(), "www.example.com", ARecord::TYPE);
+    assert!(response.header.flags.qr);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+    assert!(response.authorities.is_empty());
+
+    // the NS records at the apex are the zone's own
+    let response = ask(&store(), "example.com", NSRecord::TYPE);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn missing_names_are_nxdomain() {
+    let response = ask(&store(), "nope.example.com", ARecord::TYPE);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert_negative(&response);
+
+    let response = ask(&store(), "x.www.EXAMPLE.com", ARecord::TYPE);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert_negative(&response);
+}
+
+#[test]
+fn missing_types_are_nodata() {
+    let response = ask(&store(), "www.example.com", MXRecord::TYPE);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_negative(&response);
+
+    // b.c.example.com only exists because a name below it does
+    let response = ask(&store(), "b.c.example.com", ARecord::TYPE);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_negative(&response);
+}
+
+#[test]
+fn delegations_are_referrals() {
+    let response = ask(&store(), "host.sub.example.com", ARecord::TYPE);
+    assert!(!response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities.len(), 2);
+    assert!(response
+        .authorities
+        .iter()
+        .all(|record| record.rtype == NSRecord::TYPE && record.name == labels("sub.example.com")));
+
+    // only the nameserver inside the zone gets glue
+    let glue: Vec<u16> = response
+        .additionals
+        .iter()
+        .map(|record| record.rtype)
+        .collect();
+    assert_eq!(glue, vec![ARecord::TYPE, AAAARecord::TYPE]);
+    assert!(response
+        .additionals
+        .iter()
+        .all(|record| record.name == labels("ns.sub.example.com")));
+
+    // the glue itself is below the cut, so it is not ours to answer
+    let response = ask(&store(), "ns.sub.example.com", ARecord::TYPE);
+    assert!(!response.header.flags.aa);
+    assert!(response.answers.is_empty());
+
+    let response = ask(&store(), "other.example.com", ARecord::TYPE);
+    assert!(!response.header.flags.aa);
+    assert_eq!(response.authorities.len(), 1);
+    assert!(response.additionals.is_empty());
+}
+
+#[test]
+fn dots_inside_labels_are_part_of_the_label() {
+    let zone = "$ORIGIN example.com.\n\
+                $TTL 60\n\
+                @ SOA ns1 hostmaster 1 2 3 4 5\n\
+                a\\.b A 192.0.2.12\n";
+    let store: RecordStore = parse_zone(zone, &[]).unwrap().into_iter().collect();
+
+    let mut query = DNSPacket::new();
+    query.push_questions(DNSQuestion {
+        qname: "a\\.b.example.com".parse::<Name>().unwrap().to_labels(),
+        qtype: ARecord::TYPE,
+        qclass: 1,
+    });
+    query.update_counts();
+    let response = store.answer(&query).unwrap();
+    assert_eq!(response.answers.len(), 1);
+
+    // neither a name of three labels nor a parent of one
+    for name in ["a.b.example.com", "b.example.com"] {
+        let response = ask(&store, name, ARecord::TYPE);
+        assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN, "{}", name);
+    }
+}
+
+#[test]
+fn names_outside_zones_are_forwarded() {
+    let mut query = DNSPacket::new();
+    query.push_questions(DNSQuestion::from_simple_string("example.org", 1, 1).unwrap());
+    query.update_counts();
+    assert_eq!(store().answer(&query), None);
+}
+
+#[test]
+fn headers_keep_the_packet_flags() {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 1, 1).unwrap());
+    let header = DNSHeader::from_dns_struct(&packet, 5);
+    assert!(!header.flags.qr);
+    assert!(!header.flags.aa);
+
+    let response = ask(&store(), "nope.example.com", ARecord::TYPE);
+    let header = DNSHeader::from_dns_struct(&response, 5);
+    assert_eq!(header.id, 5);
+    assert!(header.flags.qr);
+    assert_eq!(header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert_eq!(header.authorities_count, 1);
+}
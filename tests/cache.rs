@@ -0,0 +1,652 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use joyboy_dns::cache::{Cache, CacheLimits, CacheStats, STALE_TTL};
+use joyboy_dns::config::{Config, ConfigError, ConfigFormat};
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::structs::record_types::{a_record::ARecord, soa_record::SOARecord};
+use joyboy_dns::{DNSFlags, DNSPacket, DNSQuestion, DNSRecord, DNSResourceRecord, Name};
+
+use common::{counting_upstream, failing_upstream, labels, silent_upstream};
+
+fn query(name: &str) -> DNSPacket {
+    let mut query = DNSPacket::new();
+    query.header.id = 1;
+    query.header.flags.rd = true;
+    query.push_questions(DNSQuestion::from_simple_string(name, ARecord::TYPE, 1).unwrap());
+    query.update_counts();
+    query
+}
+
+fn answer(query: &DNSPacket, ttls: &[u32]) -> DNSPacket {
+    let mut response = DNSPacket::response_to(query);
+    for (i, ttl) in ttls.iter().enumerate() {
+        let mut record = DNSResourceRecord::new()
+            .rtype(ARecord::TYPE)
+            .class(1)
+            .ttl(*ttl)
+            .data(DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, i as u8))));
+        record.name = query.questions[0].qname.clone();
+        response.answers.push(record);
+    }
+    response.update_counts();
+    response
+}
+
+/// an NXDOMAIN or NODATA answer with the SOA of example.com
+fn negative(query: &DNSPacket, rcode: u8, soa_ttl: u32, minimum_ttl: u32) -> DNSPacket {
+    let mut response = DNSPacket::response_to(query);
+    response.header.flags.rcode = rcode;
+    let mut soa = DNSResourceRecord::new()
+        .rtype(SOARecord::TYPE)
+        .class(1)
+        .ttl(soa_ttl)
+        .data(DNSRecord::SOA(SOARecord {
+            primary_ns: labels("ns1.example.com"),
+            responsible_email: labels("hostmaster.example.com"),
+            serial: 1,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum_ttl,
+        }));
+    soa.name = labels("example.com");
+    response.authorities.push(soa);
+    response.update_counts();
+    response
+}
+
+fn ttls(response: &DNSPacket) -> Vec<u32> {
+    response.answers.iter().map(|record| record.ttl).collect()
+}
+
+#[test]
+fn ttls_decay_while_cached() {
+    let cache = Cache::new(CacheLimits::default());
+    let start = Instant::now();
+    let query = query("example.com");
+    cache.insert(&query, &answer(&query, &[300, 60]), start);
+
+    let mut other_id = query.clone();
+    other_id.header.id = 2;
+    let cached = cache
+        .get(&other_id, start + Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(cached.header.id, 2);
+    assert!(cached.header.flags.qr);
+    assert_eq!(cached.questions, query.questions);
+    assert_eq!(ttls(&cached), vec![290, 50]);
+
+    // the answer expires with its shortest TTL
+    assert!(cache.get(&query, start + Duration::from_secs(59)).is_some());
+    assert!(cache.get(&query, start + Duration::from_secs(60)).is_none());
+}
+
+#[test]
+fn names_compare_case_insensitively() {
+    let cache = Cache::new(CacheLimits::default());
+    let now = Instant::now();
+    cache.insert(
+        &query("Example.COM"),
+        &answer(&query("Example.COM"), &[60]),
+        now,
+    );
+    assert!(cache.get(&query("example.com"), now).is_some());
+}
+
+#[test]
+fn dots_inside_labels_keep_names_apart() {
+    let cache = Cache::new(CacheLimits::default());
+    let now = Instant::now();
+    let mut escaped = DNSPacket::new();
+    escaped.push_questions(DNSQuestion {
+        qname: "a\\.b.example.com".parse::<Name>().unwrap().to_labels(),
+        qtype: ARecord::TYPE,
+        qclass: 1,
+    });
+    escaped.update_counts();
+    cache.insert(&escaped, &answer(&escaped, &[60]), now);
+    assert!(cache.get(&escaped, now).is_some());
+    assert!(cache.get(&query("a.b.example.com"), now).is_none());
+}
+
+#[test]
+fn ttls_are_clamped() {
+    let cache = Cache::new(CacheLimits {
+        size: 10,
+        min_ttl: 30,
+        max_ttl: 3600,
+        ..CacheLimits::default()
+    });
+    let now = Instant::now();
+    let query = query("example.com");
+    cache.insert(&query, &answer(&query, &[5, 86400]), now);
+    assert_eq!(ttls(&cache.get(&query, now).unwrap()), vec![30, 3600]);
+    assert!(cache.get(&query, now + Duration::from_secs(29)).is_some());
+    assert!(cache.get(&query, now + Duration::from_secs(30)).is_none());
+}
+
+#[test]
+fn only_complete_answers_are_cached() {
+    let cache = Cache::new(CacheLimits::default());
+    let now = Instant::now();
+    let query = query("example.com");
+
+    let mut nxdomain = answer(&query, &[]);
+    nxdomain.header.flags.rcode = DNSFlags::NXDOMAIN;
+    let mut truncated = answer(&query, &[60]);
+    truncated.header.flags.tc = true;
+    let other_question = answer(&self::query("example.org"), &[60]);
+    for response in [
+        answer(&query, &[]),
+        answer(&query, &[0]),
+        nxdomain,
+        truncated,
+        other_question,
+    ] {
+        cache.insert(&query, &response, now);
+    }
+    assert_eq!(cache.stats().entries, 0);
+
+    let off = Cache::new(CacheLimits {
+        size: 0,
+        ..CacheLimits::default()
+    });
+    off.insert(&query, &answer(&query, &[60]), now);
+    assert_eq!(off.stats().entries, 0);
+}
+
+#[test]
+fn caches_negative_answers_for_the_soa_minimum() {
+    let cache = Cache::new(CacheLimits::default());
+    let start = Instant::now();
+    let missing = query("missing.example.com");
+    cache.insert(
+        &missing,
+        &negative(&missing, DNSFlags::NXDOMAIN, 3600, 300),
+        start,
+    );
+
+    let cached = cache
+        .get(&missing, start + Duration::from_secs(100))
+        .unwrap();
+    assert_eq!(cached.header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert!(cached.answers.is_empty());
+    assert_eq!(cached.authorities.len(), 1);
+    // the SOA TTL counts down from the negative TTL
+    assert_eq!(cached.authorities[0].ttl, 200);
+    assert!(cache
+        .get(&missing, start + Duration::from_secs(300))
+        .is_none());
+
+    // NODATA, where the SOA TTL is the smaller one
+    let nodata = query("www.example.com");
+    cache.insert(
+        &nodata,
+        &negative(&nodata, DNSFlags::NOERROR, 60, 300),
+        start,
+    );
+    let cached = cache.get(&nodata, start).unwrap();
+    assert_eq!(cached.header.flags.rcode, DNSFlags::NOERROR);
+    assert!(cached.answers.is_empty());
+    assert!(cache
+        .get(&nodata, start + Duration::from_secs(60))
+        .is_none());
+}
+
+#[test]
+fn negative_ttls_are_clamped() {
+    let cache = Cache::new(CacheLimits {
+        negative_max_ttl: 30,
+        ..CacheLimits::default()
+    });
+    let now = Instant::now();
+    let missing = query("missing.example.com");
+    cache.insert(
+        &missing,
+        &negative(&missing, DNSFlags::NXDOMAIN, 86400, 86400),
+        now,
+    );
+    assert_eq!(cache.get(&missing, now).unwrap().authorities[0].ttl, 30);
+    assert!(cache.get(&missing, now + Duration::from_secs(30)).is_none());
+
+    // a CNAME to a missing name expires with the negative answer
+    let alias = query("alias.example.com");
+    let mut response = negative(&alias, DNSFlags::NXDOMAIN, 3600, 3600);
+    response.answers = answer(&alias, &[600]).answers;
+    response.update_counts();
+    cache.insert(&alias, &response, now);
+    let cached = cache.get(&alias, now).unwrap();
+    assert_eq!(cached.answers.len(), 1);
+    assert!(cache.get(&alias, now + Duration::from_secs(30)).is_none());
+}
+
+#[test]
+fn evicts_the_least_recently_used() {
+    let cache = Cache::new(CacheLimits {
+        size: 2,
+        ..CacheLimits::default()
+    });
+    let now = Instant::now();
+    for name in ["a.example", "b.example"] {
+        cache.insert(&query(name), &answer(&query(name), &[60]), now);
+    }
+    // a is used, so b is the one to go
+    assert!(cache.get(&query("a.example"), now).is_some());
+    cache.insert(
+        &query("c.example"),
+        &answer(&query("c.example"), &[60]),
+        now,
+    );
+
+    assert!(cache.get(&query("a.example"), now).is_some());
+    assert!(cache.get(&query("b.example"), now).is_none());
+    assert!(cache.get(&query("c.example"), now).is_some());
+    let stats = cache.stats();
+    assert_eq!(
+        stats,
+        CacheStats {
+            hits: 3,
+            misses: 1,
+            entries: 2,
+            memory: stats.memory,
+            ..CacheStats::default()
+        }
+    );
+
+    cache.set_limits(CacheLimits {
+        size: 1,
+        ..CacheLimits::default()
+    });
+    assert_eq!(cache.stats().entries, 1);
+    assert!(cache.get(&query("c.example"), now).is_some());
+}
+
+#[test]
+fn evicts_to_stay_within_the_memory_limit() {
+    let now = Instant::now();
+    let measure = Cache::new(CacheLimits::default());
+    measure.insert(
+        &query("a.example"),
+        &answer(&query("a.example"), &[60]),
+        now,
+    );
+    let one = measure.stats().memory;
+    assert!(one > 0);
+
+    // room for two answers, however many entries are allowed
+    let cache = Cache::new(CacheLimits {
+        memory: 2 * one,
+        ..CacheLimits::default()
+    });
+    for name in ["a.example", "b.example", "c.example"] {
+        cache.insert(&query(name), &answer(&query(name), &[60]), now);
+    }
+    assert_eq!(cache.stats().entries, 2);
+    assert_eq!(cache.stats().memory, 2 * one);
+    assert!(cache.get(&query("a.example"), now).is_none());
+    assert!(cache.get(&query("c.example"), now).is_some());
+
+    // an answer larger than the limit is not cached at all
+    let large = query("large.example");
+    cache.insert(&large, &answer(&large, &[60; 8]), now);
+    assert!(cache.get(&large, now).is_none());
+    assert_eq!(cache.stats().entries, 2);
+
+    cache.set_limits(CacheLimits {
+        memory: one,
+        ..CacheLimits::default()
+    });
+    assert_eq!(cache.stats().entries, 1);
+    assert!(cache.get(&query("c.example"), now).is_some());
+
+    cache.set_limits(CacheLimits {
+        memory: 0,
+        ..CacheLimits::default()
+    });
+    assert_eq!(cache.stats().memory, 0);
+}
+
+#[tokio::test]
+async fn forwarded_answers_are_served_from_the_cache() {
+    let (upstream, count) = counting_upstream().await;
+    let handler = Handler::new(Config {
+        upstreams: vec![upstream],
+        ..Config::default()
+    });
+
+    for id in [10, 11, 12] {
+        let mut query = query("cached.example");
+        query.header.id = id;
+        let response = handler
+            .handle(&query.to_bytes().unwrap(), Transport::Udp)
+            .await
+            .unwrap();
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(response.header.id, id);
+        assert!(response.header.flags.ra);
+        assert_eq!(response.answers.len(), 1);
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    let stats = handler.cache().stats();
+    assert_eq!((stats.hits, stats.misses), (2, 1));
+    assert_eq!(
+        stats.to_string(),
+        format!(
+            "hits: 2, misses: 1, stale: 0, prefetches: 0, entries: 1, bytes: {}",
+            stats.memory
+        )
+    );
+}
+
+#[test]
+fn serves_expired_answers_within_the_stale_window() {
+    let cache = Cache::new(CacheLimits {
+        stale_window: 600,
+        ..CacheLimits::default()
+    });
+    let start = Instant::now();
+    let query = query("example.com");
+    cache.insert(&query, &answer(&query, &[60, 300]), start);
+
+    // fresh answers are served as usual
+    assert_eq!(
+        ttls(&cache.get_stale(&query, start).unwrap()),
+        vec![60, 300]
+    );
+
+    let expired = start + Duration::from_secs(100);
+    assert!(cache.get(&query, expired).is_none());
+    let stale = cache.get_stale(&query, expired).unwrap();
+    assert_eq!(ttls(&stale), vec![STALE_TTL, STALE_TTL]);
+    assert_eq!(cache.stats().stale, 2);
+
+    // the window counts from the expiry
+    assert!(cache
+        .get_stale(&query, start + Duration::from_secs(659))
+        .is_some());
+    assert!(cache
+        .get_stale(&query, start + Duration::from_secs(660))
+        .is_none());
+    assert_eq!(cache.stats().entries, 0);
+
+    let no_stale = Cache::new(CacheLimits {
+        stale_window: 0,
+        ..CacheLimits::default()
+    });
+    no_stale.insert(&query, &answer(&query, &[60]), start);
+    assert!(no_stale
+        .get_stale(&query, start + Duration::from_secs(60))
+        .is_none());
+}
+
+#[test]
+fn prefetches_popular_answers_close_to_expiry() {
+    let cache = Cache::new(CacheLimits::default());
+    let start = Instant::now();
+    let query = query("example.com");
+    cache.insert(&query, &answer(&query, &[100]), start);
+
+    let late = start + Duration::from_secs(95);
+    cache.get(&query, late).unwrap();
+    // served once, not popular enough yet
+    assert!(!cache.take_prefetch(&query, late));
+    cache.get(&query, start).unwrap();
+    // popular, but with most of its TTL left
+    assert!(!cache.take_prefetch(&query, start + Duration::from_secs(50)));
+    assert!(cache.take_prefetch(&query, late));
+    // only handed out once
+    assert!(!cache.take_prefetch(&query, late));
+    assert_eq!(cache.stats().prefetches, 1);
+
+    // a fresh answer can be prefetched again
+    cache.insert(&query, &answer(&query, &[100]), late);
+    cache.get(&query, late).unwrap();
+    cache.get(&query, late).unwrap();
+    assert!(cache.take_prefetch(&query, late + Duration::from_secs(95)));
+
+    let off = Cache::new(CacheLimits {
+        prefetch: false,
+        ..CacheLimits::default()
+    });
+    off.insert(&query, &answer(&query, &[100]), start);
+    off.get(&query, late).unwrap();
+    off.get(&query, late).unwrap();
+    assert!(!off.take_prefetch(&query, late));
+}
+
+#[test]
+fn failed_prefetches_are_handed_out_again() {
+    let cache = Cache::new(CacheLimits::default());
+    let start = Instant::now();
+    let query = query("example.com");
+    cache.insert(&query, &answer(&query, &[100]), start);
+    let late = start + Duration::from_secs(95);
+    cache.get(&query, late).unwrap();
+    cache.get(&query, late).unwrap();
+
+    assert!(cache.take_prefetch(&query, late));
+    cache.prefetch_failed(&query);
+    assert!(cache.take_prefetch(&query, late));
+    assert_eq!(cache.stats().prefetches, 2);
+}
+
+/// an upstream answering every query with NXDOMAIN,
+/// counting the queries it gets
+async fn nxdomain_upstream(count: Arc<AtomicUsize>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((amt, src)) = socket.recv_from(&mut buffer).await {
+            count.fetch_add(1, Ordering::SeqCst);
+            let query = DNSPacket::parse(&buffer[..amt]).unwrap();
+            let response = negative(&query, DNSFlags::NXDOMAIN, 3600, 300);
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn missing_names_are_only_asked_for_once() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let handler = Handler::new(Config {
+        upstreams: vec![nxdomain_upstream(count.clone()).await],
+        ..Config::default()
+    });
+
+    for _ in 0..3 {
+        let query = query("missing.example.com");
+        let response = handler
+            .handle(&query.to_bytes().unwrap(), Transport::Udp)
+            .await
+            .unwrap();
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+        assert_eq!(response.authorities.len(), 1);
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn serves_stale_answers_when_upstreams_fail() {
+    let handler = Handler::new(Config {
+        upstreams: vec![silent_upstream().await],
+        upstream_timeout: Duration::from_millis(100),
+        ..Config::default()
+    });
+    let query = query("stale.example");
+    let long_ago = Instant::now()
+        .checked_sub(Duration::from_secs(100))
+        .unwrap();
+    handler
+        .cache()
+        .insert(&query, &answer(&query, &[10]), long_ago);
+
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(ttls(&response), vec![STALE_TTL]);
+
+    // without an answer to fall back on, the client is told
+    let query = self::query("never.example");
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::SERVFAIL);
+}
+
+#[tokio::test]
+async fn serves_stale_answers_when_upstreams_refuse() {
+    let handler = Handler::new(Config {
+        upstreams: vec![failing_upstream(DNSFlags::REFUSED).await],
+        ..Config::default()
+    });
+    let query = query("stale.example");
+    let long_ago = Instant::now()
+        .checked_sub(Duration::from_secs(100))
+        .unwrap();
+    handler
+        .cache()
+        .insert(&query, &answer(&query, &[10]), long_ago);
+
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(ttls(&response), vec![STALE_TTL]);
+}
+
+#[tokio::test]
+async fn refreshes_answers_in_the_background() {
+    let (upstream, count) = counting_upstream().await;
+    let handler = Handler::new(Config {
+        upstreams: vec![upstream],
+        ..Config::default()
+    });
+    let query = query("popular.example");
+    let almost_expired = Instant::now().checked_sub(Duration::from_secs(95)).unwrap();
+    handler
+        .cache()
+        .insert(&query, &answer(&query, &[100]), almost_expired);
+
+    for _ in 0..2 {
+        let response = handler
+            .handle(&query.to_bytes().unwrap(), Transport::Udp)
+            .await
+            .unwrap();
+        // answered from the cache without waiting for the refresh
+        assert!(ttls(&DNSPacket::parse(&response).unwrap())[0] <= 5);
+    }
+
+    for _ in 0..50 {
+        if count.load(Ordering::SeqCst) > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    let refreshed = handler.cache().get(&query, Instant::now()).unwrap();
+    assert_eq!(ttls(&refreshed), vec![120]);
+}
+
+#[tokio::test]
+async fn prefetches_again_after_a_failed_one() {
+    // drops the first query, answers the ones after it
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let upstream = socket.local_addr().unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    let counted = count.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((amt, src)) = socket.recv_from(&mut buffer).await {
+            if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+                continue;
+            }
+            let query = DNSPacket::parse(&buffer[..amt]).unwrap();
+            let response = answer(&query, &[120]);
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+        }
+    });
+    let handler = Handler::new(Config {
+        upstreams: vec![upstream],
+        upstream_timeout: Duration::from_millis(100),
+        upstream_retries: 0,
+        ..Config::default()
+    });
+    let query = query("popular.example");
+    let almost_expired = Instant::now().checked_sub(Duration::from_secs(95)).unwrap();
+    handler
+        .cache()
+        .insert(&query, &answer(&query, &[100]), almost_expired);
+
+    let ask = || async {
+        handler
+            .handle(&query.to_bytes().unwrap(), Transport::Udp)
+            .await
+            .unwrap();
+    };
+    ask().await;
+    ask().await;
+    // the first prefetch times out
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    ask().await;
+    for _ in 0..50 {
+        if count.load(Ordering::SeqCst) > 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    let refreshed = handler.cache().get(&query, Instant::now()).unwrap();
+    assert_eq!(ttls(&refreshed), vec![120]);
+}
+
+#[test]
+fn reads_limits_from_the_config() {
+    let config = Config::default()
+        .merge(
+            "[cache]\nsize = 5\nmemory = 4096\nmin_ttl = 10\nmax_ttl = 600\n\
+             negative_max_ttl = 60\nstale_window = 0\nprefetch = false\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+    assert_eq!(
+        config.cache,
+        CacheLimits {
+            size: 5,
+            memory: 4096,
+            min_ttl: 10,
+            max_ttl: 600,
+            negative_max_ttl: 60,
+            stale_window: 0,
+            prefetch: false,
+        }
+    );
+
+    let error = Config::default()
+        .merge(
+            "cache:\n  min_ttl: 100\n  max_ttl: 10\n",
+            ConfigFormat::Yaml,
+        )
+        .unwrap_err();
+    assert!(matches!(error, ConfigError::Invalid { field, .. } if field == "cache.min_ttl"));
+}
@@ -0,0 +1,240 @@
+mod common;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord, mx_record::MXRecord,
+    ns_record::NSRecord, ptr_record::PTRRecord, soa_record::SOARecord, srv_record::SRVRecord,
+    txt_record::TXTRecord,
+};
+use joyboy_dns::{DNSHeader, DNSPacket, DNSPacketBuffer, DNSQuestion, DNSRecord, UDP_MESSAGE_SIZE};
+
+use common::{labels, record};
+
+/// `example.com. IN A` with id 0x1234 and the RD bit set
+const QUERY: [u8; 29] = [
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, b'e', b'x', b'a',
+    b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+];
+
+fn write(packet: &DNSPacket) -> Vec<u8> {
+    let mut buffer = DNSPacketBuffer::empty();
+    packet.write_to_buffer(&mut buffer).unwrap();
+    buffer.response_bytes().to_vec()
+}
+
+#[test]
+fn parses_a_query() {
+    let packet = DNSPacket::parse(&QUERY).unwrap();
+
+    assert_eq!(packet.header.id, 0x1234);
+    assert!(!packet.header.flags.qr);
+    assert!(packet.header.flags.rd);
+    assert_eq!(packet.questions.len(), 1);
+    assert_eq!(packet.questions[0].qname, labels("example.com"));
+    assert_eq!(packet.questions[0].qtype, ARecord::TYPE);
+    assert_eq!(packet.questions[0].qclass, 1);
+}
+
+#[test]
+fn writes_a_query_byte_for_byte() {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 1, 1).unwrap());
+    packet.header.id = 0x1234;
+    packet.header.flags.rd = true;
+    packet.header.questions_count = 1;
+
+    assert_eq!(write(&packet), QUERY.to_vec());
+}
+
+#[test]
+fn parses_compressed_answers() {
+    // www.example.com. CNAME example.com. / example.com. A 93.184.216.34
+    let mut bytes = vec![
+        0xAB, 0xCD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x03, b'w', b'w',
+        b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+        0x01, 0x00, 0x01,
+    ];
+    bytes.extend_from_slice(&[
+        0xC0, 0x0C, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x02, 0xC0, 0x10,
+    ]);
+    bytes.extend_from_slice(&[
+        0xC0, 0x10, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x04, 93, 184, 216, 34,
+    ]);
+
+    let packet = DNSPacket::parse(&bytes).unwrap();
+
+    assert!(packet.header.flags.qr);
+    assert!(packet.header.flags.ra);
+    assert_eq!(packet.answers.len(), 2);
+    assert_eq!(packet.answers[0].name, labels("www.example.com"));
+    assert_eq!(
+        packet.answers[0].data,
+        DNSRecord::CNAME(CNAMERecord(labels("example.com")))
+    );
+    assert_eq!(packet.answers[1].name, labels("example.com"));
+    assert_eq!(packet.answers[1].ttl, 3600);
+    assert_eq!(
+        packet.answers[1].data,
+        DNSRecord::A(ARecord(Ipv4Addr::new(93, 184, 216, 34)))
+    );
+}
+
+#[test]
+fn round_trips_every_record_type() {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 255, 1).unwrap());
+
+    let answers = [
+        record(
+            "example.com",
+            ARecord::TYPE,
+            300,
+            DNSRecord::A(ARecord(Ipv4Addr::new(10, 0, 0, 1))),
+        ),
+        record(
+            "example.com",
+            AAAARecord::TYPE,
+            300,
+            DNSRecord::AAAA(AAAARecord(Ipv6Addr::LOCALHOST)),
+        ),
+        record(
+            "www.example.com",
+            CNAMERecord::TYPE,
+            300,
+            DNSRecord::CNAME(CNAMERecord(labels("example.com"))),
+        ),
+        record(
+            "example.com",
+            MXRecord::TYPE,
+            300,
+            DNSRecord::MX(MXRecord {
+                preference: 10,
+                mail_exchange: labels("mail.example.com"),
+            }),
+        ),
+        record(
+            "example.com",
+            TXTRecord::TYPE,
+            300,
+            DNSRecord::TXT(TXTRecord(vec!["v=spf1 -all".into(), "hello".into()])),
+        ),
+        record(
+            "_sip._tcp.example.com",
+            SRVRecord::TYPE,
+            300,
+            DNSRecord::SRV(SRVRecord {
+                priority: 1,
+                weight: 5,
+                port: 5060,
+                target: labels("sip.example.com"),
+            }),
+        ),
+    ];
+    let authorities = [
+        record(
+            "example.com",
+            NSRecord::TYPE,
+            300,
+            DNSRecord::NS(NSRecord(labels("ns1.example.com"))),
+        ),
+        record(
+            "example.com",
+            SOARecord::TYPE,
+            300,
+            DNSRecord::SOA(SOARecord {
+                primary_ns: labels("ns1.example.com"),
+                responsible_email: labels("hostmaster.example.com"),
+                serial: 2023080101,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum_ttl: 300,
+            }),
+        ),
+    ];
+    let additionals = [record(
+        "1.0.0.10.in-addr.arpa",
+        PTRRecord::TYPE,
+        300,
+        DNSRecord::PTR(PTRRecord(labels("example.com"))),
+    )];
+
+    for answer in answers.iter() {
+        packet.push_answers(answer.clone());
+    }
+    for authority in authorities.iter() {
+        packet.push_authorities(authority.clone());
+    }
+    for additional in additionals.iter() {
+        packet.push_additionals(additional.clone());
+    }
+    packet.header = DNSHeader::from_dns_struct(&packet, 0x4242);
+
+    let parsed = DNSPacket::parse(&write(&packet)).unwrap();
+
+    assert_eq!(parsed.header, packet.header);
+    assert_eq!(parsed.questions, packet.questions);
+    for (parsed, built) in parsed
+        .answers
+        .iter()
+        .chain(parsed.authorities.iter())
+        .chain(parsed.additionals.iter())
+        .zip(
+            answers
+                .iter()
+                .chain(authorities.iter())
+                .chain(additionals.iter()),
+        )
+    {
+        assert_eq!(parsed.name, built.name);
+        assert_eq!(parsed.rtype, built.rtype);
+        assert_eq!(parsed.class, built.class);
+        assert_eq!(parsed.ttl, built.ttl);
+        assert_eq!(parsed.data, built.data);
+    }
+}
+
+fn large_packet() -> DNSPacket {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 16, 1).unwrap());
+    for i in 0..40 {
+        packet.push_answers(record(
+            "example.com",
+            TXTRecord::TYPE,
+            300,
+            DNSRecord::TXT(TXTRecord(vec![format!("{:0>60}", i).into_bytes()])),
+        ));
+    }
+    packet.header = DNSHeader::from_dns_struct(&packet, 7);
+    packet
+}
+
+#[test]
+fn round_trips_messages_larger_than_512_bytes() {
+    let packet = large_packet();
+    let bytes = packet.to_bytes().unwrap();
+    assert!(bytes.len() > UDP_MESSAGE_SIZE);
+
+    let parsed = DNSPacket::parse(&bytes).unwrap();
+    assert_eq!(parsed.answers.len(), 40);
+    assert_eq!(
+        parsed.answers[39].data,
+        DNSRecord::TXT(TXTRecord(vec![format!("{:0>60}", 39).into_bytes()]))
+    );
+}
+
+#[test]
+fn truncates_udp_responses_that_do_not_fit() {
+    let packet = large_packet();
+    let bytes = packet.to_udp_bytes(UDP_MESSAGE_SIZE).unwrap();
+    assert!(bytes.len() <= UDP_MESSAGE_SIZE);
+
+    let parsed = DNSPacket::parse(&bytes).unwrap();
+    assert!(parsed.header.flags.tc);
+    assert_eq!(parsed.questions, packet.questions);
+    assert!(parsed.answers.is_empty());
+
+    let mut buffer = DNSPacketBuffer::with_max_size(UDP_MESSAGE_SIZE);
+    assert!(packet.write_to_buffer(&mut buffer).is_err());
+}
@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use joyboy_dns::structs::record_types::a_record::ARecord;
+use joyboy_dns::{DNSLabel, DNSPacket, DNSQuestion, DNSRecord, DNSResourceRecord, Name};
+
+/// an A query with recursion desired, as the bytes a client sends
+pub fn query(id: u16, name: &str) -> Vec<u8> {
+    question(id, name, 1).to_bytes().unwrap()
+}
+
+/// a query for one name and type, with recursion desired
+pub fn question(id: u16, name: &str, qtype: u16) -> DNSPacket {
+    let mut packet = DNSPacket::new();
+    packet.header.id = id;
+    packet.header.flags.rd = true;
+    packet.push_questions(DNSQuestion::from_simple_string(name, qtype, 1).unwrap());
+    packet.update_counts();
+    packet
+}
+
+/// the labels of a name in presentation format
+pub fn labels(name: &str) -> Vec<DNSLabel> {
+    name.parse::<Name>().unwrap().to_labels()
+}
+
+/// an IN record, the name in presentation format
+pub fn record(name: &str, rtype: u16, ttl: u32, data: DNSRecord) -> DNSResourceRecord {
+    DNSResourceRecord {
+        name: labels(name),
+        rtype,
+        class: 1,
+        ttl,
+        rdlength: 0,
+        data,
+    }
+}
+
+/// a fake upstream that answers every query with an empty NOERROR.
+/// Queries for `slow.example` are answered after `delay`
+pub async fn fake_upstream(delay: Duration) -> SocketAddr {
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((amt, src)) = socket.recv_from(&mut buffer).await {
+            let mut response = DNSPacket::parse(&buffer[..amt]).unwrap();
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                if response.contains_question(labels("slow.example")) {
+                    tokio::time::sleep(delay).await;
+                }
+                response.header.flags.qr = true;
+                response.header.flags.ra = true;
+                let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+            });
+        }
+    });
+    address
+}
+
+/// a fake upstream answering every query with one A record
+/// of 120 seconds, counting the queries it gets
+pub async fn counting_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    let counted = count.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((amt, src)) = socket.recv_from(&mut buffer).await {
+            counted.fetch_add(1, Ordering::SeqCst);
+            let query = DNSPacket::parse(&buffer[..amt]).unwrap();
+            let mut response = DNSPacket::response_to(&query);
+            let mut answer = DNSResourceRecord::new()
+                .rtype(ARecord::TYPE)
+                .class(1)
+                .ttl(120)
+                .data(DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 1))));
+            answer.name = query.questions[0].qname.clone();
+            response.answers.push(answer);
+            response.header.flags.ra = true;
+            response.update_counts();
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+        }
+    });
+    (address, count)
+}
+
+/// a fake upstream answering every query with the given error
+pub async fn failing_upstream(rcode: u8) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((amt, src)) = socket.recv_from(&mut buffer).await {
+            let query = DNSPacket::parse(&buffer[..amt]).unwrap();
+            let mut response = DNSPacket::response_to(&query);
+            response.header.flags.rcode = rcode;
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+        }
+    });
+    address
+}
+
+/// an upstream that never answers, a socket that was bound and dropped
+pub async fn silent_upstream() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.local_addr().unwrap()
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::net::Ipv4Addr;
+
+use joyboy_dns::structs::record_types::{a_record::ARecord, cname_record::CNAMERecord};
+use joyboy_dns::{DNSPacket, DNSPacketBuffer, DNSQuestion, DNSRecord, DNSResourceRecord};
+
+use common::{labels, record};
+
+fn response(question: &str, answers: Vec<DNSResourceRecord>) -> DNSPacket {
+    let mut packet = DNSPacket::new();
+    packet.header.id = 0xABCD;
+    packet.header.flags.qr = true;
+    packet.header.flags.rd = true;
+    packet.header.flags.ra = true;
+    packet.header.questions_count = 1;
+    packet.header.answers_count = answers.len() as u16;
+    packet.push_questions(DNSQuestion::from_simple_string(question, 1, 1).unwrap());
+    for answer in answers {
+        packet.push_answers(answer);
+    }
+    packet
+}
+
+#[test]
+fn compresses_like_a_real_server() {
+    // what a resolver answers for `www.example.com. IN A`
+    let mut expected = vec![
+        0xAB, 0xCD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x03, b'w', b'w',
+        b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00,
+        0x01, 0x00, 0x01,
+    ];
+    expected.extend_from_slice(&[
+        0xC0, 0x0C, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x02, 0xC0, 0x10,
+    ]);
+    expected.extend_from_slice(&[
+        0xC0, 0x10, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x04, 93, 184, 216, 34,
+    ]);
+
+    let packet = response(
+        "www.example.com",
+        vec![
+            record(
+                "www.example.com",
+                5,
+                3600,
+                DNSRecord::CNAME(CNAMERecord(labels("example.com"))),
+            ),
+            record(
+                "example.com",
+                1,
+                3600,
+                DNSRecord::A(ARecord(Ipv4Addr::new(93, 184, 216, 34))),
+            ),
+        ],
+    );
+
+    assert_eq!(packet.to_bytes().unwrap(), expected);
+}
+
+#[test]
+fn matches_names_case_insensitively() {
+    let packet = response(
+        "Example.COM",
+        vec![record(
+            "example.com",
+            1,
+            3600,
+            DNSRecord::A(ARecord(Ipv4Addr::new(10, 0, 0, 1))),
+        )],
+    );
+
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(&bytes[29..31], &[0xC0, 0x0C]);
+    // the spelling of the first occurrence is kept
+    assert_eq!(&bytes[13..20], b"Example");
+}
+
+#[test]
+fn reuses_suffixes_of_compressed_names() {
+    let packet = response(
+        "a.example.com",
+        vec![record(
+            "b.a.example.com",
+            5,
+            3600,
+            DNSRecord::CNAME(CNAMERecord(labels("c.b.a.example.com"))),
+        )],
+    );
+
+    let bytes = packet.to_bytes().unwrap();
+    // question: a.example.com at offset 12, 15 bytes
+    // answer name: `b` then a pointer to the question
+    assert_eq!(&bytes[31..35], &[0x01, b'b', 0xC0, 0x0C]);
+    // cname: `c` then a pointer to `b` in the answer name
+    assert_eq!(&bytes[45..49], &[0x01, b'c', 0xC0, 0x1F]);
+    assert_eq!(bytes.len(), 49);
+
+    let parsed = DNSPacket::parse(&bytes).unwrap();
+    assert_eq!(
+        parsed.answers[0].data,
+        DNSRecord::CNAME(CNAMERecord(labels("c.b.a.example.com")))
+    );
+}
+
+#[test]
+fn points_to_offsets_above_63() {
+    let mut buffer = DNSPacketBuffer::empty();
+    buffer.write_bytes(&[0; 300]).unwrap();
+    buffer.push_labels(&labels("example.com")).unwrap();
+    buffer.push_labels(&labels("www.example.com")).unwrap();
+
+    assert_eq!(
+        &buffer.response_bytes()[313..],
+        &[0x03, b'w', b'w', b'w', 0xC1, 0x2C]
+    );
+}
+
+#[test]
+fn does_not_point_beyond_the_14_bit_limit() {
+    let mut buffer = DNSPacketBuffer::empty();
+    buffer.write_bytes(&vec![0; 0x4000]).unwrap();
+    buffer.push_labels(&labels("example.com")).unwrap();
+    let first = buffer.offset;
+    buffer.push_labels(&labels("example.com")).unwrap();
+
+    assert_eq!(buffer.offset - first, 13);
+}
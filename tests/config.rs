@@ -0,0 +1,285 @@
+mod common;
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+
+use joyboy_dns::config::{Acl, Config, ConfigError, ConfigFormat, Network, DEFAULT_TTL};
+use joyboy_dns::options::Options;
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::server::reload::{reload, watch_config};
+use joyboy_dns::structs::record_types::{a_record::ARecord, mx_record::MXRecord};
+use joyboy_dns::{DNSFlags, DNSPacket, DNSRecord};
+
+use common::{labels, query};
+
+const TOML: &str = r#"
+listen = ["127.0.0.1:53", "[::1]:53"]
+upstreams = ["1.1.1.1", "9.9.9.9:5353"]
+upstream_timeout = 1500
+max_queries = 100
+blocklist = ["ads.example.com"]
+
+[[records]]
+name = "router.lan"
+type = "A"
+value = "192.168.1.1"
+ttl = 60
+
+[[records]]
+name = "lan."
+type = "MX"
+value = "10 mail.lan"
+
+[acl]
+allow = ["127.0.0.0/8", "::1"]
+deny = ["127.0.0.2"]
+"#;
+
+const YAML: &str = r#"
+listen: ["127.0.0.1:53", "[::1]:53"]
+upstreams: ["1.1.1.1", "9.9.9.9:5353"]
+upstream_timeout: 1500
+max_queries: 100
+blocklist: ["ads.example.com"]
+records:
+  - name: router.lan
+    type: A
+    value: 192.168.1.1
+    ttl: 60
+  - name: lan.
+    type: MX
+    value: 10 mail.lan
+acl:
+  allow: ["127.0.0.0/8", "::1"]
+  deny: ["127.0.0.2"]
+"#;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("joyboy-{}-{}", std::process::id(), name))
+}
+
+fn options() -> Options {
+    Options::try_parse_from(["joyboy_dns"]).unwrap()
+}
+
+#[test]
+fn reads_toml() {
+    let config = Config::default().merge(TOML, ConfigFormat::Toml).unwrap();
+
+    let listen: Vec<SocketAddr> =
+        vec!["127.0.0.1:53".parse().unwrap(), "[::1]:53".parse().unwrap()];
+    assert_eq!(config.listen, listen);
+    let upstreams: Vec<SocketAddr> = vec![
+        "1.1.1.1:53".parse().unwrap(),
+        "9.9.9.9:5353".parse().unwrap(),
+    ];
+    assert_eq!(config.upstreams, upstreams);
+    assert_eq!(config.upstream_timeout, Duration::from_millis(1500));
+    // not in the file, so it keeps the default
+    assert_eq!(config.tcp_idle_timeout, Config::default().tcp_idle_timeout);
+    assert_eq!(config.max_queries, 100);
+    assert_eq!(
+        config.max_tcp_connections,
+        Config::default().max_tcp_connections
+    );
+
+    assert_eq!(config.records.len(), 2);
+    let router = config.records.records(&labels("router.lan")).unwrap();
+    assert_eq!(router.len(), 1);
+    assert_eq!(router[0].ttl, 60);
+    assert_eq!(
+        router[0].data,
+        DNSRecord::A(ARecord(Ipv4Addr::new(192, 168, 1, 1)))
+    );
+    let lan = config.records.records(&labels("lan")).unwrap();
+    assert_eq!(lan[0].ttl, DEFAULT_TTL);
+    assert_eq!(
+        lan[0].data,
+        DNSRecord::MX(MXRecord {
+            preference: 10,
+            mail_exchange: labels("mail.lan"),
+        })
+    );
+}
+
+#[test]
+fn yaml_reads_the_same_as_toml() {
+    let toml = Config::default().merge(TOML, ConfigFormat::Toml).unwrap();
+    let yaml = Config::default().merge(YAML, ConfigFormat::Yaml).unwrap();
+    assert_eq!(toml, yaml);
+}
+
+#[test]
+fn reports_what_is_wrong() {
+    let errors = [
+        ("listen = [\"127.0.0.1\"]", "listen[0]"),
+        ("upstreams = [\"1.1.1.1\", \"dns.google\"]", "upstreams[1]"),
+        ("upstreams = []", "upstreams"),
+        ("upstream_timeout = 0", "upstream_timeout"),
+        ("tcp_idle_timeout = 0", "tcp_idle_timeout"),
+        ("max_queries = 0", "max_queries"),
+        ("max_tcp_connections = 0", "max_tcp_connections"),
+        ("blocklist = [\"a..b\"]", "blocklist[0]"),
+        ("[acl]\nallow = [\"10.0.0.0/33\"]", "acl.allow[0]"),
+        (
+            "[[records]]\nname = \"a.lan\"\ntype = \"A\"\nvalue = \"1.1.1.1\"\n\
+             [[records]]\nname = \"b.lan\"\ntype = \"A\"\nvalue = \"::1\"",
+            "records[1]",
+        ),
+        (
+            "[[records]]\nname = \"a.lan\"\ntype = \"HINFO\"\nvalue = \"x\"",
+            "records[0]",
+        ),
+    ];
+    for (text, expected_field) in errors {
+        match Config::default().merge(text, ConfigFormat::Toml) {
+            Err(ConfigError::Invalid { field, .. }) => assert_eq!(field, expected_field),
+            other => panic!("{:?} gave {:?}", text, other),
+        }
+    }
+
+    // a resolver needs no upstreams
+    assert!(Config::default()
+        .merge("upstreams = []\nrecursive = true", ConfigFormat::Toml)
+        .is_ok());
+
+    assert!(matches!(
+        Config::default().merge("listen = [", ConfigFormat::Toml),
+        Err(ConfigError::Syntax { .. })
+    ));
+    assert!(matches!(
+        Config::default().merge("lisen = []", ConfigFormat::Toml),
+        Err(ConfigError::Syntax { .. })
+    ));
+    assert!(matches!(
+        Config::load(&temp_path("missing.toml"), &options()),
+        Err(ConfigError::Io { .. })
+    ));
+}
+
+#[test]
+fn networks() {
+    let network: Network = "10.1.0.0/16".parse().unwrap();
+    assert!(network.contains("10.1.200.3".parse().unwrap()));
+    assert!(!network.contains("10.2.0.1".parse().unwrap()));
+    assert!(!network.contains("::1".parse().unwrap()));
+    assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+    let network: Network = "2001:db8::/32".parse().unwrap();
+    assert!(network.contains("2001:db8:1::1".parse().unwrap()));
+    assert!(!network.contains("2001:db9::1".parse().unwrap()));
+
+    let everything: Network = "0.0.0.0/0".parse().unwrap();
+    assert!(everything.contains("203.0.113.9".parse().unwrap()));
+
+    let denied: Network = "10.0.0.0/8".parse().unwrap();
+    let acl = Acl {
+        allow: vec![],
+        deny: vec![denied],
+    };
+    assert!(!acl.allows("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(acl.allows("::ffff:192.0.2.1".parse().unwrap()));
+}
+
+#[test]
+fn acl() {
+    let config = Config::default().merge(TOML, ConfigFormat::Toml).unwrap();
+    let allows = |address: &str| config.acl.allows(address.parse::<IpAddr>().unwrap());
+
+    assert!(allows("127.0.0.1"));
+    assert!(allows("::1"));
+    assert!(!allows("127.0.0.2"));
+    assert!(!allows("192.168.1.10"));
+    // IPv4 clients of a dual-stack listener
+    assert!(allows("::ffff:127.0.0.1"));
+    assert!(!allows("::ffff:127.0.0.2"));
+
+    // no rules allow everyone
+    assert!(Config::default()
+        .acl
+        .allows("192.168.1.10".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn blocked_names_get_nxdomain() {
+    let config = Config::default().merge(TOML, ConfigFormat::Toml).unwrap();
+    let handler = Handler::new(config);
+
+    for name in ["ads.example.com", "tracker.ADS.example.com"] {
+        let response = handler
+            .handle(&query(9, name), Transport::Udp)
+            .await
+            .unwrap();
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(response.header.id, 9);
+        assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+    }
+
+    let config = handler.config();
+    assert!(!config.is_blocked(&labels("example.com")));
+    assert!(!config.is_blocked(&labels("notads.example.com")));
+}
+
+#[test]
+fn a_bad_reload_keeps_the_running_config() {
+    let path = temp_path("reload.toml");
+    fs::write(&path, "upstreams = [\"1.1.1.1\"]").unwrap();
+
+    let handler = Handler::new(Config::load(&path, &options()).unwrap());
+    let before = handler.config();
+
+    fs::write(&path, "upstreams = [\"not an address\"]").unwrap();
+    assert!(reload(&path, &options(), &handler).is_err());
+    assert_eq!(handler.config(), before);
+
+    fs::write(&path, "upstreams = [\"9.9.9.9\"]").unwrap();
+    reload(&path, &options(), &handler).unwrap();
+    assert_eq!(
+        handler.config().upstreams,
+        vec!["9.9.9.9:53".parse::<SocketAddr>().unwrap()]
+    );
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn reloads_when_the_file_changes() {
+    let path = temp_path("watch.yaml");
+    fs::write(&path, "upstreams: [\"1.1.1.1\"]").unwrap();
+
+    let handler = Arc::new(Handler::new(Config::load(&path, &options()).unwrap()));
+    let watcher = tokio::spawn(watch_config(
+        path.clone(),
+        options(),
+        handler.clone(),
+        Duration::from_millis(20),
+    ));
+
+    // make sure the new file gets a different modification time
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    fs::write(&path, "upstreams: [\"9.9.9.9\"]").unwrap();
+
+    let expected: SocketAddr = "9.9.9.9:53".parse().unwrap();
+    let mut reloaded = false;
+    for _ in 0..100 {
+        if handler.config().upstreams == vec![expected] {
+            reloaded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    watcher.abort();
+    fs::remove_file(&path).unwrap();
+    assert!(reloaded, "the config was not reloaded");
+}
+
+#[test]
+fn example_config_is_valid() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("joyboy.example.toml");
+    Config::load(&path, &options()).unwrap();
+}
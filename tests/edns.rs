@@ -0,0 +1,103 @@
+use joyboy_dns::{
+    DNSHeader, DNSPacket, DNSQuestion, DNSRecord, DNSResourceRecord, EDNSOption, OPTRecord,
+    UDP_MESSAGE_SIZE,
+};
+
+/// `example.com. IN A` with an OPT record advertising 4096 bytes,
+/// the DO bit and an 8 byte client cookie
+const QUERY: [u8; 52] = [
+    0xBE, 0xEF, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x07, b'e', b'x', b'a',
+    b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x29,
+    0x10, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x0C, 0x00, 0x0A, 0x00, 0x08, 0x01, 0x02, 0x03, 0x04,
+    0x05, 0x06, 0x07, 0x08,
+];
+
+#[test]
+fn parses_the_opt_record_into_its_own_field() {
+    let packet = DNSPacket::parse(&QUERY).unwrap();
+
+    assert!(packet.additionals.is_empty());
+    assert_eq!(packet.header.additionals_count, 1);
+
+    let edns = packet.edns.unwrap();
+    assert_eq!(edns.udp_payload_size, 4096);
+    assert_eq!(edns.version, 0);
+    assert_eq!(edns.extended_rcode, 0);
+    assert!(edns.dnssec_ok);
+    assert_eq!(
+        edns.options,
+        vec![EDNSOption {
+            code: 10,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        }]
+    );
+    assert_eq!(edns.max_udp_size(), 4096);
+}
+
+#[test]
+fn writes_the_opt_record_back_byte_for_byte() {
+    let packet = DNSPacket::parse(&QUERY).unwrap();
+    assert_eq!(packet.to_bytes().unwrap(), QUERY.to_vec());
+}
+
+#[test]
+fn extended_rcodes_round_trip() {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 1, 1).unwrap());
+    packet.header = DNSHeader::from_dns_struct(&packet, 1);
+    packet.set_rcode(OPTRecord::BADVERS);
+
+    let parsed = DNSPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+    assert_eq!(parsed.rcode(), OPTRecord::BADVERS);
+    assert_eq!(parsed.header.flags.rcode, 0);
+    assert_eq!(parsed.edns.unwrap().extended_rcode, 1);
+}
+
+#[test]
+fn set_edns_counts_the_additionals_itself() {
+    // an OPT record assigned directly is not in the header count yet
+    let mut packet = DNSPacket::new();
+    packet.edns = Some(OPTRecord::new(1232));
+    packet.set_edns(None);
+    assert_eq!(packet.header.additionals_count, 0);
+
+    packet.set_edns(Some(OPTRecord::new(1232)));
+    packet.set_edns(Some(OPTRecord::new(4096)));
+    assert_eq!(packet.header.additionals_count, 1);
+}
+
+#[test]
+fn small_payload_sizes_fall_back_to_512() {
+    assert_eq!(OPTRecord::new(100).max_udp_size(), UDP_MESSAGE_SIZE);
+    assert_eq!(DNSPacket::new().max_udp_response_size(), UDP_MESSAGE_SIZE);
+}
+
+#[test]
+fn truncated_responses_keep_the_opt_record() {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 99, 1).unwrap());
+    for _ in 0..10 {
+        packet.push_answers(
+            DNSResourceRecord::new()
+                .push_name_label(String::from("example"))
+                .push_name_label(String::from("com"))
+                .rtype(99)
+                .class(1)
+                .data(DNSRecord::Unknown {
+                    record_type: 99,
+                    data_length: 100,
+                    raw_data: vec![7; 100],
+                }),
+        );
+    }
+    packet.header = DNSHeader::from_dns_struct(&packet, 1);
+    packet.set_edns(Some(OPTRecord::new(1232)));
+
+    let full = DNSPacket::parse(&packet.to_bytes().unwrap()).unwrap();
+    assert_eq!(full.answers[9].data, packet.answers[9].data);
+
+    let truncated = DNSPacket::parse(&packet.to_udp_bytes(UDP_MESSAGE_SIZE).unwrap()).unwrap();
+    assert!(truncated.header.flags.tc);
+    assert!(truncated.answers.is_empty());
+    assert_eq!(truncated.edns, Some(OPTRecord::new(1232)));
+}
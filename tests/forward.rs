@@ -0,0 +1,398 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+use joyboy_dns::config::Config;
+use joyboy_dns::server::forward::{exchange, forward};
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::server::upstreams::UpstreamPool;
+use joyboy_dns::structs::record_types::{a_record::ARecord, txt_record::TXTRecord};
+use joyboy_dns::{DNSFlags, DNSPacket, DNSRecord, DNSResourceRecord};
+
+use common::{question, silent_upstream};
+
+const TIMEOUT: Duration = Duration::from_millis(300);
+
+fn answer(query: &DNSPacket, address: [u8; 4]) -> DNSPacket {
+    let mut response = DNSPacket::response_to(query);
+    let mut record = DNSResourceRecord::new()
+        .rtype(ARecord::TYPE)
+        .class(1)
+        .ttl(60)
+        .data(DNSRecord::A(ARecord(Ipv4Addr::from(address))));
+    record.name = query.questions[0].qname.clone();
+    response.answers.push(record);
+    response.update_counts();
+    response
+}
+
+fn address(response: &DNSPacket) -> DNSRecord {
+    response.answers[0].data.clone()
+}
+
+#[tokio::test]
+async fn ignores_answers_that_do_not_match() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut buffer = [0u8; 512];
+        let (length, client) = upstream.recv_from(&mut buffer).await.unwrap();
+        let query = DNSPacket::parse(&buffer[..length]).unwrap();
+
+        // a perfect answer, but from the wrong address
+        let forged = answer(&query, [6, 6, 6, 6]);
+        spoofer
+            .send_to(&forged.to_bytes().unwrap(), client)
+            .await
+            .unwrap();
+        // from the right address, but with the wrong id
+        let mut wrong_id = answer(&query, [6, 6, 6, 6]);
+        wrong_id.header.id = query.header.id.wrapping_add(1);
+        // and with the wrong question
+        let mut wrong_question = answer(&query, [6, 6, 6, 6]);
+        wrong_question.questions[0].qtype = 28;
+        for response in [wrong_id, wrong_question, answer(&query, [192, 0, 2, 1])] {
+            upstream
+                .send_to(&response.to_bytes().unwrap(), client)
+                .await
+                .unwrap();
+        }
+    });
+
+    let response = exchange(
+        &question(42, "example.com", ARecord::TYPE),
+        address_of_upstream,
+        TIMEOUT,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.header.id, 42);
+    assert_eq!(
+        address(&response),
+        DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 1)))
+    );
+}
+
+#[tokio::test]
+async fn randomizes_ids_and_source_ports() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = upstream.local_addr().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_by_upstream = seen.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((length, client)) = upstream.recv_from(&mut buffer).await {
+            let query = DNSPacket::parse(&buffer[..length]).unwrap();
+            seen_by_upstream
+                .lock()
+                .unwrap()
+                .push((query.header.id, client.port()));
+            let response = answer(&query, [192, 0, 2, 1]);
+            let _ = upstream
+                .send_to(&response.to_bytes().unwrap(), client)
+                .await;
+        }
+    });
+
+    for _ in 0..4 {
+        let response = exchange(
+            &question(7, "example.com", ARecord::TYPE),
+            address_of_upstream,
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.header.id, 7);
+    }
+    let seen = seen.lock().unwrap();
+    let mut ids: Vec<u16> = seen.iter().map(|(id, _)| *id).collect();
+    let mut ports: Vec<u16> = seen.iter().map(|(_, port)| *port).collect();
+    ids.dedup();
+    ports.sort();
+    ports.dedup();
+    assert!(ids.len() > 1, "ids: {:?}", ids);
+    assert!(ports.len() > 1, "ports: {:?}", ports);
+}
+
+#[tokio::test]
+async fn randomizes_the_case_of_the_name() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = upstream.local_addr().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_by_upstream = seen.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((length, client)) = upstream.recv_from(&mut buffer).await {
+            let query = DNSPacket::parse(&buffer[..length]).unwrap();
+            let name = query.questions[0].to_string();
+            seen_by_upstream.lock().unwrap().push(name);
+
+            // the name echoed in the other case does not answer the query
+            let mut wrong_case = answer(&query, [6, 6, 6, 6]);
+            for label in wrong_case.questions[0].qname.iter_mut() {
+                for byte in label.value.iter_mut() {
+                    *byte = match byte.is_ascii_uppercase() {
+                        true => byte.to_ascii_lowercase(),
+                        false => byte.to_ascii_uppercase(),
+                    };
+                }
+            }
+            for response in [wrong_case, answer(&query, [192, 0, 2, 1])] {
+                let _ = upstream
+                    .send_to(&response.to_bytes().unwrap(), client)
+                    .await;
+            }
+        }
+    });
+
+    for _ in 0..4 {
+        let response = exchange(
+            &question(9, "WWW.Example.com", ARecord::TYPE),
+            address_of_upstream,
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            address(&response),
+            DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 1)))
+        );
+        // the client gets its own spelling back
+        assert_eq!(response.questions[0].qname[1].value, b"Example");
+        assert_eq!(response.answers[0].name[0].value, b"WWW");
+    }
+    let mut seen = seen.lock().unwrap().clone();
+    seen.dedup();
+    assert!(seen.len() > 1, "names: {:?}", seen);
+}
+
+#[tokio::test]
+async fn retries_when_no_upstream_answered() {
+    // drops the first query it gets and answers the rest
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        let mut dropped = false;
+        while let Ok((length, client)) = upstream.recv_from(&mut buffer).await {
+            if !dropped {
+                dropped = true;
+                continue;
+            }
+            let query = DNSPacket::parse(&buffer[..length]).unwrap();
+            let response = answer(&query, [192, 0, 2, 1]);
+            let _ = upstream
+                .send_to(&response.to_bytes().unwrap(), client)
+                .await;
+        }
+    });
+
+    let config = Config {
+        upstreams: vec![address_of_upstream],
+        upstream_timeout: TIMEOUT,
+        upstream_retries: 1,
+        ..Config::default()
+    };
+    let response = forward(
+        &question(9, "example.com", ARecord::TYPE),
+        &config,
+        &UpstreamPool::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.header.id, 9);
+
+    let config = Config {
+        upstreams: vec![silent_upstream().await],
+        upstream_retries: 0,
+        upstream_timeout: TIMEOUT,
+        ..Config::default()
+    };
+    let error = forward(
+        &question(9, "example.com", ARecord::TYPE),
+        &config,
+        &UpstreamPool::new(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+}
+
+/// an upstream refusing every query with just a header,
+/// the way some servers answer FORMERR, NOTIMP or REFUSED
+async fn refusing_upstream() -> SocketAddr {
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((length, client)) = upstream.recv_from(&mut buffer).await {
+            let query = DNSPacket::parse(&buffer[..length]).unwrap();
+            let mut refusal = DNSPacket::new();
+            refusal.header.id = query.header.id;
+            refusal.header.flags.qr = true;
+            refusal.header.flags.rcode = DNSFlags::REFUSED;
+            let _ = upstream.send_to(&refusal.to_bytes().unwrap(), client).await;
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn errors_without_a_question_fail_the_upstream_at_once() {
+    let refusing = refusing_upstream().await;
+    let started = Instant::now();
+    let error = exchange(
+        &question(5, "example.com", ARecord::TYPE),
+        refusing,
+        TIMEOUT,
+    )
+    .await
+    .unwrap_err();
+    assert_ne!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < TIMEOUT);
+
+    // the next upstream is asked right away
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        let (length, client) = upstream.recv_from(&mut buffer).await.unwrap();
+        let query = DNSPacket::parse(&buffer[..length]).unwrap();
+        let response = answer(&query, [192, 0, 2, 1]);
+        upstream
+            .send_to(&response.to_bytes().unwrap(), client)
+            .await
+            .unwrap();
+    });
+    let config = Config {
+        upstreams: vec![refusing, address_of_upstream],
+        upstream_timeout: TIMEOUT,
+        ..Config::default()
+    };
+    let started = Instant::now();
+    let response = forward(
+        &question(5, "example.com", ARecord::TYPE),
+        &config,
+        &UpstreamPool::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        address(&response),
+        DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 1)))
+    );
+    assert!(started.elapsed() < TIMEOUT);
+}
+
+#[tokio::test]
+async fn asks_again_over_tcp_when_truncated() {
+    let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = udp.local_addr().unwrap();
+    let tcp = TcpListener::bind(address_of_upstream).await.unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        let (length, client) = udp.recv_from(&mut buffer).await.unwrap();
+        let query = DNSPacket::parse(&buffer[..length]).unwrap();
+        let mut truncated = DNSPacket::response_to(&query);
+        truncated.header.flags.tc = true;
+        udp.send_to(&truncated.to_bytes().unwrap(), client)
+            .await
+            .unwrap();
+    });
+    tokio::spawn(async move {
+        let (mut stream, _) = tcp.accept().await.unwrap();
+        let length = stream.read_u16().await.unwrap() as usize;
+        let mut buffer = vec![0u8; length];
+        stream.read_exact(&mut buffer).await.unwrap();
+        let query = DNSPacket::parse(&buffer).unwrap();
+
+        let response = answer(&query, [192, 0, 2, 99]).to_bytes().unwrap();
+        let mut message = (response.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(&response);
+        stream.write_all(&message).await.unwrap();
+    });
+
+    let response = exchange(
+        &question(3, "example.com", ARecord::TYPE),
+        address_of_upstream,
+        TIMEOUT,
+    )
+    .await
+    .unwrap();
+    assert!(!response.header.flags.tc);
+    assert_eq!(
+        address(&response),
+        DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 99)))
+    );
+}
+
+#[tokio::test]
+async fn clients_get_servfail_when_no_upstream_answers() {
+    let handler = Handler::new(Config {
+        upstreams: vec![silent_upstream().await, silent_upstream().await],
+        upstream_timeout: Duration::from_millis(100),
+        ..Config::default()
+    });
+    let query = question(11, "example.com", ARecord::TYPE);
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+
+    assert_eq!(response.header.id, 11);
+    assert!(response.header.flags.qr);
+    assert!(response.header.flags.ra);
+    assert_eq!(response.header.flags.rcode, DNSFlags::SERVFAIL);
+    assert_eq!(response.questions, query.questions);
+}
+
+#[tokio::test]
+async fn passes_binary_txt_data_on_byte_for_byte() {
+    // answers by hand, so the codec of the server is not used to write it
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address_of_upstream = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 512];
+        while let Ok((length, client)) = upstream.recv_from(&mut buffer).await {
+            let query = DNSPacket::parse(&buffer[..length]).unwrap();
+            // the question is `example.com TXT IN`, 17 bytes after the header
+            let mut response = buffer[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+            response.extend_from_slice(&buffer[12..29]);
+            response.extend_from_slice(&[
+                0xC0, 0x0C, 0x00, 0x10, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x03, 0x02, 0xFF,
+                0x41,
+            ]);
+            assert_eq!(query.questions[0].qtype, TXTRecord::TYPE);
+            let _ = upstream.send_to(&response, client).await;
+        }
+    });
+
+    let handler = Handler::new(Config {
+        upstreams: vec![address_of_upstream],
+        upstream_timeout: TIMEOUT,
+        ..Config::default()
+    });
+    let query = question(12, "example.com", TXTRecord::TYPE);
+
+    // the second answer comes from the cache
+    for _ in 0..2 {
+        let response = handler
+            .handle(&query.to_bytes().unwrap(), Transport::Udp)
+            .await
+            .unwrap();
+        assert!(response.ends_with(&[0x00, 0x03, 0x02, 0xFF, 0x41]));
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(
+            response.answers[0].data,
+            DNSRecord::TXT(TXTRecord(vec![vec![0xFF, 0x41]]))
+        );
+    }
+}
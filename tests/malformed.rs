@@ -0,0 +1,151 @@
+use joyboy_dns::structs::record_types::txt_record::TXTRecord;
+use joyboy_dns::{
+    DNSFlags, DNSHeader, DNSLabel, DNSPacket, DNSPacketBuffer, DNSQuestion, DNSRecord,
+    DNSResourceRecord, ParseError, WriteError,
+};
+
+const HEADER: [u8; 12] = [
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// the labels of the text split at every dot, unchecked,
+/// so names the writer has to refuse can be built
+fn raw_labels(text: &str) -> Vec<DNSLabel> {
+    text.split('.')
+        .map(|label| DNSLabel {
+            value: label.as_bytes().to_vec(),
+        })
+        .collect()
+}
+
+fn with_question(name: &[u8]) -> Vec<u8> {
+    let mut bytes = HEADER.to_vec();
+    bytes.extend_from_slice(name);
+    bytes.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+    bytes
+}
+
+#[test]
+fn every_truncation_of_a_response_is_an_error() {
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion::from_simple_string("www.example.com", 1, 1).unwrap());
+    packet.push_answers(
+        DNSResourceRecord::new()
+            .push_name_label(String::from("www"))
+            .push_name_label(String::from("example"))
+            .push_name_label(String::from("com"))
+            .class(1)
+            .data(DNSRecord::TXT(TXTRecord(vec!["some text".into()])))
+            .rtype(16),
+    );
+    packet.header = DNSHeader::from_dns_struct(&packet, 1);
+    let bytes = packet.to_bytes().unwrap();
+
+    for length in 0..bytes.len() {
+        assert!(DNSPacket::parse(&bytes[..length]).is_err());
+    }
+    assert!(DNSPacket::parse(&bytes).is_ok());
+}
+
+#[test]
+fn short_header_is_truncated() {
+    assert_eq!(
+        DNSPacket::parse(&HEADER[..5]),
+        Err(ParseError::Truncated {
+            offset: 0,
+            needed: 12
+        })
+    );
+}
+
+#[test]
+fn pointer_to_itself_is_a_loop() {
+    let bytes = with_question(&[0xC0, 0x0C]);
+    assert_eq!(
+        DNSPacket::parse(&bytes),
+        Err(ParseError::PointerLoop { offset: 12 })
+    );
+}
+
+#[test]
+fn forward_pointer_is_rejected() {
+    let bytes = with_question(&[0x01, b'a', 0xC0, 0x20]);
+    assert_eq!(
+        DNSPacket::parse(&bytes),
+        Err(ParseError::PointerLoop { offset: 14 })
+    );
+}
+
+#[test]
+fn reserved_label_length_is_rejected() {
+    let bytes = with_question(&[0x40, b'a', 0x00]);
+    assert_eq!(
+        DNSPacket::parse(&bytes),
+        Err(ParseError::LabelTooLong {
+            offset: 12,
+            length: 0x40
+        })
+    );
+}
+
+#[test]
+fn names_over_255_bytes_are_rejected() {
+    let mut name = vec![];
+    for _ in 0..5 {
+        name.push(63);
+        name.extend_from_slice(&[b'a'; 63]);
+    }
+    name.push(0);
+
+    assert_eq!(
+        DNSPacket::parse(&with_question(&name)),
+        Err(ParseError::NameTooLong { offset: 12 })
+    );
+}
+
+#[test]
+fn writes_fail_instead_of_overflowing() {
+    let mut buffer = DNSPacketBuffer::with_max_size(3);
+    assert!(buffer.write_u16(1).is_ok());
+    assert_eq!(
+        buffer.write_u16(1),
+        Err(WriteError::BufferFull { max_size: 3 })
+    );
+
+    let long_label = "a".repeat(64);
+    let mut buffer = DNSPacketBuffer::empty();
+    assert_eq!(
+        buffer.push_labels(&raw_labels(&long_label)),
+        Err(WriteError::LabelTooLong { label: long_label })
+    );
+
+    // a trailing dot or two dots in a row would end the name early
+    for name in ["example.com.", "a..b"] {
+        assert_eq!(
+            buffer.push_labels(&raw_labels(name)),
+            Err(WriteError::EmptyLabel {
+                name: name.to_string()
+            })
+        );
+    }
+
+    let long_name = vec!["a".repeat(63); 5].join(".");
+    assert!(matches!(
+        buffer.push_labels(&raw_labels(&long_name)),
+        Err(WriteError::NameTooLong { .. })
+    ));
+}
+
+#[test]
+fn malformed_queries_get_a_format_error() {
+    let bytes = with_question(&[0xC0, 0x0C]);
+    let response = DNSPacket::error_response(&bytes, DNSFlags::FORMERR).unwrap();
+
+    assert_eq!(response.header.id, 0x1234);
+    assert!(response.header.flags.qr);
+    assert!(response.header.flags.rd);
+    assert_eq!(response.header.flags.rcode, DNSFlags::FORMERR);
+    assert!(response.questions.is_empty());
+
+    assert!(DNSPacket::error_response(&[0x12], DNSFlags::FORMERR).is_none());
+}
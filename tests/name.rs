@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+
+use joyboy_dns::{DNSLabel, DNSPacket, DNSQuestion, Name, NameError};
+
+fn name(text: &str) -> Name {
+    text.parse().unwrap()
+}
+
+#[test]
+fn reads_and_writes_text() {
+    assert_eq!(name("www.example.com").to_string(), "www.example.com.");
+    assert_eq!(name("www.example.com.").to_string(), "www.example.com.");
+    assert_eq!(name(".").to_string(), ".");
+    assert!(name(".").is_root());
+
+    let escaped = name("a\\.b.c\\046d.\\000\\255");
+    assert_eq!(
+        escaped.labels(),
+        [b"a.b".to_vec(), b"c.d".to_vec(), vec![0, 255]]
+    );
+    assert_eq!(escaped.to_string(), "a\\.b.c\\.d.\\000\\255.");
+    assert_eq!(name(&escaped.to_string()), escaped);
+
+    let origin = name("example.com");
+    assert_eq!(
+        Name::parse_relative("www", &origin).unwrap().to_string(),
+        "www.example.com."
+    );
+    assert_eq!(
+        Name::parse_relative("www.example.org.", &origin)
+            .unwrap()
+            .to_string(),
+        "www.example.org."
+    );
+}
+
+#[test]
+fn rejects_invalid_names() {
+    for text in ["", "a..b", ".a", "a.b..", "a\\", "a\\25", "a\\256"] {
+        assert!(
+            matches!(text.parse::<Name>(), Err(NameError::Invalid { .. })),
+            "{:?}",
+            text
+        );
+    }
+    assert!(matches!(
+        "a".repeat(64).parse::<Name>(),
+        Err(NameError::LabelTooLong { .. })
+    ));
+    assert_eq!(name(&"a".repeat(63)).wire_length(), 65);
+    // 127 labels of one byte take up 255 bytes, one more is too many
+    assert_eq!(name(&["a"; 127].join(".")).wire_length(), 255);
+    assert!(matches!(
+        ["a"; 128].join(".").parse::<Name>(),
+        Err(NameError::NameTooLong { .. })
+    ));
+    assert!(Name::new(vec![vec![]]).is_err());
+    assert!(name(&["a"; 127].join(".")).child(b"b").is_err());
+}
+
+#[test]
+fn compares_without_regard_to_case() {
+    assert_eq!(name("Google.COM"), name("google.com."));
+    assert_ne!(name("google.com"), name("google.co"));
+    assert_ne!(name("a.b"), name("ab"));
+
+    let names: HashSet<Name> = ["Google.COM", "google.com", "GOOGLE.com."]
+        .into_iter()
+        .map(name)
+        .collect();
+    assert_eq!(names.len(), 1);
+    // the spelling is kept all the same
+    assert_eq!(name("Google.COM").to_string(), "Google.COM.");
+    assert_eq!(name("Google.COM").to_lowercase().to_string(), "google.com.");
+}
+
+#[test]
+fn labels_compare_without_regard_to_case() {
+    let question = |text: &str| DNSQuestion::from_simple_string(text, 1, 1).unwrap();
+    assert_eq!(question("WWW.Example.com"), question("www.example.COM"));
+    assert_ne!(question("www.example.com"), question("www.example.org"));
+    assert_eq!(
+        name("Example.COM").to_labels(),
+        name("example.com").to_labels()
+    );
+    assert_ne!(name("a\\.b").to_labels(), name("a.b").to_labels());
+
+    let labels: HashSet<DNSLabel> = ["a", "A", "b"]
+        .into_iter()
+        .map(|label| DNSLabel {
+            value: label.as_bytes().to_vec(),
+        })
+        .collect();
+    assert_eq!(labels.len(), 2);
+}
+
+#[test]
+fn sorts_in_canonical_order() {
+    // RFC 4034 section 6.1
+    let sorted = [
+        "example",
+        "a.example",
+        "yljkjljk.a.example",
+        "Z.a.example",
+        "zABC.a.EXAMPLE",
+        "z.example",
+        "\\001.z.example",
+        "*.z.example",
+        "\\200.z.example",
+    ];
+    let mut names: Vec<Name> = sorted.iter().rev().map(|text| name(text)).collect();
+    names.sort();
+    let expected: Vec<Name> = sorted.iter().map(|text| name(text)).collect();
+    assert_eq!(names, expected);
+    assert!(name(".") < name("example"));
+}
+
+#[test]
+fn walks_up_and_down_the_tree() {
+    let www = name("www.Example.com");
+    assert_eq!(www.label_count(), 3);
+    assert_eq!(www.parent(), Some(name("example.com")));
+    assert_eq!(name("com").parent(), Some(Name::root()));
+    assert_eq!(Name::root().parent(), None);
+    assert_eq!(name("example.com").child(b"www").unwrap(), www);
+    assert_eq!(www.suffix(2), name("example.com"));
+    assert_eq!(www.suffix(5), www);
+
+    assert!(www.is_subdomain_of(&name("EXAMPLE.com")));
+    assert!(www.is_subdomain_of(&www));
+    assert!(www.is_subdomain_of(&Name::root()));
+    assert!(!www.is_subdomain_of(&name("ample.com")));
+    assert_eq!(www.strip_suffix(&name("example.com")), Some(name("www")));
+    assert_eq!(www.strip_suffix(&name("example.org")), None);
+    assert_eq!(
+        name("www").append(&name("example.com")).unwrap(),
+        name("www.example.com")
+    );
+}
+
+#[test]
+fn converts_to_and_from_labels() {
+    let raw = |labels: &[&str]| -> Vec<DNSLabel> {
+        labels
+            .iter()
+            .map(|label| DNSLabel {
+                value: label.as_bytes().to_vec(),
+            })
+            .collect()
+    };
+    let labels = raw(&["www", "example", "com"]);
+    let www = Name::from_labels(&labels).unwrap();
+    assert_eq!(www, name("www.example.com"));
+    assert_eq!(www.to_labels(), labels);
+    assert!(Name::from_labels(&raw(&["a", "", "b"])).is_err());
+}
+
+#[test]
+fn questions_are_read_like_names() {
+    let question = |text: &str| DNSQuestion::from_simple_string(text, 1, 1);
+    assert_eq!(
+        question("example.com.").unwrap().qname,
+        name("example.com").to_labels()
+    );
+    assert_eq!(
+        question("a\\.b.example").unwrap().qname,
+        name("a\\.b.example").to_labels()
+    );
+    assert!(matches!(question("a..b"), Err(NameError::Invalid { .. })));
+    assert!(matches!(
+        question(&"a".repeat(64)),
+        Err(NameError::LabelTooLong { .. })
+    ));
+}
+
+#[test]
+fn keeps_every_byte_of_a_label_on_the_wire() {
+    let raw = name("caf\\233.\\000\\128\\255.example");
+    let mut packet = DNSPacket::new();
+    packet.push_questions(DNSQuestion {
+        qname: raw.to_labels(),
+        qtype: 1,
+        qclass: 1,
+    });
+    packet.update_counts();
+
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(&bytes[12..22], b"\x04caf\xE9\x03\x00\x80\xFF\x07");
+    let parsed = DNSPacket::parse(&bytes).unwrap();
+    assert_eq!(Name::from_labels(&parsed.questions[0].qname).unwrap(), raw);
+    assert_eq!(parsed.to_bytes().unwrap(), bytes);
+    assert_eq!(
+        parsed.questions[0].to_string(),
+        "caf\\233.\\000\\128\\255.example.\tIN\tA"
+    );
+}
+
+#[test]
+fn text_is_written_as_utf8() {
+    for text in ["b\u{fc}cher.de", "b\u{20ac}cher.de"] {
+        let question = DNSQuestion::from_simple_string(text, 1, 1).unwrap();
+        assert_eq!(question.qname, name(text).to_labels());
+
+        let mut packet = DNSPacket::new();
+        packet.push_questions(question);
+        packet.update_counts();
+        let bytes = packet.to_bytes().unwrap();
+        let label = text.split('.').next().unwrap();
+        assert_eq!(bytes[12] as usize, label.len());
+        assert_eq!(&bytes[13..13 + label.len()], label.as_bytes());
+    }
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::net::UdpSocket;
+
+use joyboy_dns::config::Config;
+use joyboy_dns::options::{parse_upstream, Options};
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::DNSPacket;
+
+use common::{fake_upstream, query};
+
+#[test]
+fn upstream_port_defaults_to_53() {
+    let cases = [
+        ("1.1.1.1", "1.1.1.1:53"),
+        ("1.1.1.1:5353", "1.1.1.1:5353"),
+        ("2606:4700::1111", "[2606:4700::1111]:53"),
+        ("[2606:4700::1111]:5353", "[2606:4700::1111]:5353"),
+    ];
+    for (value, expected) in cases {
+        let expected: SocketAddr = expected.parse().unwrap();
+        assert_eq!(parse_upstream(value), Ok(expected));
+    }
+    assert!(parse_upstream("dns.google").is_err());
+}
+
+#[test]
+fn defaults() {
+    let options = Options::try_parse_from(["joyboy_dns"]).unwrap();
+    assert_eq!(options.listen, vec!["127.0.0.1:5300".parse().unwrap()]);
+    assert_eq!(options.upstreams, vec!["8.8.4.4:53".parse().unwrap()]);
+    assert_eq!(options.upstream_timeout(), Duration::from_secs(5));
+    assert_eq!(options.tcp_idle_timeout(), Duration::from_secs(10));
+    assert_eq!(options.log_level, tracing::Level::INFO);
+}
+
+#[test]
+fn multiple_listeners_and_upstreams() {
+    let options = Options::try_parse_from([
+        "joyboy_dns",
+        "--listen",
+        "127.0.0.1:53,[::1]:53",
+        "-l",
+        "0.0.0.0:5353",
+        "--upstream",
+        "1.1.1.1",
+        "-u",
+        "2606:4700::1111",
+        "--upstream-timeout",
+        "250",
+        "--log-level",
+        "debug",
+    ])
+    .unwrap();
+
+    let listen: Vec<SocketAddr> = ["127.0.0.1:53", "[::1]:53", "0.0.0.0:5353"]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+    assert_eq!(options.listen, listen);
+    let upstreams: Vec<SocketAddr> = ["1.1.1.1:53", "[2606:4700::1111]:53"]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+    assert_eq!(options.upstreams, upstreams);
+    assert_eq!(options.upstream_timeout(), Duration::from_millis(250));
+    assert_eq!(options.log_level, tracing::Level::DEBUG);
+}
+
+#[test]
+fn rejects_bad_addresses() {
+    assert!(Options::try_parse_from(["joyboy_dns", "--listen", "localhost"]).is_err());
+    assert!(Options::try_parse_from(["joyboy_dns", "--upstream", "1.1.1.1:x"]).is_err());
+}
+
+#[test]
+fn rejects_zero_limits() {
+    for flag in [
+        "--upstream-timeout",
+        "--tcp-idle-timeout",
+        "--max-queries",
+        "--max-tcp-connections",
+    ] {
+        assert!(
+            Options::try_parse_from(["joyboy_dns", flag, "0"]).is_err(),
+            "{}",
+            flag
+        );
+    }
+}
+
+#[tokio::test]
+async fn fails_over_to_the_next_upstream() {
+    // an upstream that never answers
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let working = fake_upstream(Duration::ZERO).await;
+
+    let handler = Handler::new(Config {
+        upstreams: vec![silent.local_addr().unwrap(), working],
+        upstream_timeout: Duration::from_millis(200),
+        ..Config::default()
+    });
+    let response = handler
+        .handle(&query(7, "example.org"), Transport::Udp)
+        .await
+        .expect("the second upstream should answer");
+
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.id, 7);
+    assert!(response.header.flags.qr);
+}
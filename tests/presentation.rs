@@ -0,0 +1,230 @@
+mod common;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use joyboy_dns::records::RecordStore;
+use joyboy_dns::structs::presentation::{rcode_name, type_name};
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord, mx_record::MXRecord,
+    ns_record::NSRecord, ptr_record::PTRRecord, soa_record::SOARecord, srv_record::SRVRecord,
+    txt_record::TXTRecord,
+};
+use joyboy_dns::zone::{parse_name, parse_zone};
+use joyboy_dns::{
+    DNSLabel, DNSPacket, DNSQuestion, DNSRecord, DNSResourceRecord, EDNSOption, OPTRecord,
+};
+
+use common::{labels, record};
+
+fn records() -> Vec<DNSResourceRecord> {
+    vec![
+        record(
+            "example.com",
+            ARecord::TYPE,
+            300,
+            DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 1))),
+        ),
+        record(
+            "example.com",
+            AAAARecord::TYPE,
+            300,
+            DNSRecord::AAAA(AAAARecord("2001:db8::1".parse::<Ipv6Addr>().unwrap())),
+        ),
+        record(
+            "www.example.com",
+            CNAMERecord::TYPE,
+            60,
+            DNSRecord::CNAME(CNAMERecord(labels("example.com"))),
+        ),
+        record(
+            "example.com",
+            NSRecord::TYPE,
+            86400,
+            DNSRecord::NS(NSRecord(labels("ns1.example.com"))),
+        ),
+        record(
+            "1.2.0.192.in-addr.arpa",
+            PTRRecord::TYPE,
+            3600,
+            DNSRecord::PTR(PTRRecord(labels("example.com"))),
+        ),
+        record(
+            "example.com",
+            MXRecord::TYPE,
+            3600,
+            DNSRecord::MX(MXRecord {
+                preference: 10,
+                mail_exchange: labels("mail.example.com"),
+            }),
+        ),
+        record(
+            "example.com",
+            TXTRecord::TYPE,
+            300,
+            DNSRecord::TXT(TXTRecord(vec![
+                "v=spf1 -all".into(),
+                "say \"hi\"; \\ \u{7}".into(),
+            ])),
+        ),
+        record(
+            "example.com",
+            TXTRecord::TYPE,
+            300,
+            // UTF-8 is written byte by byte, like data that is not text
+            DNSRecord::TXT(TXTRecord(vec![vec![0xC3, 0xA9], vec![0xFF, 0x00]])),
+        ),
+        record(
+            "_sip._udp.example.com",
+            SRVRecord::TYPE,
+            300,
+            DNSRecord::SRV(SRVRecord {
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                target: labels("sip.example.com"),
+            }),
+        ),
+        record(
+            "example.com",
+            SOARecord::TYPE,
+            3600,
+            DNSRecord::SOA(SOARecord {
+                primary_ns: labels("ns1.example.com"),
+                responsible_email: parse_name("host\\.master.example.com.", &[]).unwrap(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum_ttl: 300,
+            }),
+        ),
+        record(
+            "example.com",
+            999,
+            300,
+            DNSRecord::Unknown {
+                record_type: 999,
+                data_length: 3,
+                raw_data: vec![0xAB, 0xCD, 0xEF],
+            },
+        ),
+    ]
+}
+
+#[test]
+fn writes_every_record_type() {
+    let lines: Vec<String> = records().iter().map(|record| record.to_string()).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "example.com.\t300\tIN\tA\t192.0.2.1",
+            "example.com.\t300\tIN\tAAAA\t2001:db8::1",
+            "www.example.com.\t60\tIN\tCNAME\texample.com.",
+            "example.com.\t86400\tIN\tNS\tns1.example.com.",
+            "1.2.0.192.in-addr.arpa.\t3600\tIN\tPTR\texample.com.",
+            "example.com.\t3600\tIN\tMX\t10 mail.example.com.",
+            "example.com.\t300\tIN\tTXT\t\"v=spf1 -all\" \"say \\\"hi\\\"; \\\\ \\007\"",
+            "example.com.\t300\tIN\tTXT\t\"\\195\\169\" \"\\255\\000\"",
+            "_sip._udp.example.com.\t300\tIN\tSRV\t10 60 5060 sip.example.com.",
+            "example.com.\t3600\tIN\tSOA\tns1.example.com. host\\.master.example.com. \
+             2024010101 7200 900 1209600 300",
+            "example.com.\t300\tIN\tTYPE999\t\\# 3 ABCDEF",
+        ]
+    );
+}
+
+#[test]
+fn reads_back_what_it_writes() {
+    for record in records() {
+        let text = record.to_string();
+        let parsed = parse_zone(&text, &[]).unwrap_or_else(|e| panic!("{}: {}", text, e));
+        assert_eq!(parsed, vec![record], "{}", text);
+    }
+}
+
+#[test]
+fn escapes_names() {
+    let name = vec![
+        DNSLabel {
+            value: b"a.b c".to_vec(),
+        },
+        DNSLabel {
+            value: b"@$".to_vec(),
+        },
+    ];
+    let mut record = record(
+        ".",
+        ARecord::TYPE,
+        1,
+        DNSRecord::A(ARecord(Ipv4Addr::LOCALHOST)),
+    );
+    record.name = name;
+    assert_eq!(
+        record.to_string(),
+        "a\\.b\\032c.\\@\\$.\t1\tIN\tA\t127.0.0.1"
+    );
+    assert_eq!(parse_zone(&record.to_string(), &[]).unwrap(), vec![record]);
+
+    let root = DNSQuestion::from_simple_string("x", 2, 1).unwrap();
+    assert_eq!(root.to_string(), "x.\tIN\tNS");
+}
+
+#[test]
+fn writes_packets_like_dig() {
+    let mut packet = DNSPacket::new();
+    packet.header.id = 4242;
+    packet.header.flags.qr = true;
+    packet.header.flags.rd = true;
+    packet.header.flags.ra = true;
+    packet.push_questions(DNSQuestion::from_simple_string("example.com", 1, 1).unwrap());
+    packet.push_answers(records().remove(0));
+    packet.push_authorities(records().remove(3));
+    let mut edns = OPTRecord::new(1232);
+    edns.dnssec_ok = true;
+    edns.options.push(EDNSOption {
+        code: 10,
+        data: vec![1, 2],
+    });
+    packet.set_edns(Some(edns.clone()));
+    packet.set_rcode(3);
+
+    assert_eq!(
+        packet.to_string(),
+        ";; ->>HEADER<<- opcode: QUERY, status: NXDOMAIN, id: 4242\n\
+         ;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 1, ADDITIONAL: 1\n\
+         \n\
+         ;; OPT PSEUDOSECTION:\n\
+         ; EDNS: version: 0, flags: do; udp: 1232\n\
+         \n\
+         ;; QUESTION SECTION:\n\
+         ;example.com.\tIN\tA\n\
+         \n\
+         ;; ANSWER SECTION:\n\
+         example.com.\t300\tIN\tA\t192.0.2.1\n\
+         \n\
+         ;; AUTHORITY SECTION:\n\
+         example.com.\t86400\tIN\tNS\tns1.example.com.\n"
+    );
+    assert_eq!(edns.to_string(), "\\# 6 000A00020102");
+}
+
+#[test]
+fn names_of_types_and_codes() {
+    assert_eq!(type_name(28), "AAAA");
+    assert_eq!(type_name(255), "ANY");
+    assert_eq!(type_name(65000), "TYPE65000");
+    assert_eq!(DNSRecord::type_from_name("type65000"), Some(65000));
+    assert_eq!(rcode_name(2), "SERVFAIL");
+    assert_eq!(rcode_name(16), "BADVERS");
+}
+
+#[test]
+fn dumps_a_record_store() {
+    let store: RecordStore = records().into_iter().take(3).collect();
+    assert_eq!(
+        store.to_string(),
+        "example.com.\t300\tIN\tA\t192.0.2.1\n\
+         example.com.\t300\tIN\tAAAA\t2001:db8::1\n\
+         www.example.com.\t60\tIN\tCNAME\texample.com.\n"
+    );
+}
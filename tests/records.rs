@@ -0,0 +1,216 @@
+mod common;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use joyboy_dns::config::Config;
+use joyboy_dns::records::{RecordStore, QTYPE_ANY};
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord, mx_record::MXRecord,
+};
+use joyboy_dns::{
+    DNSFlags, DNSPacket, DNSRecord, DNSResourceRecord, OPTRecord, EDNS_UDP_PAYLOAD_SIZE,
+};
+
+use common::{fake_upstream, labels, query, question, record};
+
+fn a(name: &str, ttl: u32, address: [u8; 4]) -> DNSResourceRecord {
+    record(
+        name,
+        ARecord::TYPE,
+        ttl,
+        DNSRecord::A(ARecord(Ipv4Addr::from(address))),
+    )
+}
+
+fn store() -> RecordStore {
+    [
+        a("web.lan", 60, [10, 0, 0, 1]),
+        a("web.lan", 60, [10, 0, 0, 2]),
+        a("web.lan", 60, [10, 0, 0, 2]),
+        record(
+            "web.lan",
+            AAAARecord::TYPE,
+            120,
+            DNSRecord::AAAA(AAAARecord(Ipv6Addr::LOCALHOST)),
+        ),
+        record(
+            "lan",
+            MXRecord::TYPE,
+            3600,
+            DNSRecord::MX(MXRecord {
+                preference: 10,
+                mail_exchange: labels("mail.lan"),
+            }),
+        ),
+        record(
+            "www.lan",
+            CNAMERecord::TYPE,
+            300,
+            DNSRecord::CNAME(CNAMERecord(labels("web.lan"))),
+        ),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[test]
+fn rrsets() {
+    let store = store();
+    // the duplicate A record is only stored once
+    assert_eq!(store.len(), 5);
+
+    let rrset = store.lookup(&labels("web.lan"), ARecord::TYPE).unwrap();
+    let addresses: Vec<&DNSRecord> = rrset.iter().map(|record| &record.data).collect();
+    assert_eq!(
+        addresses,
+        vec![
+            &DNSRecord::A(ARecord(Ipv4Addr::new(10, 0, 0, 1))),
+            &DNSRecord::A(ARecord(Ipv4Addr::new(10, 0, 0, 2))),
+        ]
+    );
+
+    // names compare case-insensitively
+    let rrset = store.lookup(&labels("WEB.Lan"), AAAARecord::TYPE).unwrap();
+    assert_eq!(rrset.len(), 1);
+    assert_eq!(rrset[0].ttl, 120);
+
+    assert_eq!(
+        store.lookup(&labels("web.lan"), QTYPE_ANY).unwrap().len(),
+        3
+    );
+    // the name exists, but has no records of the type
+    assert_eq!(
+        store.lookup(&labels("web.lan"), MXRecord::TYPE),
+        Some(vec![])
+    );
+    assert_eq!(store.lookup(&labels("other.lan"), ARecord::TYPE), None);
+
+    // a CNAME answers for every type
+    let rrset = store.lookup(&labels("www.lan"), ARecord::TYPE).unwrap();
+    assert_eq!(rrset.len(), 1);
+    assert_eq!(rrset[0].rtype, CNAMERecord::TYPE);
+}
+
+async fn handler() -> Handler {
+    Handler::new(Config {
+        upstreams: vec![fake_upstream(Duration::ZERO).await],
+        records: store(),
+        ..Config::default()
+    })
+}
+
+async fn ask(handler: &Handler, query: &DNSPacket) -> DNSPacket {
+    let response = handler
+        .handle(&query.to_bytes().unwrap(), Transport::Udp)
+        .await
+        .unwrap();
+    DNSPacket::parse(&response).unwrap()
+}
+
+#[tokio::test]
+async fn answers_local_records_authoritatively() {
+    let handler = handler().await;
+    let response = ask(&handler, &question(21, "web.lan", ARecord::TYPE)).await;
+
+    assert_eq!(response.header.id, 21);
+    let flags = &response.header.flags;
+    assert!(flags.qr);
+    assert!(flags.aa);
+    assert!(flags.rd);
+    assert!(!flags.tc);
+    assert_eq!(flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(response.questions, question(21, "web.lan", 1).questions);
+    assert_eq!(response.answers.len(), 2);
+    assert!(response.answers.iter().all(|answer| answer.ttl == 60));
+
+    let response = ask(&handler, &question(22, "lan", MXRecord::TYPE)).await;
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].ttl, 3600);
+}
+
+#[tokio::test]
+async fn answers_other_opcodes_with_notimp() {
+    let handler = handler().await;
+    // an IQUERY, then a NOTIFY
+    for opcode in [1, 4] {
+        let mut query = question(25, "web.lan", ARecord::TYPE);
+        query.header.flags.opcode = opcode;
+        let response = ask(&handler, &query).await;
+
+        assert_eq!(response.header.id, 25);
+        assert_eq!(response.header.flags.opcode, opcode);
+        assert_eq!(response.header.flags.rcode, DNSFlags::NOTIMP);
+        assert!(response.answers.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn answers_formerr_unless_there_is_one_question() {
+    let handler = handler().await;
+    let mut none = question(27, "web.lan", ARecord::TYPE);
+    none.questions.clear();
+    none.update_counts();
+    let mut two = question(28, "web.lan", ARecord::TYPE);
+    two.questions.push(two.questions[0].clone());
+    two.update_counts();
+
+    for (id, query) in [(27, none), (28, two)] {
+        let response = ask(&handler, &query).await;
+        assert_eq!(response.header.id, id);
+        assert_eq!(response.header.flags.rcode, DNSFlags::FORMERR);
+        assert!(response.answers.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn drops_responses() {
+    let handler = handler().await;
+    let mut response = question(26, "web.lan", ARecord::TYPE);
+    response.header.flags.qr = true;
+    let bytes = response.to_bytes().unwrap();
+    assert!(handler.handle(&bytes, Transport::Udp).await.is_none());
+    // malformed ones too, instead of answering FORMERR
+    assert!(handler.handle(&bytes[..14], Transport::Udp).await.is_none());
+}
+
+#[tokio::test]
+async fn answers_nodata_for_other_types() {
+    let handler = handler().await;
+    let response = ask(&handler, &question(23, "lan", ARecord::TYPE)).await;
+
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert!(response.answers.is_empty());
+}
+
+#[tokio::test]
+async fn keeps_edns_on_local_answers() {
+    let handler = handler().await;
+    let mut query = question(24, "web.lan", AAAARecord::TYPE);
+    query.set_edns(Some(OPTRecord::new(4096)));
+
+    let response = ask(&handler, &query).await;
+    assert!(response.header.flags.aa);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(
+        response.edns.map(|edns| edns.udp_payload_size),
+        Some(EDNS_UDP_PAYLOAD_SIZE)
+    );
+}
+
+#[tokio::test]
+async fn forwards_unknown_names() {
+    let handler = handler().await;
+    let response = handler
+        .handle(&query(25, "example.com"), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+
+    // answered by the fake upstream, which never sets aa
+    assert_eq!(response.header.id, 25);
+    assert!(!response.header.flags.aa);
+    assert!(response.header.flags.ra);
+}
@@ -0,0 +1,497 @@
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use joyboy_dns::config::{Config, ConfigFormat};
+use joyboy_dns::records::RecordStore;
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::server::resolver::{BoxFuture, Resolver, ResolverTransport};
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, cname_record::CNAMERecord, soa_record::SOARecord,
+};
+use joyboy_dns::zone::parse_zone;
+use joyboy_dns::{DNSFlags, DNSPacket, DNSRecord, Name};
+
+use common::{query, question};
+
+const ROOT: &str = "\
+$ORIGIN .
+$TTL 86400
+@                       SOA     a.root-servers.test. hostmaster.test. 1 1800 900 604800 86400
+@                       NS      a.root-servers.test.
+com.                    NS      a.gtld.com.
+a.gtld.com.             A       10.0.1.1
+net.                    NS      a.gtld.net.
+a.gtld.net.             A       10.0.2.1
+";
+
+const COM: &str = "\
+$ORIGIN com.
+$TTL 172800
+@                       SOA     a.gtld hostmaster.gtld 1 1800 900 604800 900
+@                       NS      a.gtld
+a.gtld                  A       10.0.1.1
+example                 NS      ns1.example
+ns1.example             A       10.0.3.1
+outside                 NS      ns.hosting.net.
+v6                      NS      ns.v6
+ns.v6                   AAAA    fd00::6
+glueless6               NS      ns.hosting6.net.
+evil                    NS      ns.evil
+ns.evil                 A       10.0.5.1
+lame                    NS      ns1.lame
+lame                    NS      ns2.lame
+ns1.lame                A       10.0.6.1
+ns2.lame                A       10.0.6.2
+";
+
+const NET: &str = "\
+$ORIGIN net.
+$TTL 172800
+@                       SOA     a.gtld hostmaster.gtld 1 1800 900 604800 900
+@                       NS      a.gtld
+a.gtld                  A       10.0.2.1
+hosting                 NS      ns.hosting
+ns.hosting              A       10.0.4.1
+hosting6                NS      ns.hosting6
+ns.hosting6             AAAA    fd00::7
+";
+
+const EXAMPLE: &str = "\
+$ORIGIN example.com.
+$TTL 3600
+@                       SOA     ns1 hostmaster 1 7200 900 1209600 300
+@                       NS      ns1
+ns1                     A       10.0.3.1
+www                     A       192.0.2.80
+alias                   CNAME   www.outside.com.
+a.b.deep                A       192.0.2.5
+";
+
+/// served by the same server as hosting.net
+const HOSTING: &str = "\
+$ORIGIN hosting.net.
+$TTL 3600
+@                       SOA     ns hostmaster 1 7200 900 1209600 300
+@                       NS      ns
+ns                      A       10.0.4.1
+$ORIGIN outside.com.
+@                       SOA     ns.hosting.net. hostmaster.hosting.net. 1 7200 900 1209600 300
+@                       NS      ns.hosting.net.
+www                     A       198.51.100.1
+";
+
+/// served by a name server with only an IPv6 address, given as glue
+const V6: &str = "\
+$ORIGIN v6.com.
+$TTL 3600
+@                       SOA     ns hostmaster 1 7200 900 1209600 300
+@                       NS      ns
+ns                      AAAA    fd00::6
+www                     A       192.0.2.6
+";
+
+/// served by a name server with only an IPv6 address, found
+/// by looking up the name server
+const HOSTING6: &str = "\
+$ORIGIN hosting6.net.
+$TTL 3600
+@                       SOA     ns hostmaster 1 7200 900 1209600 300
+@                       NS      ns
+ns                      AAAA    fd00::7
+$ORIGIN glueless6.com.
+@                       SOA     ns.hosting6.net. hostmaster.hosting6.net. 1 7200 900 1209600 300
+@                       NS      ns.hosting6.net.
+www                     A       192.0.2.7
+";
+
+/// a server slipping an address for a name of another zone in
+const EVIL: &str = "\
+$ORIGIN evil.com.
+$TTL 3600
+@                       SOA     ns hostmaster 1 7200 900 1209600 300
+@                       NS      ns
+ns                      A       10.0.5.1
+www                     CNAME   www.example.com.
+";
+const FORGED: &str = "www.example.com. 3600 A 6.6.6.6\n";
+
+/// served by its second name server only, the first one is lame
+const LAME: &str = "\
+$ORIGIN lame.com.
+$TTL 3600
+@                       SOA     ns2 hostmaster 1 7200 900 1209600 300
+@                       NS      ns1
+@                       NS      ns2
+ns1                     A       10.0.6.1
+ns2                     A       10.0.6.2
+www                     A       192.0.2.9
+";
+
+/// name servers answering from zones in memory, by address.
+/// Addresses without zones do not answer at all, broken ones
+/// answer NXDOMAIN instead of NODATA, and lame ones answer
+/// everything with an empty, non-authoritative NOERROR.
+#[derive(Default)]
+struct FakeServers {
+    zones: HashMap<IpAddr, RecordStore>,
+    broken: HashSet<IpAddr>,
+    lame: HashSet<IpAddr>,
+    /// the server and name of every query
+    queries: Mutex<Vec<(IpAddr, Name)>>,
+}
+
+impl FakeServers {
+    fn internet() -> Self {
+        let mut servers = Self::default();
+        for (address, zone) in [
+            ([10, 0, 0, 1], ROOT),
+            ([10, 0, 1, 1], COM),
+            ([10, 0, 2, 1], NET),
+            ([10, 0, 3, 1], EXAMPLE),
+            ([10, 0, 4, 1], HOSTING),
+            ([10, 0, 5, 1], EVIL),
+            ([10, 0, 6, 2], LAME),
+        ] {
+            let mut records = parse_zone(zone, &[]).unwrap();
+            if zone == EVIL {
+                records.extend(parse_zone(FORGED, &[]).unwrap());
+            }
+            let store = records.into_iter().collect();
+            servers
+                .zones
+                .insert(IpAddr::V4(Ipv4Addr::from(address)), store);
+        }
+        for (address, zone) in [("fd00::6", V6), ("fd00::7", HOSTING6)] {
+            let store = parse_zone(zone, &[]).unwrap().into_iter().collect();
+            servers.zones.insert(address.parse().unwrap(), store);
+        }
+        servers.lame.insert(IpAddr::V4(Ipv4Addr::new(10, 0, 6, 1)));
+        servers
+    }
+
+    fn queries_to(&self, address: [u8; 4]) -> usize {
+        let address = IpAddr::V4(Ipv4Addr::from(address));
+        let queries = self.queries.lock().unwrap();
+        queries
+            .iter()
+            .filter(|(queried, _)| *queried == address)
+            .count()
+    }
+
+    /// the names the server was asked for, in order
+    fn names_asked(&self, address: [u8; 4]) -> Vec<Name> {
+        let address = IpAddr::V4(Ipv4Addr::from(address));
+        let queries = self.queries.lock().unwrap();
+        queries
+            .iter()
+            .filter(|(queried, _)| *queried == address)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+}
+
+impl ResolverTransport for FakeServers {
+    fn query<'a>(
+        &'a self,
+        query: &'a DNSPacket,
+        server: SocketAddr,
+        _timeout: Duration,
+    ) -> BoxFuture<'a, io::Result<DNSPacket>> {
+        self.queries.lock().unwrap().push((
+            server.ip(),
+            Name::from_labels(&query.questions[0].qname).unwrap(),
+        ));
+        if self.lame.contains(&server.ip()) {
+            let response = DNSPacket::response_to(query);
+            return Box::pin(async move { Ok(response) });
+        }
+        let response = match self.zones.get(&server.ip()) {
+            Some(store) => Ok(store
+                .answer(query)
+                .map(|mut response| {
+                    let nodata = response.header.flags.aa && response.answers.is_empty();
+                    if nodata && self.broken.contains(&server.ip()) {
+                        response.header.flags.rcode = DNSFlags::NXDOMAIN;
+                    }
+                    response
+                })
+                .unwrap_or_else(|| {
+                    let mut refused = DNSPacket::response_to(query);
+                    refused.header.flags.rcode = DNSFlags::REFUSED;
+                    refused
+                })),
+            None => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "upstream timed out",
+            )),
+        };
+        Box::pin(async move { response })
+    }
+}
+
+fn resolver(servers: &Arc<FakeServers>) -> Resolver {
+    // the first root server never answers
+    let hints = vec![
+        SocketAddr::from(([10, 0, 0, 99], 53)),
+        SocketAddr::from(([10, 0, 0, 1], 53)),
+    ];
+    Resolver::new(servers.clone(), hints)
+}
+
+async fn resolve(resolver: &Resolver, name: &str) -> DNSPacket {
+    resolver
+        .resolve(&question(99, name, ARecord::TYPE), Duration::from_secs(1))
+        .await
+        .unwrap()
+}
+
+fn names(texts: &[&str]) -> Vec<Name> {
+    texts.iter().map(|text| text.parse().unwrap()).collect()
+}
+
+fn address(address: [u8; 4]) -> DNSRecord {
+    DNSRecord::A(ARecord(Ipv4Addr::from(address)))
+}
+
+#[tokio::test]
+async fn walks_down_from_the_root() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "www.example.com").await;
+
+    assert_eq!(response.header.id, 99);
+    assert!(response.header.flags.qr);
+    assert!(!response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].data, address([192, 0, 2, 80]));
+    assert_eq!(servers.queries_to([10, 0, 0, 1]), 1);
+    assert_eq!(servers.queries_to([10, 0, 1, 1]), 1);
+    assert_eq!(servers.queries_to([10, 0, 3, 1]), 1);
+}
+
+#[tokio::test]
+async fn remembers_delegations() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    resolve(&resolver, "www.example.com").await;
+    assert_eq!(resolver.delegations(), 2);
+
+    // straight to the servers of example.com
+    let response = resolve(&resolver, "ns1.example.com").await;
+    assert_eq!(response.answers[0].data, address([10, 0, 3, 1]));
+    assert_eq!(servers.queries_to([10, 0, 0, 99]), 1);
+    assert_eq!(servers.queries_to([10, 0, 0, 1]), 1);
+    assert_eq!(servers.queries_to([10, 0, 1, 1]), 1);
+    assert_eq!(servers.queries_to([10, 0, 3, 1]), 2);
+}
+
+#[tokio::test]
+async fn resolves_name_servers_without_glue() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "www.outside.com").await;
+
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].data, address([198, 51, 100, 1]));
+    // ns.hosting.net was looked up through net
+    assert_eq!(servers.queries_to([10, 0, 2, 1]), 1);
+    assert_eq!(servers.queries_to([10, 0, 4, 1]), 2);
+}
+
+#[tokio::test]
+async fn reaches_name_servers_with_only_ipv6_addresses() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+
+    let response = resolve(&resolver, "www.v6.com").await;
+    assert_eq!(response.answers[0].data, address([192, 0, 2, 6]));
+
+    let response = resolve(&resolver, "www.glueless6.com").await;
+    assert_eq!(response.answers[0].data, address([192, 0, 2, 7]));
+}
+
+#[tokio::test]
+async fn follows_cnames_into_other_zones() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "alias.example.com").await;
+
+    let types: Vec<u16> = response.answers.iter().map(|record| record.rtype).collect();
+    assert_eq!(types, vec![CNAMERecord::TYPE, ARecord::TYPE]);
+    assert_eq!(response.answers[1].data, address([198, 51, 100, 1]));
+    assert_eq!(
+        response.questions,
+        question(99, "alias.example.com", ARecord::TYPE).questions
+    );
+}
+
+#[tokio::test]
+async fn servers_only_answer_for_their_own_zone() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "www.evil.com").await;
+
+    let types: Vec<u16> = response.answers.iter().map(|record| record.rtype).collect();
+    assert_eq!(types, vec![CNAMERecord::TYPE, ARecord::TYPE]);
+    // the address comes from the servers of example.com
+    assert_eq!(response.answers[1].data, address([192, 0, 2, 80]));
+    assert_eq!(servers.queries_to([10, 0, 3, 1]), 1);
+}
+
+#[tokio::test]
+async fn passes_over_lame_servers() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "www.lame.com").await;
+
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].data, address([192, 0, 2, 9]));
+    assert_eq!(servers.queries_to([10, 0, 6, 1]), 1);
+    assert_eq!(servers.queries_to([10, 0, 6, 2]), 1);
+}
+
+#[tokio::test]
+async fn missing_names_are_nxdomain() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "missing.example.com").await;
+
+    assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities[0].rtype, SOARecord::TYPE);
+}
+
+#[tokio::test]
+async fn fails_when_no_server_answers() {
+    let servers = Arc::new(FakeServers::default());
+    let resolver = resolver(&servers);
+    let error = resolver
+        .resolve(
+            &question(99, "www.example.com", ARecord::TYPE),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn recursive_mode_resolves_and_caches() {
+    let config = Config::default()
+        .merge("recursive = true", ConfigFormat::Toml)
+        .unwrap();
+    assert!(config.recursive);
+
+    let servers = Arc::new(FakeServers::internet());
+    let handler = Handler::with_resolver(config, resolver(&servers));
+    for _ in 0..2 {
+        let response = handler
+            .handle(&query(7, "www.example.com"), Transport::Udp)
+            .await
+            .unwrap();
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(response.header.id, 7);
+        assert!(response.header.flags.ra);
+        assert_eq!(response.answers[0].data, address([192, 0, 2, 80]));
+    }
+    assert_eq!(servers.queries_to([10, 0, 3, 1]), 1);
+
+    // without a server to ask the client gets SERVFAIL
+    let config = Config {
+        recursive: true,
+        ..Config::default()
+    };
+    let handler = Handler::with_resolver(config, resolver(&Arc::new(FakeServers::default())));
+    let response = handler
+        .handle(&query(8, "www.example.com"), Transport::Udp)
+        .await
+        .unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::SERVFAIL);
+}
+
+#[tokio::test]
+async fn shows_servers_only_the_next_label() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    resolve(&resolver, "www.example.com").await;
+
+    assert_eq!(servers.names_asked([10, 0, 0, 1]), names(&["com"]));
+    assert_eq!(servers.names_asked([10, 0, 1, 1]), names(&["example.com"]));
+    assert_eq!(
+        servers.names_asked([10, 0, 3, 1]),
+        names(&["www.example.com"])
+    );
+}
+
+#[tokio::test]
+async fn walks_through_empty_non_terminals() {
+    let servers = Arc::new(FakeServers::internet());
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "a.b.deep.example.com").await;
+
+    assert_eq!(response.answers[0].data, address([192, 0, 2, 5]));
+    assert_eq!(
+        servers.names_asked([10, 0, 3, 1]),
+        names(&[
+            "deep.example.com",
+            "b.deep.example.com",
+            "a.b.deep.example.com"
+        ])
+    );
+}
+
+#[tokio::test]
+async fn asks_for_the_whole_name_when_servers_deny_empty_non_terminals() {
+    let mut servers = FakeServers::internet();
+    servers
+        .broken
+        .insert(IpAddr::V4(Ipv4Addr::new(10, 0, 3, 1)));
+    let servers = Arc::new(servers);
+    let resolver = resolver(&servers);
+    let response = resolve(&resolver, "a.b.deep.example.com").await;
+
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(response.answers[0].data, address([192, 0, 2, 5]));
+    assert_eq!(
+        servers.names_asked([10, 0, 3, 1]),
+        names(&["deep.example.com", "a.b.deep.example.com"])
+    );
+
+    // names that really are missing stay missing
+    let response = resolve(&resolver, "x.missing.example.com").await;
+    assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+}
+
+#[tokio::test]
+async fn minimisation_can_be_turned_off() {
+    let config = Config::default()
+        .merge(
+            "recursive = true\nqname_minimisation = false",
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+    assert!(!config.qname_minimisation);
+
+    let servers = Arc::new(FakeServers::internet());
+    let handler = Handler::with_resolver(config, resolver(&servers));
+    handler
+        .handle(&query(1, "www.example.com"), Transport::Udp)
+        .await
+        .unwrap();
+    assert_eq!(
+        servers.names_asked([10, 0, 0, 1]),
+        names(&["www.example.com"])
+    );
+    assert_eq!(
+        servers.names_asked([10, 0, 1, 1]),
+        names(&["www.example.com"])
+    );
+}
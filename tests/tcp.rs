@@ -0,0 +1,132 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use joyboy_dns::config::Config;
+use joyboy_dns::server::handler::Handler;
+use joyboy_dns::server::tcp::{
+    handle_connection, handle_tcp, read_message, write_message, TCP_IDLE_TIMEOUT,
+};
+use joyboy_dns::DNSPacket;
+
+use common::{fake_upstream, query};
+
+async fn serve_one_connection(idle_timeout: Duration) -> TcpStream {
+    let handler = Arc::new(Handler::new(Config {
+        upstreams: vec![fake_upstream(Duration::from_millis(300)).await],
+        ..Config::default()
+    }));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let _ = handle_connection(stream, handler, idle_timeout).await;
+    });
+    TcpStream::connect(address).await.unwrap()
+}
+
+#[tokio::test]
+async fn answers_pipelined_queries() {
+    let mut stream = serve_one_connection(TCP_IDLE_TIMEOUT).await;
+
+    // all queries are sent before reading any answer
+    let mut pipelined = vec![];
+    write_message(&mut pipelined, &query(1, "slow.example"))
+        .await
+        .unwrap();
+    write_message(&mut pipelined, &query(2, "example.com"))
+        .await
+        .unwrap();
+    write_message(&mut pipelined, &query(3, "google.com"))
+        .await
+        .unwrap();
+    stream.write_all(&pipelined).await.unwrap();
+
+    let mut ids = vec![];
+    for _ in 0..3 {
+        let response = read_message(&mut stream).await.unwrap().unwrap();
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(response.questions.len(), 1);
+        ids.push(response.header.id);
+    }
+    // the slow upstream answer does not hold up the others
+    assert_eq!(ids.last(), Some(&1));
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn closes_when_the_client_does() {
+    let mut stream = serve_one_connection(TCP_IDLE_TIMEOUT).await;
+    write_message(&mut stream, &query(9, "google.com"))
+        .await
+        .unwrap();
+    stream.shutdown().await.unwrap();
+
+    assert!(read_message(&mut stream).await.unwrap().is_some());
+    assert!(read_message(&mut stream).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn answers_malformed_queries_with_formerr() {
+    let mut stream = serve_one_connection(TCP_IDLE_TIMEOUT).await;
+    let mut bad = query(5, "google.com");
+    bad.truncate(20);
+    write_message(&mut stream, &bad).await.unwrap();
+
+    let response = read_message(&mut stream).await.unwrap().unwrap();
+    let response = DNSPacket::parse(&response).unwrap();
+    assert_eq!(response.header.id, 5);
+    assert_eq!(response.header.flags.rcode, 1);
+}
+
+#[tokio::test]
+async fn closes_idle_connections() {
+    let idle_timeout = Duration::from_millis(200);
+    let started = Instant::now();
+    let mut stream = serve_one_connection(idle_timeout).await;
+
+    let mut buffer = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buffer))
+        .await
+        .expect("the idle connection was not closed")
+        .unwrap();
+    assert_eq!(read, 0);
+    assert!(started.elapsed() >= idle_timeout);
+}
+
+#[tokio::test]
+async fn closes_connections_beyond_the_limit() {
+    let handler = Arc::new(Handler::new(Config {
+        upstreams: vec![fake_upstream(Duration::ZERO).await],
+        max_tcp_connections: 1,
+        ..Config::default()
+    }));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(handle_tcp(listener, handler, TCP_IDLE_TIMEOUT));
+
+    let mut first = TcpStream::connect(address).await.unwrap();
+    write_message(&mut first, &query(1, "example.com"))
+        .await
+        .unwrap();
+    assert!(read_message(&mut first).await.unwrap().is_some());
+
+    let mut second = TcpStream::connect(address).await.unwrap();
+    let mut buffer = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(2), second.read(&mut buffer))
+        .await
+        .expect("the connection beyond the limit was not closed")
+        .unwrap();
+    assert_eq!(read, 0);
+
+    // the first one is still served
+    write_message(&mut first, &query(2, "example.com"))
+        .await
+        .unwrap();
+    assert!(read_message(&mut first).await.unwrap().is_some());
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use joyboy_dns::config::Config;
+use joyboy_dns::server::handler::Handler;
+use joyboy_dns::server::udp::handle_udp;
+use joyboy_dns::DNSPacket;
+
+use common::{fake_upstream, query};
+
+#[tokio::test]
+async fn a_slow_upstream_does_not_stall_other_clients() {
+    let handler = Arc::new(Handler::new(Config {
+        upstreams: vec![fake_upstream(Duration::from_secs(2)).await],
+        ..Config::default()
+    }));
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = server.local_addr().unwrap();
+    tokio::spawn(handle_udp(server, handler));
+
+    let slow_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    slow_client
+        .send_to(&query(1, "slow.example"), address)
+        .await
+        .unwrap();
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buffer = [0u8; 512];
+    for id in 2..6 {
+        client
+            .send_to(&query(id, "example.com"), address)
+            .await
+            .unwrap();
+        let (amt, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buffer))
+            .await
+            .expect("answered while the slow query is still waiting")
+            .unwrap();
+        assert_eq!(DNSPacket::parse(&buffer[..amt]).unwrap().header.id, id);
+    }
+
+    let (amt, _) = slow_client.recv_from(&mut buffer).await.unwrap();
+    assert_eq!(DNSPacket::parse(&buffer[..amt]).unwrap().header.id, 1);
+}
+
+#[tokio::test]
+async fn drops_queries_beyond_the_limit() {
+    let handler = Arc::new(Handler::new(Config {
+        upstreams: vec![fake_upstream(Duration::from_millis(300)).await],
+        max_queries: 1,
+        ..Config::default()
+    }));
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = server.local_addr().unwrap();
+    tokio::spawn(handle_udp(server, handler));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client
+        .send_to(&query(1, "slow.example"), address)
+        .await
+        .unwrap();
+    client
+        .send_to(&query(2, "example.com"), address)
+        .await
+        .unwrap();
+
+    // only the first query is answered
+    let mut buffer = [0u8; 512];
+    let (amt, _) = client.recv_from(&mut buffer).await.unwrap();
+    assert_eq!(DNSPacket::parse(&buffer[..amt]).unwrap().header.id, 1);
+    let dropped =
+        tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buffer)).await;
+    assert!(dropped.is_err());
+
+    // once it is, there is room again
+    client
+        .send_to(&query(3, "example.com"), address)
+        .await
+        .unwrap();
+    let (amt, _) = client.recv_from(&mut buffer).await.unwrap();
+    assert_eq!(DNSPacket::parse(&buffer[..amt]).unwrap().header.id, 3);
+}
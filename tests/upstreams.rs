@@ -0,0 +1,399 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use joyboy_dns::config::{Config, ConfigError, ConfigFormat, ForwardRule};
+use joyboy_dns::server::forward::forward;
+use joyboy_dns::server::handler::{Handler, Transport};
+use joyboy_dns::server::upstreams::{
+    check_upstreams, HealthLimits, UpstreamPool, UpstreamStrategy,
+};
+use joyboy_dns::{DNSFlags, DNSPacket};
+
+use common::{counting_upstream, failing_upstream, fake_upstream, labels, query, silent_upstream};
+
+fn address(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn packet(id: u16) -> DNSPacket {
+    DNSPacket::parse(&query(id, "example.com")).unwrap()
+}
+
+#[test]
+fn strategies_order_the_upstreams() {
+    let upstreams = [address(1), address(2), address(3)];
+    let pool = UpstreamPool::new();
+    let now = Instant::now();
+
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Failover, now),
+        upstreams
+    );
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Parallel, now),
+        upstreams
+    );
+
+    let firsts: Vec<SocketAddr> = (0..3)
+        .map(|_| pool.order(&upstreams, UpstreamStrategy::RoundRobin, now)[0])
+        .collect();
+    assert_eq!(firsts, upstreams);
+
+    pool.succeeded(address(1), Duration::from_millis(30));
+    pool.succeeded(address(2), Duration::from_millis(10));
+    pool.succeeded(address(3), Duration::from_millis(20));
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Fastest, now),
+        [address(2), address(3), address(1)]
+    );
+}
+
+#[test]
+fn round_trip_times_are_smoothed() {
+    let pool = UpstreamPool::new();
+    pool.succeeded(address(1), Duration::from_millis(80));
+    assert_eq!(pool.stats(address(1)).rtt, Some(Duration::from_millis(80)));
+    pool.succeeded(address(1), Duration::from_millis(0));
+    assert_eq!(pool.stats(address(1)).rtt, Some(Duration::from_millis(70)));
+    assert_eq!(pool.stats(address(1)).queries, 2);
+}
+
+#[test]
+fn failing_upstreams_are_left_out_for_a_while() {
+    let upstreams = [address(1), address(2)];
+    let limits = HealthLimits {
+        max_failures: 2,
+        eject_time: Duration::from_secs(30),
+        ..HealthLimits::default()
+    };
+    let pool = UpstreamPool::new();
+    let now = Instant::now();
+
+    pool.failed(address(1), &limits, now);
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Failover, now),
+        upstreams
+    );
+    pool.failed(address(1), &limits, now);
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Failover, now),
+        [address(2)]
+    );
+    assert_eq!(
+        pool.stats(address(1)).ejected_until,
+        Some(now + Duration::from_secs(30))
+    );
+
+    // once the time is up it is tried again, and one more failure
+    // leaves it out again
+    let later = now + Duration::from_secs(30);
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Failover, later),
+        upstreams
+    );
+    pool.failed(address(1), &limits, later);
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::Failover, later),
+        [address(2)]
+    );
+
+    // an answer takes it back into use right away
+    pool.succeeded(address(1), Duration::from_millis(5));
+    assert_eq!(pool.stats(address(1)).ejected_until, None);
+    assert_eq!(pool.stats(address(1)).failures_in_a_row, 0);
+    assert_eq!(pool.stats(address(1)).failures, 3);
+}
+
+#[test]
+fn when_all_upstreams_are_left_out_all_are_asked() {
+    let upstreams = [address(1), address(2)];
+    let limits = HealthLimits {
+        max_failures: 1,
+        ..HealthLimits::default()
+    };
+    let pool = UpstreamPool::new();
+    let now = Instant::now();
+    pool.failed(address(1), &limits, now);
+    pool.failed(address(2), &limits, now);
+    assert_eq!(
+        pool.order(&upstreams, UpstreamStrategy::RoundRobin, now),
+        upstreams
+    );
+}
+
+#[tokio::test]
+async fn failover_skips_upstreams_that_are_down() {
+    let dead = silent_upstream().await;
+    let (alive, count) = counting_upstream().await;
+    let config = Config {
+        upstreams: vec![dead, alive],
+        upstream_timeout: Duration::from_millis(100),
+        upstream_health: HealthLimits {
+            max_failures: 1,
+            ..HealthLimits::default()
+        },
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+
+    forward(&packet(1), &config, &pool).await.unwrap();
+    assert!(pool.stats(dead).ejected_until.is_some());
+
+    // the dead upstream is not waited for anymore
+    let started = Instant::now();
+    forward(&packet(2), &config, &pool).await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(100));
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.stats(dead).queries, 1);
+}
+
+#[tokio::test]
+async fn round_robin_spreads_the_queries() {
+    let (first, first_count) = counting_upstream().await;
+    let (second, second_count) = counting_upstream().await;
+    let config = Config {
+        upstreams: vec![first, second],
+        upstream_strategy: UpstreamStrategy::RoundRobin,
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+    for id in 0..4 {
+        forward(&packet(id), &config, &pool).await.unwrap();
+    }
+    assert_eq!(first_count.load(Ordering::SeqCst), 2);
+    assert_eq!(second_count.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn parallel_takes_the_first_answer() {
+    let config = Config {
+        upstreams: vec![silent_upstream().await, fake_upstream(Duration::ZERO).await],
+        upstream_strategy: UpstreamStrategy::Parallel,
+        upstream_timeout: Duration::from_secs(2),
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+    let started = Instant::now();
+    let response = forward(&packet(5), &config, &pool).await.unwrap();
+    assert_eq!(response.header.id, 5);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn failover_moves_on_from_servfail_and_refused() {
+    let servfail = failing_upstream(DNSFlags::SERVFAIL).await;
+    let refused = failing_upstream(DNSFlags::REFUSED).await;
+    let (alive, count) = counting_upstream().await;
+    let config = Config {
+        upstreams: vec![servfail, refused, alive],
+        upstream_health: HealthLimits {
+            max_failures: 2,
+            ..HealthLimits::default()
+        },
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+
+    for id in 1..=2 {
+        let response = forward(&packet(id), &config, &pool).await.unwrap();
+        assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+        assert_eq!(response.answers.len(), 1);
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    for upstream in [servfail, refused] {
+        assert_eq!(pool.stats(upstream).failures, 2);
+        assert!(pool.stats(upstream).ejected_until.is_some());
+    }
+}
+
+#[tokio::test]
+async fn the_error_is_returned_when_every_upstream_gives_one() {
+    let servfail = failing_upstream(DNSFlags::SERVFAIL).await;
+    let config = Config {
+        upstreams: vec![servfail, failing_upstream(DNSFlags::SERVFAIL).await],
+        upstream_retries: 0,
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+    let response = forward(&packet(3), &config, &pool).await.unwrap();
+    assert_eq!(response.header.id, 3);
+    assert_eq!(response.header.flags.rcode, DNSFlags::SERVFAIL);
+    assert_eq!(pool.stats(servfail).failures, 1);
+}
+
+#[tokio::test]
+async fn parallel_servfail_loses_to_a_slower_answer() {
+    let servfail = failing_upstream(DNSFlags::SERVFAIL).await;
+    let config = Config {
+        upstreams: vec![servfail, fake_upstream(Duration::from_millis(200)).await],
+        upstream_strategy: UpstreamStrategy::Parallel,
+        upstream_timeout: Duration::from_secs(2),
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+    let query = DNSPacket::parse(&query(6, "slow.example")).unwrap();
+    let response = forward(&query, &config, &pool).await.unwrap();
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(pool.stats(servfail).failures, 1);
+}
+
+#[tokio::test]
+async fn health_checks_eject_and_readmit() {
+    let dead = silent_upstream().await;
+    let (alive, count) = counting_upstream().await;
+    let config = Config {
+        upstreams: vec![dead, alive],
+        upstream_timeout: Duration::from_millis(100),
+        upstream_health: HealthLimits {
+            max_failures: 1,
+            ..HealthLimits::default()
+        },
+        ..Config::default()
+    };
+    let pool = UpstreamPool::new();
+    pool.failed(alive, &config.upstream_health, Instant::now());
+    assert!(pool.stats(alive).ejected_until.is_some());
+
+    check_upstreams(&config, &pool).await;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.stats(alive).ejected_until, None);
+    assert!(pool.stats(alive).rtt.is_some());
+    assert!(pool.stats(dead).ejected_until.is_some());
+}
+
+#[test]
+fn reads_the_pool_settings() {
+    let config = Config::default()
+        .merge(
+            r#"
+            upstream_strategy = "fastest"
+
+            [upstream_health]
+            max_failures = 5
+            eject_time = 60
+            check_interval = 0
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+    assert_eq!(config.upstream_strategy, UpstreamStrategy::Fastest);
+    assert_eq!(
+        config.upstream_health,
+        HealthLimits {
+            max_failures: 5,
+            eject_time: Duration::from_secs(60),
+            check_interval: Duration::ZERO,
+        }
+    );
+
+    let error = Config::default()
+        .merge("upstream_strategy = \"random\"", ConfigFormat::Toml)
+        .unwrap_err();
+    assert!(matches!(error, ConfigError::Invalid { field, .. } if field == "upstream_strategy"));
+}
+
+fn rules_config() -> Config {
+    Config::default()
+        .merge(
+            r#"
+            upstreams = ["192.0.2.1"]
+
+            [[forward]]
+            domain = "corp.internal"
+            upstreams = ["10.0.0.10", "10.0.0.11:5353"]
+
+            [[forward]]
+            domain = "eu.corp.internal."
+            upstreams = ["10.1.0.10"]
+            strategy = "parallel"
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap()
+}
+
+#[test]
+fn the_longest_matching_domain_wins() {
+    let config = rules_config();
+    let route = |name: &str| {
+        let (upstreams, strategy) = config.route(&labels(name));
+        (upstreams.to_vec(), strategy)
+    };
+
+    assert_eq!(
+        route("dc1.corp.internal"),
+        (
+            vec![
+                "10.0.0.10:53".parse().unwrap(),
+                "10.0.0.11:5353".parse().unwrap()
+            ],
+            UpstreamStrategy::Failover
+        )
+    );
+    assert_eq!(route("CORP.Internal").0.len(), 2);
+    assert_eq!(
+        route("dc1.EU.corp.internal"),
+        (
+            vec!["10.1.0.10:53".parse().unwrap()],
+            UpstreamStrategy::Parallel
+        )
+    );
+    // only whole labels match
+    assert_eq!(
+        route("notcorp.internal").0,
+        vec!["192.0.2.1:53".parse::<SocketAddr>().unwrap()]
+    );
+    assert_eq!(config.all_upstreams().len(), 4);
+}
+
+#[test]
+fn reports_bad_rules() {
+    let bad = [
+        "[[forward]]\ndomain = \"corp\"\nupstreams = []",
+        "[[forward]]\ndomain = \"corp\"\nupstreams = [\"nope\"]",
+        "[[forward]]\ndomain = \"corp\"\nupstreams = [\"10.0.0.1\"]\nstrategy = \"random\"",
+        "[[forward]]\ndomain = \"corp\"\nupstreams = [\"10.0.0.1\"]\n\
+         [[forward]]\ndomain = \"CORP.\"\nupstreams = [\"10.0.0.2\"]",
+    ];
+    for text in bad {
+        let error = Config::default()
+            .merge(text, ConfigFormat::Toml)
+            .unwrap_err();
+        assert!(
+            matches!(&error, ConfigError::Invalid { field, .. } if field.starts_with("forward[")),
+            "{}: {}",
+            text,
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn forwards_domains_to_their_own_upstreams() {
+    let (public, public_count) = counting_upstream().await;
+    let (corporate, corporate_count) = counting_upstream().await;
+    let config = Config {
+        upstreams: vec![public],
+        forward_rules: vec![ForwardRule {
+            domain: "corp.internal".parse().unwrap(),
+            upstreams: vec![corporate],
+            strategy: None,
+        }],
+        ..Config::default()
+    };
+    let handler = Handler::new(config);
+
+    for name in ["dc1.corp.internal", "example.com", "corp.internal"] {
+        let response = handler
+            .handle(&query(1, name), Transport::Udp)
+            .await
+            .unwrap();
+        let response = DNSPacket::parse(&response).unwrap();
+        assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    }
+    assert_eq!(corporate_count.load(Ordering::SeqCst), 2);
+    assert_eq!(public_count.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use joyboy_dns::records::RecordStore;
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, mx_record::MXRecord, ns_record::NSRecord, soa_record::SOARecord,
+    srv_record::SRVRecord, txt_record::TXTRecord,
+};
+use joyboy_dns::zone::parse_zone;
+use joyboy_dns::{DNSFlags, DNSPacket, DNSQuestion, DNSRecord};
+
+use common::labels;
+
+/// the example zone of RFC 4592 section 2.2.1
+const ZONE: &str = "\
+$ORIGIN example.
+$TTL 3600
+@                   SOA     ns.example.com. hostmaster 1 7200 900 1209600 300
+                    NS      ns.example.com.
+                    NS      ns.example.net.
+*                   TXT     \"this is a wildcard\"
+*                   MX      10 host1.example.
+sub.*               TXT     \"this is not a wildcard\"
+host1               A       192.0.2.1
+_ssh._tcp.host1     SRV     0 0 22 host1.example.
+_ssh._tcp.host2     SRV     0 0 22 host2.example.
+subdel              NS      ns.example.com.
+subdel              NS      ns.example.net.
+";
+
+fn ask(name: &str, qtype: u16) -> DNSPacket {
+    let store: RecordStore = parse_zone(ZONE, &[]).unwrap().into_iter().collect();
+    let mut query = DNSPacket::new();
+    query.push_questions(DNSQuestion::from_simple_string(name, qtype, 1).unwrap());
+    query.update_counts();
+    store.answer(&query).unwrap()
+}
+
+fn assert_nodata(response: &DNSPacket) {
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities[0].rtype, SOARecord::TYPE);
+}
+
+fn assert_nxdomain(response: &DNSPacket) {
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NXDOMAIN);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities[0].rtype, SOARecord::TYPE);
+}
+
+#[test]
+fn synthesizes_records_for_missing_names() {
+    let response = ask("host3.example", MXRecord::TYPE);
+    assert!(response.header.flags.aa);
+    assert_eq!(response.header.flags.rcode, DNSFlags::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+    // the owner is the name asked for, not the wildcard
+    assert_eq!(response.answers[0].name, labels("host3.example"));
+    assert!(response.authorities.is_empty());
+
+    // any depth below the closest encloser matches
+    let response = ask("foo.bar.EXAMPLE", TXTRecord::TYPE);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].name, labels("foo.bar.EXAMPLE"));
+    assert_eq!(
+        response.answers[0].data,
+        DNSRecord::TXT(TXTRecord(vec!["this is a wildcard".into()]))
+    );
+}
+
+#[test]
+fn a_wildcard_without_the_type_is_nodata() {
+    assert_nodata(&ask("host3.example", ARecord::TYPE));
+}
+
+#[test]
+fn existing_names_are_not_matched() {
+    assert_nodata(&ask("host1.example", MXRecord::TYPE));
+    assert_nodata(&ask("sub.*.example", MXRecord::TYPE));
+    // _tcp.host2.example exists without records of its own
+    assert_nodata(&ask("_tcp.host2.example", TXTRecord::TYPE));
+}
+
+#[test]
+fn only_the_closest_encloser_is_tried() {
+    // _tcp.host1.example exists and has no wildcard below it
+    assert_nxdomain(&ask("_telnet._tcp.host1.example", SRVRecord::TYPE));
+    // *.example itself is the closest encloser here
+    assert_nxdomain(&ask("ghost.*.example", MXRecord::TYPE));
+}
+
+#[test]
+fn the_wildcard_answers_for_itself() {
+    let response = ask("*.example", TXTRecord::TYPE);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].name, labels("*.example"));
+}
+
+#[test]
+fn delegations_win_over_wildcards() {
+    let response = ask("host.subdel.example", ARecord::TYPE);
+    assert!(!response.header.flags.aa);
+    assert!(response.answers.is_empty());
+    assert_eq!(response.authorities.len(), 2);
+    assert!(response
+        .authorities
+        .iter()
+        .all(|record| record.rtype == NSRecord::TYPE));
+}
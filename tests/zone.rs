@@ -0,0 +1,295 @@
+mod common;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+use joyboy_dns::config::{Config, ConfigError, ConfigFormat};
+use joyboy_dns::structs::record_types::{
+    a_record::ARecord, aaaa_record::AAAARecord, cname_record::CNAMERecord, mx_record::MXRecord,
+    ns_record::NSRecord, soa_record::SOARecord, srv_record::SRVRecord, txt_record::TXTRecord,
+};
+use joyboy_dns::zone::{load_zone, parse_name, parse_rdata, parse_ttl, parse_zone};
+use joyboy_dns::{DNSPacketBuffer, DNSRecord, DNSResourceRecord};
+
+use common::labels;
+
+fn data(file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(file)
+}
+
+fn find<'a>(records: &'a [DNSResourceRecord], name: &str, rtype: u16) -> &'a DNSResourceRecord {
+    records
+        .iter()
+        .find(|record| record.name == labels(name) && record.rtype == rtype)
+        .unwrap_or_else(|| panic!("no {} record for {}", rtype, name))
+}
+
+#[test]
+fn names() {
+    let origin = labels("example.com");
+    assert_eq!(parse_name("@", &origin), Ok(origin.clone()));
+    assert_eq!(parse_name("www", &origin), Ok(labels("www.example.com")));
+    assert_eq!(
+        parse_name("www.example.org.", &origin),
+        Ok(labels("www.example.org"))
+    );
+    assert_eq!(parse_name(".", &origin), Ok(vec![]));
+
+    let escaped = parse_name("a\\.b.c\\046d.", &[]).unwrap();
+    let values: Vec<&[u8]> = escaped.iter().map(|label| &label.value[..]).collect();
+    assert_eq!(values, vec![b"a.b", b"c.d"]);
+
+    assert!(parse_name("a..b", &origin).is_err());
+    assert!(parse_name(&"a".repeat(64), &origin).is_err());
+    assert!(parse_name(&"abcdefgh.".repeat(30), &[]).is_err());
+}
+
+#[test]
+fn ttls() {
+    assert_eq!(parse_ttl("3600"), Some(3600));
+    assert_eq!(parse_ttl("1h30m"), Some(5400));
+    assert_eq!(parse_ttl("1W2D"), Some(9 * 86400));
+    assert_eq!(parse_ttl("h"), None);
+    assert_eq!(parse_ttl("10x"), None);
+    assert_eq!(parse_ttl("10h5"), None);
+}
+
+#[test]
+fn loads_a_zone_with_includes() {
+    let records = load_zone(&data("example.com.zone"), &labels("example.com")).unwrap();
+    assert_eq!(records.len(), 8);
+
+    let soa = find(&records, "example.com", SOARecord::TYPE);
+    assert_eq!(soa.ttl, 3600);
+    assert_eq!(
+        soa.data,
+        DNSRecord::SOA(SOARecord {
+            primary_ns: labels("ns1.example.com"),
+            responsible_email: labels("hostmaster.example.com"),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 900,
+            expire: 14 * 86400,
+            minimum_ttl: 300,
+        })
+    );
+
+    // lines starting with blanks belong to the previous owner
+    let ns: Vec<&DNSRecord> = records
+        .iter()
+        .filter(|record| record.rtype == NSRecord::TYPE)
+        .map(|record| &record.data)
+        .collect();
+    assert_eq!(
+        ns,
+        vec![
+            &DNSRecord::NS(NSRecord(labels("ns1.example.com"))),
+            &DNSRecord::NS(NSRecord(labels("ns2.example.net"))),
+        ]
+    );
+    assert_eq!(
+        find(&records, "example.com", MXRecord::TYPE).data,
+        DNSRecord::MX(MXRecord {
+            preference: 10,
+            mail_exchange: labels("mail.example.com"),
+        })
+    );
+    assert_eq!(find(&records, "mail.example.com", ARecord::TYPE).ttl, 600);
+
+    // the include has its own origin
+    assert_eq!(
+        find(&records, "db.internal.example.com", ARecord::TYPE).data,
+        DNSRecord::A(ARecord(Ipv4Addr::new(10, 0, 0, 5)))
+    );
+    assert_eq!(
+        find(&records, "db.internal.example.com", AAAARecord::TYPE).data,
+        DNSRecord::AAAA(AAAARecord("fd00::5".parse::<Ipv6Addr>().unwrap()))
+    );
+}
+
+#[test]
+fn every_record_type() {
+    let zone = r#"
+$TTL 300
+a       A       192.0.2.1
+a       AAAA    2001:db8::1
+alias   CNAME   a
+srv     SRV     10 60 5060 a.example.com.
+txt     TXT     "hello world" "quote \" and \059" plain
+ptr     PTR     a
+raw     TYPE999 \# 3 abcdef
+raw2    A       \# 4 C0000202
+"#;
+    let records = parse_zone(zone, &labels("example.com")).unwrap();
+    assert_eq!(records.len(), 8);
+
+    assert_eq!(
+        find(&records, "alias.example.com", CNAMERecord::TYPE).data,
+        DNSRecord::CNAME(CNAMERecord(labels("a.example.com")))
+    );
+    assert_eq!(
+        find(&records, "srv.example.com", SRVRecord::TYPE).data,
+        DNSRecord::SRV(SRVRecord {
+            priority: 10,
+            weight: 60,
+            port: 5060,
+            target: labels("a.example.com"),
+        })
+    );
+    assert_eq!(
+        find(&records, "txt.example.com", TXTRecord::TYPE).data,
+        DNSRecord::TXT(TXTRecord(vec![
+            "hello world".into(),
+            "quote \" and ;".into(),
+            "plain".into(),
+        ]))
+    );
+    assert_eq!(
+        find(&records, "raw.example.com", 999).data,
+        DNSRecord::Unknown {
+            record_type: 999,
+            data_length: 3,
+            raw_data: vec![0xAB, 0xCD, 0xEF],
+        }
+    );
+    assert_eq!(
+        find(&records, "raw2.example.com", ARecord::TYPE).data,
+        DNSRecord::A(ARecord(Ipv4Addr::new(192, 0, 2, 2)))
+    );
+}
+
+#[test]
+fn escaped_bytes_stay_single_bytes() {
+    let zone = "t 60 TXT \"\\200abc\" caf\u{e9}\n";
+    let records = parse_zone(zone, &labels("lan")).unwrap();
+    let txt = find(&records, "t.lan", TXTRecord::TYPE);
+    assert_eq!(
+        txt.data,
+        DNSRecord::TXT(TXTRecord(vec![
+            vec![200, b'a', b'b', b'c'],
+            "caf\u{e9}".into()
+        ]))
+    );
+
+    let mut buffer = DNSPacketBuffer::empty();
+    txt.data.write_to_bytes(&mut buffer).unwrap();
+    assert_eq!(
+        buffer.response_bytes(),
+        [4, 0xC8, b'a', b'b', b'c', 5, b'c', b'a', b'f', 0xC3, 0xA9]
+    );
+}
+
+#[test]
+fn ttl_and_class_in_either_order() {
+    let zone = "a 60 IN A 192.0.2.1\nb IN 70 A 192.0.2.2\nc A 192.0.2.3\n";
+    let records = parse_zone(zone, &labels("lan")).unwrap();
+    let ttls: Vec<u32> = records.iter().map(|record| record.ttl).collect();
+    // without $TTL the last explicit TTL is used
+    assert_eq!(ttls, vec![60, 70, 70]);
+}
+
+#[test]
+fn owners_are_checked_against_the_apex() {
+    let zone = "$TTL 60\n\
+                @ SOA ns hostmaster 1 2 3 4 5\n\
+                \n\
+                $ORIGIN sub.example.com.\n\
+                mail.example.com. A 192.0.2.5\n\
+                www A 192.0.2.6\n";
+    let records = parse_zone(zone, &labels("example.com")).unwrap();
+    find(&records, "mail.example.com", ARecord::TYPE);
+    find(&records, "www.sub.example.com", ARecord::TYPE);
+
+    // a new $ORIGIN does not move the zone
+    let zone = "$TTL 60\n$ORIGIN example.org.\nwww A 192.0.2.1\n";
+    let error = parse_zone(zone, &labels("example.com")).unwrap_err();
+    assert_eq!(error.line, 3);
+}
+
+#[test]
+fn errors_have_line_numbers() {
+    let origin = labels("example.com");
+    let cases = [
+        ("$TTL 60\n\nwww A 300.1.1.1\n", 3),
+        ("$TTL 60\nwww A 192.0.2.1\n  MX ( 10\n  mail..x )\n", 3),
+        ("$TTL 60\nwww BOGUS 1\n", 2),
+        ("www A 192.0.2.1\n", 1),
+        ("$TTL 60\n  A 192.0.2.1\n", 2),
+        ("$TTL 60\nwww CH A 192.0.2.1\n", 2),
+        ("$TTL 60\nwww.example.org. A 192.0.2.1\n", 2),
+        ("$TTL 60\n$GENERATE 1-2 a A 1.1.1.1\n", 2),
+        ("$TTL 60\nwww TXT \"open\n", 2),
+        ("$TTL 60\nwww MX ( 10 mail\n", 2),
+        ("$TTL 60\n$INCLUDE missing.zone\n", 2),
+    ];
+    for (zone, line) in cases {
+        match parse_zone(zone, &origin) {
+            Err(e) => assert_eq!(e.line, line, "{:?}: {}", zone, e),
+            Ok(records) => panic!("{:?} parsed to {:?}", zone, records),
+        }
+    }
+
+    let error = parse_zone("$TTL 60\nwww A nope\n", &origin).unwrap_err();
+    assert_eq!(error.to_string(), "line 2: \"nope\" is not an IPv4 address");
+}
+
+#[test]
+fn rdata_on_its_own() {
+    assert_eq!(
+        parse_rdata(MXRecord::TYPE, "5 mx.lan.", &[]),
+        Ok(DNSRecord::MX(MXRecord {
+            preference: 5,
+            mail_exchange: labels("mx.lan"),
+        }))
+    );
+    assert!(parse_rdata(MXRecord::TYPE, "mx.lan.", &[]).is_err());
+}
+
+#[test]
+fn zones_need_an_soa_at_the_apex() {
+    let error = load_zone(&data("no-soa.zone"), &labels("example.org")).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "{}:0: no SOA record at example.org.",
+            data("no-soa.zone").display()
+        )
+    );
+
+    // the SOA of example.com is not at the apex of the root zone
+    assert!(load_zone(&data("example.com.zone"), &[]).is_err());
+}
+
+#[test]
+fn zones_in_the_config() {
+    let text = format!(
+        "[[zones]]\norigin = \"example.com\"\nfile = \"{}\"",
+        data("example.com.zone").display()
+    );
+    let config = Config::default().merge(&text, ConfigFormat::Toml).unwrap();
+    assert_eq!(config.records.len(), 8);
+    assert!(config
+        .records
+        .lookup(&labels("db.internal.example.com"), ARecord::TYPE)
+        .is_some());
+
+    let text = "[[zones]]\norigin = \"example.com\"\nfile = \"missing.zone\"";
+    assert!(matches!(
+        Config::default().merge(text, ConfigFormat::Toml),
+        Err(ConfigError::Invalid { field, .. }) if field == "zones[0]"
+    ));
+
+    let text = format!(
+        "[[zones]]\norigin = \"example.org\"\nfile = \"{}\"",
+        data("no-soa.zone").display()
+    );
+    match Config::default().merge(&text, ConfigFormat::Toml) {
+        Err(ConfigError::Invalid { field, msg }) => {
+            assert_eq!(field, "zones[0]");
+            assert!(msg.contains("no-soa.zone"), "{}", msg);
+        }
+        other => panic!("loaded a zone without an SOA: {:?}", other.map(|_| ())),
+    }
+}